@@ -1,6 +1,6 @@
 use crate::cli::Error::SqlError;
-use crate::tokens::{Token, Tokens};
-use crate::{sql, Database};
+use crate::tokens::{TokenKind, Tokens};
+use crate::{sql, Database, Options};
 use const_format::formatcp;
 use std::io::{self, Write};
 use std::path::PathBuf;
@@ -35,14 +35,14 @@ pub fn read_input() -> Result<Command> {
     }
 }
 
-pub fn open_database(path: Option<&PathBuf>) -> Result<Database> {
-    match Database::open(path) {
+pub fn open_database(path: Option<&PathBuf>, options: &Options) -> Result<Database> {
+    match Database::open_with(path, options) {
         Ok(table) => Ok(table),
         Err(error) => Err(SqlError(error)),
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum Command {
     None,
     Meta(MetaCommand),
@@ -51,10 +51,10 @@ pub enum Command {
 
 impl Command {
     pub fn parse(mut tokens: Tokens) -> Result<Self> {
-        match tokens.peek() {
-            None | Some(Token::None) => Ok(Self::None),
-            Some(Token::Meta(_)) => Ok(Self::Meta(MetaCommand::parse(tokens)?)),
-            Some(Token::Other(_)) => match sql::Statement::parse(tokens) {
+        match tokens.peek().map(|token| &token.kind) {
+            None => Ok(Self::None),
+            Some(TokenKind::Meta(_)) => Ok(Self::Meta(MetaCommand::parse(tokens)?)),
+            Some(_) => match sql::Statement::parse(tokens) {
                 Ok(statement) => Ok(Self::Statement(statement)),
                 Err(error) => Err(Error::SqlError(error)),
             },
@@ -81,16 +81,29 @@ pub enum MetaCommand {
 
 impl MetaCommand {
     pub fn parse(mut tokens: Tokens) -> Result<Self> {
+        let command = match tokens.next() {
+            None => return Ok(Self::None),
+            Some(token) => match token.kind {
+                TokenKind::Meta(meta) if meta == ".exit" => Self::Exit,
+                TokenKind::Meta(meta) => {
+                    return Err(Error::MetaSyntaxError(format!(
+                        "invalid meta command '{}'",
+                        meta
+                    )))
+                }
+                _ => {
+                    return Err(Error::MetaSyntaxError(format!(
+                        "expected meta command, but found {}",
+                        token
+                    )))
+                }
+            },
+        };
         match tokens.next() {
-            None | Some(Token::None) => Ok(Self::None),
-            Some(Token::Meta(".exit")) => Ok(Self::Exit),
-            Some(Token::Meta(s)) => Err(Error::MetaSyntaxError(format!(
-                "invalid meta command '{}'",
-                s
-            ))),
-            Some(Token::Other(s)) => Err(Error::MetaSyntaxError(format!(
-                "expected meta command, but found '{}'",
-                s
+            None => Ok(command),
+            Some(token) => Err(Error::MetaSyntaxError(format!(
+                "unexpected argument {}",
+                token
             ))),
         }
     }
@@ -104,6 +117,7 @@ impl MetaCommand {
 }
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("I/O error")]
     IoError(#[from] io::Error),
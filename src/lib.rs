@@ -1,7 +1,7 @@
 pub mod cli;
 mod sql;
 mod tokens;
-pub use sql::Database;
+pub use sql::{Database, JournalMode, Options};
 
 #[cfg(test)]
 mod tests {}
@@ -1,6 +1,7 @@
-use flakedb::cli;
+use flakedb::{cli, JournalMode, Options};
 use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -8,17 +9,38 @@ use structopt::StructOpt;
 struct Args {
     #[structopt(name = "DB_FILE", parse(from_os_str))]
     db_path: Option<PathBuf>,
+    /// How a new database file is kept consistent during transactions: wal or rollback. An
+    /// existing file keeps the mode it was created with
+    #[structopt(long, default_value = "wal")]
+    journal_mode: JournalMode,
+    /// Do not wait for commits to reach the disk, trading safety on power loss for speed
+    #[structopt(long)]
+    no_sync: bool,
+    /// How many milliseconds to keep retrying when another process has the database locked
+    #[structopt(long, default_value = "0")]
+    busy_timeout: u64,
 }
 
 fn main() -> Result<(), cli::Error> {
     let args = Args::from_args();
 
     cli::print_splash()?;
-    // main loop lives in a block to ensure database is Dropped before we call exit()
+    // main loop lives in a block to ensure database is closed before we call exit()
     let exit_code = {
-        let mut db = cli::open_database(args.db_path.as_ref())?;
+        let options = Options {
+            journal_mode: args.journal_mode,
+            sync: !args.no_sync,
+            busy_timeout: Duration::from_millis(args.busy_timeout),
+        };
+        let mut db = match cli::open_database(args.db_path.as_ref(), &options) {
+            Err(cli::Error::SqlError(error)) => {
+                eprintln!("SQL error: {}.", error);
+                process::exit(1);
+            }
+            x => x?,
+        };
 
-        loop {
+        let exit_code = loop {
             cli::print_prompt()?;
             let command = match cli::read_input() {
                 Err(cli::Error::MetaSyntaxError(s)) => {
@@ -40,6 +62,13 @@ fn main() -> Result<(), cli::Error> {
                 }
                 x => x,
             }?;
+        };
+        match db.close() {
+            Err(error) => {
+                eprintln!("SQL error: {}.", error);
+                1
+            }
+            Ok(()) => exit_code,
         }
     };
     process::exit(exit_code);
@@ -1,19 +1,40 @@
 use std::io;
 use thiserror::Error;
 
+mod aggregate;
+mod ast;
+mod btree;
+mod catalog;
 mod db;
+mod eval;
+mod executor;
+mod functions;
+mod header;
+mod index;
+mod join;
+mod journal;
+mod lock;
 mod pager;
+mod parser;
+mod planner;
+mod projection;
 mod row;
+mod schema;
+mod sort;
 mod statement;
 mod table;
+mod value;
+mod wal;
 
 pub use crate::tokens::{Token, Tokens};
 pub use db::Database;
+pub use pager::{JournalMode, Options};
 pub use statement::Statement;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("syntax error: {0}")]
     SyntaxError(String),
@@ -21,8 +42,22 @@ pub enum Error {
     ExecutionError(String),
     #[error("parser error: {0}")]
     ParserError(String),
-    #[error("table full (max rows {0})")]
-    TableFullError(usize),
+    #[error("constraint violation: duplicate key {key} for {column} in table {table}")]
+    ConstraintViolation {
+        table: String,
+        column: String,
+        key: String,
+    },
+    #[error("database is locked")]
+    DatabaseLocked,
+    #[error("file is not a flakedb database")]
+    NotADatabase,
+    #[error("database disk image is malformed")]
+    Corrupt,
+    #[error("unsupported database format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("unsupported database page size {0}")]
+    UnsupportedPageSize(usize),
     #[error("IO error")]
     IoError(#[from] io::Error),
 }
@@ -0,0 +1,585 @@
+use super::ast::{Expr, Select, SelectItem};
+use super::eval::{self, Scope, ScopeColumn};
+use super::executor::{Node, Operator};
+use super::value::Value;
+use super::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::{slice, vec};
+
+/// Function computing a single value from the rows of each group
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Function {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Self> {
+        Some(match name {
+            "count" => Self::Count,
+            "sum" => Self::Sum,
+            "avg" => Self::Avg,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "group_concat" => Self::GroupConcat,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a lower-case function name is that of an aggregate function
+pub fn is_aggregate(name: &str) -> bool {
+    Function::named(name).is_some()
+}
+
+/// Call of an aggregate function, which is computed into its own column of the aggregated rows
+#[derive(Clone, Debug)]
+struct Aggregate {
+    function: Function,
+    args: Vec<Expr>,
+    distinct: bool,
+    /// Whether this is `count(*)`, counting rows rather than values
+    wildcard: bool,
+}
+
+impl Aggregate {
+    fn new(
+        function: Function,
+        name: &str,
+        args: &[Expr],
+        distinct: bool,
+        wildcard: bool,
+    ) -> Result<Self> {
+        let valid = match function {
+            Function::Count if wildcard => args.is_empty(),
+            Function::GroupConcat => !wildcard && matches!(args.len(), 1 | 2),
+            _ => !wildcard && args.len() == 1,
+        };
+        if !valid {
+            return Err(Error::ExecutionError(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+        if distinct && args.len() != 1 {
+            return Err(Error::ExecutionError(
+                "DISTINCT aggregates must have exactly one argument".into(),
+            ));
+        }
+        Ok(Self {
+            function,
+            args: args.to_vec(),
+            distinct,
+            wildcard,
+        })
+    }
+}
+
+/// Collects the aggregate calls of a query, replacing each by a reference to its column
+#[derive(Default)]
+struct Rewriter {
+    aggregates: Vec<Aggregate>,
+    /// Name of the column of each aggregate, which is the text of its call
+    names: Vec<String>,
+}
+
+impl Rewriter {
+    fn rewrite(&mut self, expr: &Expr) -> Result<Expr> {
+        expr.transform(&mut |expr| {
+            let Expr::Function {
+                name,
+                args,
+                distinct,
+                wildcard,
+            } = expr
+            else {
+                return Ok(None);
+            };
+            let Some(function) = Function::named(name) else {
+                return Ok(None);
+            };
+            // repeated calls are computed once
+            let column = expr.to_string();
+            if !self.names.contains(&column) {
+                let aggregate = Aggregate::new(function, name, args, *distinct, *wildcard)?;
+                self.aggregates.push(aggregate);
+                self.names.push(column.clone());
+            }
+            Ok(Some(Expr::Column {
+                table: None,
+                name: column,
+            }))
+        })
+    }
+}
+
+/// Hash aggregation of the rows of a query into groups, as needed when it has a GROUP BY or HAVING
+/// clause or calls aggregate functions.
+///
+/// Each group is produced as a single row, made of the values of a row of the group followed by
+/// those of the aggregates. Expressions evaluated after grouping refer to the aggregates as columns.
+pub struct Aggregation {
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    /// Scope of the aggregated rows, where aggregates are columns without a table named after
+    /// their call
+    pub scope: Scope,
+    /// Query with its select list, HAVING clause and ORDER BY terms reading aggregates from their
+    /// columns
+    pub select: Select,
+}
+
+impl Aggregation {
+    /// Plan the aggregation of a query over rows of the given scope, unless it needs none
+    pub fn plan(select: &Select, scope: &Scope) -> Result<Option<Self>> {
+        let mut rewriter = Rewriter::default();
+        let mut rewritten = select.clone();
+        for item in &mut rewritten.projection {
+            if let SelectItem::Expr { expr, .. } = item {
+                *expr = rewriter.rewrite(expr)?;
+            }
+        }
+        for term in &mut rewritten.order_by {
+            term.expr = rewriter.rewrite(&term.expr)?;
+        }
+        if let Some(having) = &mut rewritten.having {
+            *having = rewriter.rewrite(having)?;
+        }
+        if select.group_by.is_empty() && select.having.is_none() && rewriter.aggregates.is_empty() {
+            return Ok(None);
+        }
+        let group_by = select
+            .group_by
+            .iter()
+            .map(|expr| group_term(expr, &select.projection, scope))
+            .collect::<Result<_>>()?;
+        let mut aggregated = scope.clone();
+        aggregated.columns.extend(
+            rewriter
+                .names
+                .into_iter()
+                .map(|name| ScopeColumn { table: None, name }),
+        );
+        Ok(Some(Self {
+            group_by,
+            aggregates: rewriter.aggregates,
+            scope: aggregated,
+            select: rewritten,
+        }))
+    }
+
+    /// Operator grouping the rows of an input laid out as described by the given scope.
+    ///
+    /// The HAVING clause is left for the node above it to apply.
+    pub fn operator<'a>(&self, input: Node<'a>, scope: Scope) -> HashAggregate<'a> {
+        HashAggregate {
+            input,
+            group_by: self.group_by.clone(),
+            aggregates: self.aggregates.clone(),
+            scope,
+            groups: None,
+        }
+    }
+}
+
+/// Hash aggregation operator, reading all of its input on the first pull before producing one
+/// row per group
+pub struct HashAggregate<'a> {
+    input: Node<'a>,
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    /// Scope of the rows of the input
+    scope: Scope,
+    groups: Option<vec::IntoIter<Vec<Value>>>,
+}
+
+impl HashAggregate<'_> {
+    fn group_rows(&mut self) -> Result<Vec<Vec<Value>>> {
+        let mut groups = Vec::new();
+        let mut lookup = HashMap::new();
+        while let Some(row) = self.input.next()? {
+            let keys = self
+                .group_by
+                .iter()
+                .map(|expr| eval::evaluate(expr, &self.scope, &row))
+                .collect::<Result<Vec<_>>>()?;
+            let index = *lookup.entry(group_key(&keys)).or_insert_with(|| {
+                groups.push(self.group(row.clone()));
+                groups.len() - 1
+            });
+            let group: &mut Group = &mut groups[index];
+            for (aggregate, state) in self.aggregates.iter().zip(&mut group.states) {
+                state.add(aggregate, &self.scope, &row)?;
+            }
+        }
+        // without GROUP BY, all rows form a single group even when there are none
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push(self.group(vec![Value::Null; self.scope.columns.len()]));
+        }
+        Ok(groups
+            .into_iter()
+            .map(|Group { mut row, states }| {
+                row.extend(
+                    states
+                        .into_iter()
+                        .zip(&self.aggregates)
+                        .map(|(state, aggregate)| state.finish(aggregate.function)),
+                );
+                row
+            })
+            .collect())
+    }
+
+    fn group(&self, row: Vec<Value>) -> Group {
+        Group {
+            row,
+            states: self.aggregates.iter().map(State::new).collect(),
+        }
+    }
+}
+
+impl Operator for HashAggregate<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        if self.groups.is_none() {
+            self.groups = Some(self.group_rows()?.into_iter());
+        }
+        Ok(self.groups.as_mut().unwrap().next())
+    }
+}
+
+/// Resolve a GROUP BY term, which like in SQLite may refer to an item of the select list by its
+/// position or alias
+fn group_term(expr: &Expr, projection: &[SelectItem], scope: &Scope) -> Result<Expr> {
+    let expr = match expr {
+        Expr::Literal(Value::Integer(position)) => {
+            let item = usize::try_from(*position)
+                .ok()
+                .and_then(|position| projection.get(position.checked_sub(1)?));
+            match item {
+                Some(SelectItem::Expr { expr, .. }) => expr,
+                _ => {
+                    return Err(Error::ExecutionError(format!(
+                        "GROUP BY term out of range - should be between 1 and {}",
+                        projection.len()
+                    )))
+                }
+            }
+        }
+        Expr::Column { table: None, name } if scope.resolve(None, name).is_err() => projection
+            .iter()
+            .find_map(|item| match item {
+                SelectItem::Expr {
+                    expr,
+                    alias: Some(alias),
+                } if alias.eq_ignore_ascii_case(name) => Some(expr),
+                _ => None,
+            })
+            .unwrap_or(expr),
+        expr => expr,
+    };
+    let mut rewriter = Rewriter::default();
+    rewriter.rewrite(expr)?;
+    if !rewriter.aggregates.is_empty() {
+        return Err(Error::ExecutionError(
+            "aggregate functions are not allowed in the GROUP BY clause".into(),
+        ));
+    }
+    Ok(expr.clone())
+}
+
+/// Bytes identifying values up to equality, so that values comparing equal such as 1 and 1.0 are
+/// grouped together
+fn group_key(values: &[Value]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for value in values {
+        let integer = match value {
+            Value::Integer(i) => Some(*i),
+            Value::Boolean(b) => Some(*b as i64),
+            Value::Real(r)
+                if r.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(r) =>
+            {
+                Some(*r as i64)
+            }
+            _ => None,
+        };
+        match (value, integer) {
+            (_, Some(i)) => {
+                key.push(1);
+                key.extend_from_slice(&i.to_be_bytes());
+            }
+            (Value::Null, _) => key.push(0),
+            (Value::Real(r), _) => {
+                key.push(2);
+                key.extend_from_slice(&r.to_bits().to_be_bytes());
+            }
+            (Value::Text(s), _) => {
+                key.push(3);
+                key.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                key.extend_from_slice(s.as_bytes());
+            }
+            (Value::Blob(b), _) => {
+                key.push(4);
+                key.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                key.extend_from_slice(b);
+            }
+            (Value::Integer(_) | Value::Boolean(_), None) => unreachable!(),
+        }
+    }
+    key
+}
+
+/// Aggregated row of a group, before the values of its aggregates are added
+struct Group {
+    row: Vec<Value>,
+    states: Vec<State>,
+}
+
+/// Value of an aggregate over the rows of a group seen so far
+enum Accumulator {
+    Count(i64),
+    /// Sum of the values of `sum` or `avg`, along with how many there were
+    Sum {
+        sum: Option<Value>,
+        count: i64,
+    },
+    /// Smallest value for `min`, or largest for `max`
+    Extreme(Option<Value>),
+    Concat(Option<String>),
+}
+
+struct State {
+    accumulator: Accumulator,
+    /// Keys of the values added so far, for aggregates over distinct values only
+    seen: Option<HashSet<Vec<u8>>>,
+}
+
+impl State {
+    fn new(aggregate: &Aggregate) -> Self {
+        let accumulator = match aggregate.function {
+            Function::Count => Accumulator::Count(0),
+            Function::Sum | Function::Avg => Accumulator::Sum {
+                sum: None,
+                count: 0,
+            },
+            Function::Min | Function::Max => Accumulator::Extreme(None),
+            Function::GroupConcat => Accumulator::Concat(None),
+        };
+        Self {
+            accumulator,
+            seen: aggregate.distinct.then(HashSet::new),
+        }
+    }
+
+    fn add(&mut self, aggregate: &Aggregate, scope: &Scope, row: &[Value]) -> Result<()> {
+        if aggregate.wildcard {
+            if let Accumulator::Count(count) = &mut self.accumulator {
+                *count += 1;
+            }
+            return Ok(());
+        }
+        let mut args = aggregate
+            .args
+            .iter()
+            .map(|arg| eval::evaluate(arg, scope, row));
+        let value = args.next().unwrap()?;
+        // like in SQL generally, aggregates skip NULLs
+        if value == Value::Null {
+            return Ok(());
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(group_key(slice::from_ref(&value))) {
+                return Ok(());
+            }
+        }
+        match &mut self.accumulator {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum { sum, count } => {
+                *sum = Some(eval::add(sum.take().unwrap_or(Value::Integer(0)), value)?);
+                *count += 1;
+            }
+            Accumulator::Extreme(extreme) => {
+                let replace = extreme.as_ref().is_none_or(|current| {
+                    let ordering = value.compare(current);
+                    match aggregate.function {
+                        Function::Min => ordering.is_lt(),
+                        _ => ordering.is_gt(),
+                    }
+                });
+                if replace {
+                    *extreme = Some(value);
+                }
+            }
+            Accumulator::Concat(text) => {
+                let separator = args.next().transpose()?;
+                match text {
+                    None => *text = Some(value.to_string()),
+                    Some(text) => {
+                        match separator {
+                            None => text.push(','),
+                            Some(Value::Null) => (),
+                            Some(separator) => text.push_str(&separator.to_string()),
+                        }
+                        text.push_str(&value.to_string());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, function: Function) -> Value {
+        match self.accumulator {
+            Accumulator::Count(count) => Value::Integer(count),
+            Accumulator::Sum { sum: None, .. } => Value::Null,
+            Accumulator::Sum {
+                sum: Some(sum),
+                count,
+            } if function == Function::Avg => Value::Real(sum.as_number().unwrap() / count as f64),
+            Accumulator::Sum { sum: Some(sum), .. } => sum,
+            Accumulator::Extreme(extreme) => extreme.unwrap_or(Value::Null),
+            Accumulator::Concat(text) => text.map_or(Value::Null, Value::Text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::executor::{Filter, Project, Values};
+    use crate::sql::parser::tests::parse_select;
+    use crate::sql::projection::Projection;
+    use crate::sql::schema::{Column, ColumnType, Schema};
+
+    /// Aggregate rows of `(team, score)` and project them with the query's select list
+    fn aggregate(raw: &str, rows: &[(&str, Option<i64>)]) -> Result<Vec<Vec<Value>>> {
+        let select = parse_select(raw)?;
+        let schema = Schema::new(vec![
+            Column::new("team", ColumnType::Text),
+            Column::new("score", ColumnType::Integer),
+        ])
+        .unwrap();
+        let scope = Scope::table("scores", &schema);
+        let aggregation = Aggregation::plan(&select, &scope)?.unwrap();
+        let rows = rows
+            .iter()
+            .map(|(team, score)| {
+                vec![
+                    Value::Text(team.to_string()),
+                    score.map_or(Value::Null, Value::Integer),
+                ]
+            })
+            .collect();
+        let mut node: Node = Box::new(aggregation.operator(Box::new(Values::new(rows)), scope));
+        if let Some(having) = &aggregation.select.having {
+            let filter = Filter::new(node, having.clone(), aggregation.scope.clone());
+            node = Box::new(filter);
+        }
+        let projection = Projection::new(&aggregation.select.projection, &aggregation.scope)?;
+        let mut node = Project::new(node, projection, aggregation.scope.clone());
+        let mut results = Vec::new();
+        while let Some(row) = node.next()? {
+            results.push(row);
+        }
+        Ok(results)
+    }
+
+    const ROWS: &[(&str, Option<i64>)] = &[
+        ("red", Some(3)),
+        ("blue", Some(4)),
+        ("red", None),
+        ("red", Some(3)),
+        ("blue", Some(1)),
+        ("green", None),
+    ];
+
+    #[test]
+    fn aggregates_per_group() {
+        let rows = aggregate(
+            "select team, count(*), count(score), count(distinct score), sum(score), avg(score), \
+             min(score), max(score), group_concat(score, '+') from scores group by team",
+            ROWS,
+        )
+        .unwrap();
+        let text = |s: &str| Value::Text(s.into());
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    text("red"),
+                    Value::Integer(3),
+                    Value::Integer(2),
+                    Value::Integer(1),
+                    Value::Integer(6),
+                    Value::Real(3.0),
+                    Value::Integer(3),
+                    Value::Integer(3),
+                    text("3+3"),
+                ],
+                vec![
+                    text("blue"),
+                    Value::Integer(2),
+                    Value::Integer(2),
+                    Value::Integer(2),
+                    Value::Integer(5),
+                    Value::Real(2.5),
+                    Value::Integer(1),
+                    Value::Integer(4),
+                    text("4+1"),
+                ],
+                vec![
+                    text("green"),
+                    Value::Integer(1),
+                    Value::Integer(0),
+                    Value::Integer(0),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn having_and_expressions() {
+        let rows = aggregate(
+            "select team as t, sum(score) * 2 from scores group by t having count(*) > 2",
+            ROWS,
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![vec![Value::Text("red".into()), Value::Integer(12)]]
+        );
+    }
+
+    #[test]
+    fn single_group_without_rows() {
+        let rows = aggregate(
+            "select count(*), sum(score), group_concat(team) from scores",
+            &[],
+        );
+        assert_eq!(
+            rows.unwrap(),
+            vec![vec![Value::Integer(0), Value::Null, Value::Null]]
+        );
+    }
+
+    #[test]
+    fn misuse_rejected() {
+        for raw in [
+            "select sum(score, 1) from scores",
+            "select count(distinct team, score) from scores",
+            "select team from scores group by count(*)",
+            "select sum(count(*)) from scores",
+            "select team from scores group by 3",
+        ] {
+            assert!(aggregate(raw, ROWS).is_err(), "{}", raw);
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use super::value::Value;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOperator {
+    Not,
+    Negate,
+    Plus,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+}
+
+impl Display for UnaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::Not => "not",
+            Self::Negate => "-",
+            Self::Plus => "+",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::Or => "or",
+            Self::And => "and",
+            Self::Equal => "=",
+            Self::NotEqual => "<>",
+            Self::Less => "<",
+            Self::LessEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterEqual => ">=",
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Modulo => "%",
+            Self::Concat => "||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    /// Column reference, optionally qualified by a table name or alias as in `u.id`
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: BinaryOperator,
+        right: Box<Expr>,
+    },
+    IsNull {
+        operand: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        operand: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Between {
+        operand: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    Like {
+        operand: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    /// Function call, where `wildcard` marks the `count(*)` form taking no arguments
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+        wildcard: bool,
+    },
+}
+
+impl Expr {
+    pub fn column(name: &str) -> Self {
+        Self::Column {
+            table: None,
+            name: name.into(),
+        }
+    }
+
+    /// Binding strength of the expression's outermost operator, matching the parser's levels
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Binary { operator, .. } => operator.precedence(),
+            Self::Unary {
+                operator: UnaryOperator::Not,
+                ..
+            } => 3,
+            Self::IsNull { .. }
+            | Self::InList { .. }
+            | Self::Between { .. }
+            | Self::Like { .. } => 4,
+            Self::Unary { .. } => 9,
+            Self::Literal(_) | Self::Column { .. } | Self::Function { .. } => 10,
+        }
+    }
+
+    /// Rebuild the expression, replacing each subexpression for which `replace` returns a new one
+    /// and leaving the rest as they are
+    pub fn transform<E>(
+        &self,
+        replace: &mut impl FnMut(&Expr) -> Result<Option<Expr>, E>,
+    ) -> Result<Expr, E> {
+        if let Some(expr) = replace(self)? {
+            return Ok(expr);
+        }
+        let mut transform = |expr: &Expr| expr.transform(replace).map(Box::new);
+        Ok(match self {
+            Self::Literal(_) | Self::Column { .. } => self.clone(),
+            Self::Unary { operator, operand } => Self::Unary {
+                operator: *operator,
+                operand: transform(operand)?,
+            },
+            Self::Binary {
+                left,
+                operator,
+                right,
+            } => Self::Binary {
+                left: transform(left)?,
+                operator: *operator,
+                right: transform(right)?,
+            },
+            Self::IsNull { operand, negated } => Self::IsNull {
+                operand: transform(operand)?,
+                negated: *negated,
+            },
+            Self::InList {
+                operand,
+                list,
+                negated,
+            } => Self::InList {
+                operand: transform(operand)?,
+                list: list
+                    .iter()
+                    .map(|item| Ok(*transform(item)?))
+                    .collect::<Result<_, E>>()?,
+                negated: *negated,
+            },
+            Self::Between {
+                operand,
+                low,
+                high,
+                negated,
+            } => Self::Between {
+                operand: transform(operand)?,
+                low: transform(low)?,
+                high: transform(high)?,
+                negated: *negated,
+            },
+            Self::Like {
+                operand,
+                pattern,
+                negated,
+            } => Self::Like {
+                operand: transform(operand)?,
+                pattern: transform(pattern)?,
+                negated: *negated,
+            },
+            Self::Function {
+                name,
+                args,
+                distinct,
+                wildcard,
+            } => Self::Function {
+                name: name.clone(),
+                args: args
+                    .iter()
+                    .map(|arg| Ok(*transform(arg)?))
+                    .collect::<Result<_, E>>()?,
+                distinct: *distinct,
+                wildcard: *wildcard,
+            },
+        })
+    }
+
+    /// Write a subexpression, parenthesised if it binds less tightly than its position requires
+    fn fmt_operand(&self, f: &mut Formatter<'_>, precedence: u8) -> std::fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl BinaryOperator {
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Equal | Self::NotEqual => 4,
+            Self::Less | Self::LessEqual | Self::Greater | Self::GreaterEqual => 5,
+            Self::Add | Self::Subtract => 6,
+            Self::Multiply | Self::Divide | Self::Modulo => 7,
+            Self::Concat => 8,
+        }
+    }
+}
+
+/// Renders the expression as SQL, as used for the names of unaliased result columns
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let not = |negated: &bool| if *negated { "not " } else { "" };
+        match self {
+            Self::Literal(Value::Null) => write!(f, "null"),
+            Self::Literal(Value::Text(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            Self::Literal(value) => write!(f, "{}", value),
+            Self::Column { table: None, name } => write!(f, "{}", name),
+            Self::Column {
+                table: Some(table),
+                name,
+            } => write!(f, "{}.{}", table, name),
+            Self::Unary {
+                operator: UnaryOperator::Not,
+                operand,
+            } => {
+                write!(f, "not ")?;
+                operand.fmt_operand(f, 3)
+            }
+            Self::Unary { operator, operand } => {
+                write!(f, "{}", operator)?;
+                operand.fmt_operand(f, 9)
+            }
+            Self::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let precedence = operator.precedence();
+                left.fmt_operand(f, precedence)?;
+                write!(f, " {} ", operator)?;
+                right.fmt_operand(f, precedence + 1)
+            }
+            Self::IsNull { operand, negated } => {
+                operand.fmt_operand(f, 4)?;
+                write!(f, " is {}null", not(negated))
+            }
+            Self::InList {
+                operand,
+                list,
+                negated,
+            } => {
+                operand.fmt_operand(f, 4)?;
+                write!(f, " {}in (", not(negated))?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+            Self::Between {
+                operand,
+                low,
+                high,
+                negated,
+            } => {
+                operand.fmt_operand(f, 4)?;
+                write!(f, " {}between ", not(negated))?;
+                low.fmt_operand(f, 5)?;
+                write!(f, " and ")?;
+                high.fmt_operand(f, 5)
+            }
+            Self::Like {
+                operand,
+                pattern,
+                negated,
+            } => {
+                operand.fmt_operand(f, 4)?;
+                write!(f, " {}like ", not(negated))?;
+                pattern.fmt_operand(f, 5)
+            }
+            Self::Function {
+                name,
+                args,
+                distinct,
+                wildcard,
+            } => {
+                write!(f, "{}(", name)?;
+                if *wildcard {
+                    write!(f, "*")?;
+                }
+                if *distinct {
+                    write!(f, "distinct ")?;
+                }
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectItem {
+    /// `*`, or `t.*` when qualified by a table
+    Wildcard(Option<String>),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    /// Keeps rows of the left side without a match, pairing them with NULLs
+    Left,
+}
+
+/// Table joined to the ones before it in the from clause, where a cross join is an inner join
+/// without a condition
+#[derive(Clone, Debug, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub on: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+    /// Whether NULLs come before other values, which by default they do in ascending order only
+    pub nulls_first: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    pub projection: Vec<SelectItem>,
+    pub from: Option<TableRef>,
+    pub joins: Vec<Join>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Insert {
+    pub table: String,
+    /// Columns the values are given for, or every column in schema order when absent
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Update {
+    pub table: String,
+    pub assignments: Vec<(String, Expr)>,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delete {
+    pub table: String,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::parser::Parser;
+    use crate::tokens::Tokens;
+    use test_case::test_case;
+
+    #[test_case("id*2" => "id * 2" ; "spacing")]
+    #[test_case("(a + b) * c" => "(a + b) * c" ; "needed parentheses")]
+    #[test_case("a + (b * c)" => "a + b * c" ; "redundant parentheses")]
+    #[test_case("a - (b - c)" => "a - (b - c)" ; "right operand")]
+    #[test_case("NOT (a OR b) AND c" => "not (a or b) and c" ; "boolean")]
+    #[test_case("-(1 + x)" => "-(1 + x)" ; "negation")]
+    #[test_case("u.name NOT LIKE 'it''s%'" => "u.name not like 'it''s%'" ; "like with quote")]
+    #[test_case("x not between 1 and 2 + 3" => "x not between 1 and 2 + 3" ; "between")]
+    #[test_case("x in (1, null)" => "x in (1, null)" ; "in list")]
+    #[test_case("Count(Distinct a) + COUNT(*)" => "count(distinct a) + count(*)" ; "functions")]
+    fn display(raw: &str) -> String {
+        Parser::new(Tokens::from(raw))
+            .parse_expr()
+            .unwrap()
+            .to_string()
+    }
+}
@@ -0,0 +1,980 @@
+use crate::sql::pager::{Pager, PAGE_SIZE};
+use crate::sql::{Error, Result};
+
+// node header layout, shared by leaf and internal nodes
+const NODE_TYPE: usize = 0;
+const NUM_CELLS: usize = 2;
+const CONTENT_START: usize = 4;
+// next leaf page for leaf nodes, right-most child page for internal nodes
+const LINK: usize = 6;
+const HEADER_SIZE: usize = 10;
+const CELL_POINTER_SIZE: usize = 2;
+
+const LEAF_CELL_HEADER_SIZE: usize = 4;
+const INTERNAL_CELL_HEADER_SIZE: usize = 6;
+
+/// Space available for cells and their pointers in a single node
+const NODE_CAPACITY: usize = PAGE_SIZE - HEADER_SIZE;
+
+/// Largest cell accepted by the tree, chosen so that any split leaves both halves non-empty
+pub const MAX_CELL_SIZE: usize = (PAGE_SIZE - HEADER_SIZE) / 4 - CELL_POINTER_SIZE;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeType {
+    Leaf = 1,
+    Internal = 2,
+}
+
+/// View over a page interpreted as a B+tree node.
+///
+/// Cells are stored in a slotted layout: a sorted array of cell pointers grows forwards after the
+/// header while the cell contents grow backwards from the end of the page. Leaf cells hold a key
+/// and a value; internal cells hold a child page and the largest key stored under that child.
+pub struct Node<T> {
+    data: T,
+    node_type: NodeType,
+}
+
+impl<T: AsRef<[u8]>> Node<T> {
+    /// View a page holding a node, failing if it does not start with a valid node type
+    pub fn new(data: T) -> Result<Self> {
+        let node_type = match data.as_ref()[NODE_TYPE] {
+            1 => NodeType::Leaf,
+            2 => NodeType::Internal,
+            _ => return Err(Error::Corrupt),
+        };
+        Ok(Self { data, node_type })
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    fn read_u16(&self, offset: usize) -> usize {
+        u16::from_be_bytes(self.data()[offset..offset + 2].try_into().unwrap()) as usize
+    }
+
+    fn read_u32(&self, offset: usize) -> usize {
+        u32::from_be_bytes(self.data()[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        self.node_type
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.read_u16(NUM_CELLS)
+    }
+
+    fn content_start(&self) -> usize {
+        self.read_u16(CONTENT_START)
+    }
+
+    /// Page number of the next leaf in key order, if any
+    pub fn next_leaf(&self) -> Option<usize> {
+        match self.read_u32(LINK) {
+            0 => None,
+            page => Some(page),
+        }
+    }
+
+    /// Contiguous space between the cell pointer array and the cell contents
+    fn free_space(&self) -> usize {
+        self.content_start() - HEADER_SIZE - CELL_POINTER_SIZE * self.num_cells()
+    }
+
+    /// Free space including gaps left behind by removed cells
+    fn total_free_space(&self) -> usize {
+        NODE_CAPACITY - self.used_space()
+    }
+
+    /// Space taken up by cells and their pointers
+    fn used_space(&self) -> usize {
+        let used: usize = (0..self.num_cells()).map(|i| self.cell_size(i)).sum();
+        CELL_POINTER_SIZE * self.num_cells() + used
+    }
+
+    fn cell_offset(&self, index: usize) -> usize {
+        self.read_u16(HEADER_SIZE + CELL_POINTER_SIZE * index)
+    }
+
+    fn cell_size(&self, index: usize) -> usize {
+        let offset = self.cell_offset(index);
+        match self.node_type() {
+            NodeType::Leaf => {
+                LEAF_CELL_HEADER_SIZE + self.read_u16(offset) + self.read_u16(offset + 2)
+            }
+            NodeType::Internal => INTERNAL_CELL_HEADER_SIZE + self.read_u16(offset + 4),
+        }
+    }
+
+    fn cell(&self, index: usize) -> &[u8] {
+        let offset = self.cell_offset(index);
+        &self.data()[offset..offset + self.cell_size(index)]
+    }
+
+    pub fn key(&self, index: usize) -> &[u8] {
+        let offset = self.cell_offset(index);
+        match self.node_type() {
+            NodeType::Leaf => {
+                let start = offset + LEAF_CELL_HEADER_SIZE;
+                &self.data()[start..start + self.read_u16(offset)]
+            }
+            NodeType::Internal => {
+                let start = offset + INTERNAL_CELL_HEADER_SIZE;
+                &self.data()[start..start + self.read_u16(offset + 4)]
+            }
+        }
+    }
+
+    pub fn value(&self, index: usize) -> &[u8] {
+        let offset = self.cell_offset(index);
+        let start = offset + LEAF_CELL_HEADER_SIZE + self.read_u16(offset);
+        &self.data()[start..start + self.read_u16(offset + 2)]
+    }
+
+    /// Child page at `index`, where `index == num_cells()` refers to the right-most child
+    pub fn child(&self, index: usize) -> usize {
+        if index == self.num_cells() {
+            self.read_u32(LINK)
+        } else {
+            self.read_u32(self.cell_offset(index))
+        }
+    }
+
+    /// Index of the first cell whose key is greater than (or equal to, unless `after_equal`) `key`
+    pub fn search(&self, key: &[u8], after_equal: bool) -> usize {
+        let (mut low, mut high) = (0, self.num_cells());
+        while low < high {
+            let mid = (low + high) / 2;
+            let cell_key = self.key(mid);
+            if cell_key < key || (after_equal && cell_key == key) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    fn cells(&self) -> Vec<Vec<u8>> {
+        (0..self.num_cells())
+            .map(|i| self.cell(i).to_vec())
+            .collect()
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Node<T> {
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut()
+    }
+
+    fn write_u16(&mut self, offset: usize, value: usize) {
+        self.data_mut()[offset..offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    fn write_u32(&mut self, offset: usize, value: usize) {
+        self.data_mut()[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes());
+    }
+
+    /// Format a page, whatever it held before, as an empty node of the given type
+    pub fn create(data: T, node_type: NodeType) -> Self {
+        let mut node = Self { data, node_type };
+        node.init(node_type);
+        node
+    }
+
+    /// Reset the page to an empty node of the given type
+    fn init(&mut self, node_type: NodeType) {
+        self.data_mut()[..HEADER_SIZE].fill(0);
+        self.data_mut()[NODE_TYPE] = node_type as u8;
+        self.node_type = node_type;
+        self.write_u16(CONTENT_START, PAGE_SIZE);
+    }
+
+    pub fn set_next_leaf(&mut self, page: Option<usize>) {
+        self.write_u32(LINK, page.unwrap_or(0));
+    }
+
+    pub fn set_right_child(&mut self, page: usize) {
+        self.write_u32(LINK, page);
+    }
+
+    /// Point the child at `index` (or the right-most child) to a different page
+    pub fn set_child(&mut self, index: usize, page: usize) {
+        if index == self.num_cells() {
+            self.set_right_child(page);
+        } else {
+            let offset = self.cell_offset(index);
+            self.write_u32(offset, page);
+        }
+    }
+
+    /// Insert raw cell bytes at `index`, returning false if the page has no room left
+    fn insert_cell(&mut self, index: usize, cell: &[u8]) -> bool {
+        if cell.len() + CELL_POINTER_SIZE > self.free_space() {
+            if cell.len() + CELL_POINTER_SIZE > self.total_free_space() {
+                return false;
+            }
+            // reclaim the gaps left by removed cells
+            let cells = self.cells();
+            self.rewrite(&cells);
+        }
+        let num_cells = self.num_cells();
+        let offset = self.content_start() - cell.len();
+        self.data_mut()[offset..offset + cell.len()].copy_from_slice(cell);
+        let pointers = HEADER_SIZE + CELL_POINTER_SIZE * index;
+        let pointers_end = HEADER_SIZE + CELL_POINTER_SIZE * num_cells;
+        self.data_mut()
+            .copy_within(pointers..pointers_end, pointers + CELL_POINTER_SIZE);
+        self.write_u16(pointers, offset);
+        self.write_u16(NUM_CELLS, num_cells + 1);
+        self.write_u16(CONTENT_START, offset);
+        true
+    }
+
+    /// Remove the cell at `index`, leaving its space to be reclaimed by a later insert
+    pub fn remove_cell(&mut self, index: usize) {
+        let num_cells = self.num_cells();
+        let (offset, size) = (self.cell_offset(index), self.cell_size(index));
+        let pointer = HEADER_SIZE + CELL_POINTER_SIZE * index;
+        let pointers_end = HEADER_SIZE + CELL_POINTER_SIZE * num_cells;
+        self.data_mut()
+            .copy_within(pointer + CELL_POINTER_SIZE..pointers_end, pointer);
+        self.write_u16(NUM_CELLS, num_cells - 1);
+        if offset == self.content_start() {
+            self.write_u16(CONTENT_START, offset + size);
+        }
+    }
+
+    /// Replace the contents of the node with the given cells, keeping its type and link
+    fn rewrite(&mut self, cells: &[Vec<u8>]) {
+        let node_type = self.node_type();
+        let link = self.read_u32(LINK);
+        self.init(node_type);
+        self.write_u32(LINK, link);
+        for (i, cell) in cells.iter().enumerate() {
+            assert!(
+                self.insert_cell(i, cell),
+                "cells do not fit in a single page"
+            );
+        }
+    }
+}
+
+fn leaf_cell(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(LEAF_CELL_HEADER_SIZE + key.len() + value.len());
+    cell.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    cell.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    cell.extend_from_slice(key);
+    cell.extend_from_slice(value);
+    cell
+}
+
+fn internal_cell(child: usize, key: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(INTERNAL_CELL_HEADER_SIZE + key.len());
+    cell.extend_from_slice(&(child as u32).to_be_bytes());
+    cell.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    cell.extend_from_slice(key);
+    cell
+}
+
+fn internal_cell_parts(cell: &[u8]) -> (usize, &[u8]) {
+    let child = u32::from_be_bytes(cell[0..4].try_into().unwrap()) as usize;
+    (child, &cell[INTERNAL_CELL_HEADER_SIZE..])
+}
+
+fn leaf_cell_key(cell: &[u8]) -> &[u8] {
+    let len = u16::from_be_bytes(cell[0..2].try_into().unwrap()) as usize;
+    &cell[LEAF_CELL_HEADER_SIZE..LEAF_CELL_HEADER_SIZE + len]
+}
+
+fn check_cell_size(cell: &[u8]) -> Result<()> {
+    if cell.len() > MAX_CELL_SIZE {
+        Err(Error::ExecutionError(format!(
+            "record too large ({} bytes, max {})",
+            cell.len(),
+            MAX_CELL_SIZE
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Index at which to split `cells` so that both halves hold roughly the same number of bytes
+fn split_point(cells: &[Vec<u8>]) -> usize {
+    let total: usize = cells.iter().map(|cell| cell.len()).sum();
+    let mut left = 0;
+    for (i, cell) in cells.iter().enumerate() {
+        left += cell.len();
+        if left >= total / 2 {
+            return (i + 1).clamp(1, cells.len() - 1);
+        }
+    }
+    cells.len() - 1
+}
+
+/// Separator key and page of the new right sibling produced by splitting a node
+type Split = (Vec<u8>, usize);
+
+/// B+tree of byte string keys and values rooted at a fixed page.
+///
+/// Keys are compared as raw bytes and may repeat, in which case they are kept in insertion order.
+/// The root never moves: when it splits, its contents are copied to a fresh page first.
+pub struct BTree<'a> {
+    pager: &'a Pager,
+    root: usize,
+}
+
+impl<'a> BTree<'a> {
+    pub fn new(pager: &'a Pager, root: usize) -> Self {
+        Self { pager, root }
+    }
+
+    /// Allocate a page for a new, empty tree
+    pub fn create(pager: &'a Pager) -> Result<Self> {
+        let root = pager.allocate_page()?;
+        Node::create(pager.borrow_page_mut(root)?.as_mut_slice(), NodeType::Leaf);
+        Ok(Self::new(pager, root))
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Locate the leaf page and cell index of the first entry with a key not less than `key`
+    pub fn seek(&self, key: &[u8]) -> Result<(usize, usize)> {
+        let mut page = self.root;
+        loop {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            match node.node_type() {
+                NodeType::Leaf => return Ok((page, node.search(key, false))),
+                NodeType::Internal => page = node.child(node.search(key, false)),
+            }
+        }
+    }
+
+    /// Locate the first entry with exactly the given key
+    pub fn find(&self, key: &[u8]) -> Result<Option<(usize, usize)>> {
+        let (mut page, mut index) = self.seek(key)?;
+        loop {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            if index < node.num_cells() {
+                return Ok((node.key(index) == key).then_some((page, index)));
+            }
+            // the leaf may be exhausted when all of its keys are smaller than the one sought
+            match node.next_leaf() {
+                Some(next) => {
+                    page = next;
+                    index = 0;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Overwrite the value of the first entry with the given key, returning false if there is none
+    pub fn replace(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        let cell = leaf_cell(key, value);
+        check_cell_size(&cell)?;
+        let (page, index) = match self.find(key)? {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        {
+            let mut node = self.pager.borrow_page_mut(page)?;
+            let mut node = Node::new(node.as_mut_slice())?;
+            node.remove_cell(index);
+            if node.insert_cell(index, &cell) {
+                return Ok(true);
+            }
+        }
+        // the new value is larger and no longer fits, so go through a regular insert and split
+        self.insert(key, value)?;
+        Ok(true)
+    }
+
+    /// Insert an entry after any existing entries with the same key
+    pub fn insert(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let cell = leaf_cell(key, value);
+        check_cell_size(&cell)?;
+        if let Some((separator, right)) = self.insert_into(self.root, key, &cell)? {
+            self.split_root(&separator, right)?;
+        }
+        Ok(())
+    }
+
+    fn insert_into(&self, page: usize, key: &[u8], cell: &[u8]) -> Result<Option<Split>> {
+        let node_type = Node::new(self.pager.borrow_page(page)?.as_slice())?.node_type();
+        match node_type {
+            NodeType::Leaf => self.insert_into_leaf(page, key, cell),
+            NodeType::Internal => {
+                let (index, child) = {
+                    let node = self.pager.borrow_page(page)?;
+                    let node = Node::new(node.as_slice())?;
+                    let index = node.search(key, true);
+                    (index, node.child(index))
+                };
+                match self.insert_into(child, key, cell)? {
+                    None => Ok(None),
+                    Some((separator, right)) => {
+                        self.insert_into_internal(page, index, child, &separator, right)
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert_into_leaf(&self, page: usize, key: &[u8], cell: &[u8]) -> Result<Option<Split>> {
+        let (mut cells, index, next_leaf) = {
+            let mut node = self.pager.borrow_page_mut(page)?;
+            let mut node = Node::new(node.as_mut_slice())?;
+            let index = node.search(key, true);
+            if node.insert_cell(index, cell) {
+                return Ok(None);
+            }
+            (node.cells(), index, node.next_leaf())
+        };
+        let appending = index == cells.len() && next_leaf.is_none();
+        cells.insert(index, cell.to_vec());
+        // sequential inserts at the end of the tree leave the left node full rather than half empty
+        let split = if appending {
+            cells.len() - 1
+        } else {
+            split_point(&cells)
+        };
+        let right_page = self.pager.allocate_page()?;
+        {
+            let mut right = self.pager.borrow_page_mut(right_page)?;
+            let mut right = Node::create(right.as_mut_slice(), NodeType::Leaf);
+            right.set_next_leaf(next_leaf);
+            right.rewrite(&cells[split..]);
+        }
+        let mut left = self.pager.borrow_page_mut(page)?;
+        let mut left = Node::new(left.as_mut_slice())?;
+        left.set_next_leaf(Some(right_page));
+        left.rewrite(&cells[..split]);
+        let separator = leaf_cell_key(&cells[split - 1]).to_vec();
+        Ok(Some((separator, right_page)))
+    }
+
+    /// Record that the child at `index` was split into `child` (keeping keys up to `separator`)
+    /// and `right`
+    fn insert_into_internal(
+        &self,
+        page: usize,
+        index: usize,
+        child: usize,
+        separator: &[u8],
+        right: usize,
+    ) -> Result<Option<Split>> {
+        let cell = internal_cell(child, separator);
+        let (mut cells, right_child) = {
+            let mut node = self.pager.borrow_page_mut(page)?;
+            let mut node = Node::new(node.as_mut_slice())?;
+            if node.insert_cell(index, &cell) {
+                node.set_child(index + 1, right);
+                return Ok(None);
+            }
+            (node.cells(), node.child(node.num_cells()))
+        };
+        // the cell previously at `index` (or the right-most child) now covers the new right page
+        let right_child = if index == cells.len() {
+            right
+        } else {
+            let (_, key) = internal_cell_parts(&cells[index]);
+            cells[index] = internal_cell(right, key);
+            right_child
+        };
+        cells.insert(index, cell);
+        let middle = split_point(&cells);
+        let (middle_child, middle_key) = internal_cell_parts(&cells[middle]);
+        let new_page = self.pager.allocate_page()?;
+        {
+            let mut new_node = self.pager.borrow_page_mut(new_page)?;
+            let mut new_node = Node::create(new_node.as_mut_slice(), NodeType::Internal);
+            new_node.set_right_child(right_child);
+            new_node.rewrite(&cells[middle + 1..]);
+        }
+        let mut node = self.pager.borrow_page_mut(page)?;
+        let mut node = Node::new(node.as_mut_slice())?;
+        node.set_right_child(middle_child);
+        node.rewrite(&cells[..middle]);
+        Ok(Some((middle_key.to_vec(), new_page)))
+    }
+
+    /// Remove the first entry with exactly the given key, returning false if there is none.
+    ///
+    /// Nodes left less than half full are merged with a neighbour whenever the two fit in a single
+    /// page, and the pages emptied this way are handed back to the pager for reuse.
+    pub fn remove(&self, key: &[u8]) -> Result<bool> {
+        if !self.remove_from(self.root, key)? {
+            return Ok(false);
+        }
+        self.collapse_root()?;
+        Ok(true)
+    }
+
+    fn remove_from(&self, page: usize, key: &[u8]) -> Result<bool> {
+        let (node_type, mut index) = {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            let index = node.search(key, false);
+            if node.node_type() == NodeType::Leaf
+                && !(index < node.num_cells() && node.key(index) == key)
+            {
+                return Ok(false);
+            }
+            (node.node_type(), index)
+        };
+        if node_type == NodeType::Leaf {
+            Node::new(self.pager.borrow_page_mut(page)?.as_mut_slice())?.remove_cell(index);
+            return Ok(true);
+        }
+        loop {
+            let (child, more) = {
+                let node = self.pager.borrow_page(page)?;
+                let node = Node::new(node.as_slice())?;
+                // duplicates of a separator key may continue into the following child
+                let more = index < node.num_cells() && node.key(index) == key;
+                (node.child(index), more)
+            };
+            if self.remove_from(child, key)? {
+                self.rebalance(page, index)?;
+                return Ok(true);
+            }
+            if !more {
+                return Ok(false);
+            }
+            index += 1;
+        }
+    }
+
+    /// Merge the child at `index` into a neighbour if it is less than half full and the two fit in
+    /// a single page, freeing the page of the right-hand node of the pair
+    fn rebalance(&self, page: usize, index: usize) -> Result<()> {
+        let (left_index, left, right, separator) = {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            let used =
+                Node::new(self.pager.borrow_page(node.child(index))?.as_slice())?.used_space();
+            if used >= NODE_CAPACITY / 2 {
+                return Ok(());
+            }
+            let left_index = if index < node.num_cells() {
+                index
+            } else if index > 0 {
+                index - 1
+            } else {
+                return Ok(());
+            };
+            (
+                left_index,
+                node.child(left_index),
+                node.child(left_index + 1),
+                node.key(left_index).to_vec(),
+            )
+        };
+        let (cells, link) = {
+            let left = self.pager.borrow_page(left)?;
+            let left = Node::new(left.as_slice())?;
+            let right = self.pager.borrow_page(right)?;
+            let right = Node::new(right.as_slice())?;
+            let mut cells = left.cells();
+            if left.node_type() == NodeType::Internal {
+                // the left node's right-most child moves into a cell bounded by the separator
+                cells.push(internal_cell(left.child(left.num_cells()), &separator));
+            }
+            cells.extend(right.cells());
+            (cells, right.read_u32(LINK))
+        };
+        let size: usize = cells
+            .iter()
+            .map(|cell| cell.len() + CELL_POINTER_SIZE)
+            .sum();
+        if size > NODE_CAPACITY {
+            return Ok(());
+        }
+        {
+            let mut left = self.pager.borrow_page_mut(left)?;
+            let mut left = Node::new(left.as_mut_slice())?;
+            left.write_u32(LINK, link);
+            left.rewrite(&cells);
+        }
+        // the parent entry that covered the right node now covers the merged node
+        let mut node = self.pager.borrow_page_mut(page)?;
+        let mut node = Node::new(node.as_mut_slice())?;
+        node.remove_cell(left_index);
+        node.set_child(left_index, left);
+        self.pager.free_page(right)?;
+        Ok(())
+    }
+
+    /// Pull the contents of the only child of an internal root up into the root, removing a level
+    /// from the tree
+    fn collapse_root(&self) -> Result<()> {
+        loop {
+            let child = {
+                let root = self.pager.borrow_page(self.root)?;
+                let root = Node::new(root.as_slice())?;
+                if root.node_type() == NodeType::Leaf || root.num_cells() > 0 {
+                    return Ok(());
+                }
+                root.child(0)
+            };
+            let child_page = self.pager.borrow_page(child)?;
+            self.pager
+                .borrow_page_mut(self.root)?
+                .as_mut_slice()
+                .copy_from_slice(child_page.as_slice());
+            drop(child_page);
+            self.pager.free_page(child)?;
+        }
+    }
+
+    /// Hand every page of the tree, including the root, back to the pager for reuse
+    pub fn destroy(self) -> Result<()> {
+        self.free_from(self.root)
+    }
+
+    fn free_from(&self, page: usize) -> Result<()> {
+        let children: Vec<usize> = {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            match node.node_type() {
+                NodeType::Leaf => Vec::new(),
+                NodeType::Internal => (0..=node.num_cells()).map(|i| node.child(i)).collect(),
+            }
+        };
+        for child in children {
+            self.free_from(child)?;
+        }
+        self.pager.free_page(page)?;
+        Ok(())
+    }
+
+    /// Move every page of the tree numbered `limit` or above into one of `destinations`, rewriting
+    /// the pointers to it, so that the database can be cut down to `limit` pages. Returns the new
+    /// root, which moves like any other page.
+    pub fn relocate(&self, limit: usize, destinations: &mut Vec<usize>) -> Result<usize> {
+        self.relocate_from(self.root, limit, destinations, &mut None)
+    }
+
+    /// Relocate the subtree under `page`, returning where `page` ended up. `prev_leaf` tracks the
+    /// last leaf visited, which links to the next one in key order.
+    fn relocate_from(
+        &self,
+        page: usize,
+        limit: usize,
+        destinations: &mut Vec<usize>,
+        prev_leaf: &mut Option<usize>,
+    ) -> Result<usize> {
+        let moved = page >= limit;
+        let page = if moved {
+            let destination = destinations.pop().expect("no free page to relocate to");
+            let original = self.pager.borrow_page(page)?;
+            self.pager
+                .borrow_page_mut(destination)?
+                .as_mut_slice()
+                .copy_from_slice(original.as_slice());
+            destination
+        } else {
+            page
+        };
+        let children: Vec<usize> = {
+            let node = self.pager.borrow_page(page)?;
+            let node = Node::new(node.as_slice())?;
+            match node.node_type() {
+                NodeType::Leaf => Vec::new(),
+                NodeType::Internal => (0..=node.num_cells()).map(|i| node.child(i)).collect(),
+            }
+        };
+        if children.is_empty() {
+            if let Some(prev) = prev_leaf.filter(|_| moved) {
+                let mut prev = self.pager.borrow_page_mut(prev)?;
+                Node::new(prev.as_mut_slice())?.write_u32(LINK, page);
+            }
+            *prev_leaf = Some(page);
+        }
+        for (i, child) in children.into_iter().enumerate() {
+            let relocated = self.relocate_from(child, limit, destinations, prev_leaf)?;
+            if relocated != child {
+                let mut node = self.pager.borrow_page_mut(page)?;
+                Node::new(node.as_mut_slice())?.set_child(i, relocated);
+            }
+        }
+        Ok(page)
+    }
+
+    /// Move the contents of the root into a new left child and make the root an internal node
+    /// over that child and `right`
+    fn split_root(&self, separator: &[u8], right: usize) -> Result<()> {
+        let left = self.pager.allocate_page()?;
+        let mut root = self.pager.borrow_page_mut(self.root)?;
+        self.pager
+            .borrow_page_mut(left)?
+            .as_mut_slice()
+            .copy_from_slice(root.as_slice());
+        let mut root = Node::new(root.as_mut_slice())?;
+        root.init(NodeType::Internal);
+        root.set_right_child(right);
+        root.insert_cell(0, &internal_cell(left, separator));
+        Ok(())
+    }
+}
+
+/// Position within the leaf level of a B+tree, advancing through entries in key order
+pub struct Cursor<'a> {
+    pager: &'a Pager,
+    page: usize,
+    cell: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn start(pager: &'a Pager, root: usize) -> Result<Self> {
+        Self::seek(pager, root, &[])
+    }
+
+    /// Position the cursor on the first entry with a key not less than `key`
+    pub fn seek(pager: &'a Pager, root: usize, key: &[u8]) -> Result<Self> {
+        let (page, cell) = BTree::new(pager, root).seek(key)?;
+        Ok(Self { pager, page, cell })
+    }
+
+    /// Copy out the key and record under the cursor, following leaf links past the end of each page
+    fn entry(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let page = self.pager.borrow_page(self.page)?;
+            let node = Node::new(page.as_slice())?;
+            if self.cell < node.num_cells() {
+                let key = node.key(self.cell).to_vec();
+                return Ok(Some((key, node.value(self.cell).to_vec())));
+            }
+            match node.next_leaf() {
+                Some(next) => {
+                    self.page = next;
+                    self.cell = 0;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.entry() {
+            Ok(Some(entry)) => {
+                self.cell += 1;
+                Some(Ok(entry))
+            }
+            Ok(None) => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u32) -> [u8; 4] {
+        i.to_be_bytes()
+    }
+
+    fn scan(tree: &BTree) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (mut page, mut index) = tree.seek(&[]).unwrap();
+        let mut entries = Vec::new();
+        loop {
+            let data = tree.pager.borrow_page(page).unwrap();
+            let node = Node::new(data.as_slice()).unwrap();
+            if index < node.num_cells() {
+                entries.push((node.key(index).to_vec(), node.value(index).to_vec()));
+                index += 1;
+            } else if let Some(next) = node.next_leaf() {
+                page = next;
+                index = 0;
+            } else {
+                return entries;
+            }
+        }
+    }
+
+    #[test]
+    fn insert_out_of_order() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        // a multiplicative permutation of 0..400 to insert keys in scrambled order
+        let keys: Vec<u32> = (0..400).map(|i| (i * 379) % 400).collect();
+        for &k in &keys {
+            tree.insert(&key(k), &[0xab; 200]).unwrap();
+        }
+        let root = pager.borrow_page(tree.root).unwrap();
+        assert_eq!(
+            Node::new(root.as_slice()).unwrap().node_type(),
+            NodeType::Internal
+        );
+        drop(root);
+        let scanned: Vec<_> = scan(&tree).into_iter().map(|(k, _)| k).collect();
+        let expected: Vec<_> = (0..400).map(|k| key(k).to_vec()).collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn duplicate_keys_keep_insertion_order() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..200u32 {
+            tree.insert(&key(i % 2), &i.to_be_bytes()).unwrap();
+        }
+        let values: Vec<_> = scan(&tree).into_iter().map(|(_, v)| v).collect();
+        let expected: Vec<_> = (0..200u32)
+            .filter(|i| i % 2 == 0)
+            .chain((0..200u32).filter(|i| i % 2 == 1))
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn seek_finds_first_match() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..500u32 {
+            tree.insert(&key(i * 2), &[0; 100]).unwrap();
+        }
+        let (page, index) = tree.seek(&key(601)).unwrap();
+        let data = pager.borrow_page(page).unwrap();
+        assert_eq!(Node::new(data.as_slice()).unwrap().key(index), key(602));
+    }
+
+    #[test]
+    fn replace_values() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..300u32 {
+            tree.insert(&key(i), &[1; 50]).unwrap();
+        }
+        // shrink every value, then grow them all past their original size to force splits
+        for (i, size) in (0..300u32)
+            .map(|i| (i, 10))
+            .chain((0..300u32).map(|i| (i, 120)))
+        {
+            assert!(tree.replace(&key(i), &vec![i as u8; size]).unwrap());
+        }
+        assert!(!tree.replace(&key(1000), &[]).unwrap());
+        let entries = scan(&tree);
+        assert_eq!(entries.len(), 300);
+        for (i, (k, v)) in entries.into_iter().enumerate() {
+            assert_eq!(k, key(i as u32));
+            assert_eq!(v, vec![i as u8; 120]);
+        }
+    }
+
+    #[test]
+    fn remove_entries() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        let keys: Vec<u32> = (0..1000).map(|i| (i * 379) % 1000).collect();
+        for &k in &keys {
+            tree.insert(&key(k), &[0xcd; 100]).unwrap();
+        }
+        // remove the odd keys in scrambled order
+        for &k in keys.iter().filter(|&&k| k % 2 == 1) {
+            assert!(tree.remove(&key(k)).unwrap(), "missing key {}", k);
+        }
+        assert!(!tree.remove(&key(1)).unwrap());
+        assert!(!tree.remove(&key(5000)).unwrap());
+        let scanned: Vec<_> = scan(&tree).into_iter().map(|(k, _)| k).collect();
+        let expected: Vec<_> = (0..1000).step_by(2).map(|k| key(k).to_vec()).collect();
+        assert_eq!(scanned, expected);
+        for &k in keys.iter().filter(|&&k| k % 2 == 0) {
+            assert!(tree.remove(&key(k)).unwrap(), "missing key {}", k);
+        }
+        assert!(scan(&tree).is_empty());
+        let root = pager.borrow_page(tree.root).unwrap();
+        assert_eq!(
+            Node::new(root.as_slice()).unwrap().node_type(),
+            NodeType::Leaf
+        );
+    }
+
+    #[test]
+    fn corrupt_node_rejected() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        tree.insert(&key(1), b"flakes").unwrap();
+        pager.borrow_page_mut(tree.root).unwrap().as_mut_slice()[NODE_TYPE] = 7;
+        assert!(matches!(tree.seek(&key(1)), Err(Error::Corrupt)));
+        assert!(matches!(tree.insert(&key(2), b"milk"), Err(Error::Corrupt)));
+    }
+
+    #[test]
+    fn removed_space_reused() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..1000u32 {
+            tree.insert(&key(i), &[0; 100]).unwrap();
+        }
+        let num_pages = pager.num_pages();
+        for round in 1..5u32 {
+            for i in 0..1000u32 {
+                assert!(tree.remove(&key((round - 1) * 1000 + i)).unwrap());
+                tree.insert(&key(round * 1000 + i), &[0; 100]).unwrap();
+            }
+        }
+        assert_eq!(scan(&tree).len(), 1000);
+        assert!(pager.num_pages() <= num_pages + 2);
+    }
+
+    #[test]
+    fn destroyed_pages_reused() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..500u32 {
+            tree.insert(&key(i), &[0; 100]).unwrap();
+        }
+        let num_pages = pager.num_pages();
+        tree.destroy().unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        for i in 0..500u32 {
+            tree.insert(&key(i), &[0; 100]).unwrap();
+        }
+        assert_eq!(pager.num_pages(), num_pages);
+    }
+
+    #[test]
+    fn relocated_pages_stay_linked() {
+        let pager = Pager::open(None).unwrap();
+        let first = BTree::create(&pager).unwrap();
+        let second = BTree::create(&pager).unwrap();
+        // interleave the pages of both trees, so that destroying the first leaves gaps
+        for i in 0..500u32 {
+            first.insert(&key(i), &[0; 100]).unwrap();
+            second.insert(&key(i), &key(i).repeat(25)).unwrap();
+        }
+        first.destroy().unwrap();
+        let free = pager.take_free_pages().unwrap();
+        let limit = pager.num_pages() - free.len();
+        let mut destinations: Vec<usize> = free.into_iter().filter(|&page| page < limit).collect();
+        let root = second.relocate(limit, &mut destinations).unwrap();
+        assert!(destinations.is_empty());
+        pager.truncate(limit).unwrap();
+        let tree = BTree::new(&pager, root);
+        let values: Vec<_> = scan(&tree).into_iter().map(|(_, v)| v).collect();
+        let expected: Vec<_> = (0..500u32).map(|i| key(i).repeat(25)).collect();
+        assert_eq!(values, expected);
+        assert_eq!(pager.allocate_page().unwrap(), limit);
+    }
+
+    #[test]
+    fn oversized_cell_rejected() {
+        let pager = Pager::open(None).unwrap();
+        let tree = BTree::create(&pager).unwrap();
+        assert!(tree.insert(&key(1), &[0; PAGE_SIZE]).is_err());
+    }
+}
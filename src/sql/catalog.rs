@@ -0,0 +1,138 @@
+use super::index::Index;
+use super::row::Row;
+use super::schema::{Column, ColumnType, Schema};
+use super::table::TableStats;
+use super::value::Value;
+use super::{Error, Result};
+
+/// Name of the table describing every other table, queryable like any user table
+pub const CATALOG_TABLE: &str = "flakedb_master";
+/// The catalog is always the first tree in the file, straight after the header page, so it can be
+/// found on open
+pub const CATALOG_ROOT: usize = 1;
+
+pub fn schema() -> Schema {
+    Schema::new(vec![
+        Column::new("type", ColumnType::Text),
+        Column::new("name", ColumnType::Text),
+        Column::new("tbl_name", ColumnType::Text),
+        Column::new("rootpage", ColumnType::Integer),
+        Column::new("sql", ColumnType::Text),
+        Column::new("row_count", ColumnType::Integer),
+        Column::new("next_rowid", ColumnType::Integer),
+    ])
+    .unwrap()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EntryType {
+    Table,
+    Index,
+}
+
+impl EntryType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Table => "table",
+            Self::Index => "index",
+        }
+    }
+}
+
+/// Row of the catalog describing a single table or index
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub entry_type: EntryType,
+    pub name: String,
+    /// Table the entry belongs to, which for a table is the table itself
+    pub table_name: String,
+    pub root: usize,
+    /// Statement that recreates the entry's definition when replayed
+    pub sql: String,
+    pub stats: TableStats,
+}
+
+impl Entry {
+    pub fn table(name: &str, root: usize, schema: &Schema, stats: TableStats) -> Self {
+        let columns: Vec<_> = schema.columns.iter().map(Column::to_string).collect();
+        Self {
+            entry_type: EntryType::Table,
+            name: name.into(),
+            table_name: name.into(),
+            root,
+            sql: format!("create table {} ({})", name, columns.join(", ")),
+            stats,
+        }
+    }
+
+    pub fn index(index: &Index) -> Self {
+        Self {
+            entry_type: EntryType::Index,
+            name: index.name.clone(),
+            table_name: index.table.clone(),
+            root: index.root(),
+            sql: index.sql(),
+            stats: TableStats::default(),
+        }
+    }
+
+    pub fn to_row(&self) -> Row {
+        Row::new(vec![
+            Value::Text(self.entry_type.as_str().into()),
+            Value::Text(self.name.clone()),
+            Value::Text(self.table_name.clone()),
+            Value::Integer(self.root as i64),
+            Value::Text(self.sql.clone()),
+            Value::Integer(self.stats.row_count as i64),
+            Value::Integer(self.stats.next_rowid),
+        ])
+    }
+
+    pub fn from_row(row: &Row) -> Result<Self> {
+        let corrupt = || Error::ExecutionError(format!("corrupt catalog entry '{}'", row));
+        match row.values.as_slice() {
+            [Value::Text(entry_type), Value::Text(name), Value::Text(table_name), Value::Integer(root), Value::Text(sql), Value::Integer(row_count), Value::Integer(next_rowid)] => {
+                Ok(Self {
+                    entry_type: match entry_type.as_str() {
+                        "table" => EntryType::Table,
+                        "index" => EntryType::Index,
+                        _ => return Err(corrupt()),
+                    },
+                    name: name.clone(),
+                    table_name: table_name.clone(),
+                    root: usize::try_from(*root).map_err(|_| corrupt())?,
+                    sql: sql.clone(),
+                    stats: TableStats {
+                        row_count: usize::try_from(*row_count).map_err(|_| corrupt())?,
+                        next_rowid: *next_rowid,
+                    },
+                })
+            }
+            _ => Err(corrupt()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer).primary_key(),
+            Column::new("name", ColumnType::Text).max_length(10),
+        ])
+        .unwrap();
+        let stats = TableStats {
+            row_count: 4,
+            next_rowid: 12,
+        };
+        let entry = Entry::table("t", 3, &schema, stats);
+        assert_eq!(
+            entry.sql,
+            "create table t (id integer primary key, name text(10))"
+        );
+        assert_eq!(Entry::from_row(&entry.to_row()).unwrap(), entry);
+    }
+}
@@ -1,25 +1,1069 @@
-use super::row::ValidatedRow;
+use super::catalog::{self, Entry, EntryType, CATALOG_ROOT, CATALOG_TABLE};
+use super::index::{Index, KeyRange};
+use super::pager::{Options, Pager, DEFAULT_CACHE_SIZE};
+use super::row::Row;
+use super::schema::{Column, ColumnType, Schema};
 use super::table::{Results, Table};
-use super::Result;
+use super::value::Value;
+use super::{Error, Result, Statement, Tokens};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 
+/// Name of the table created in every new database, accepting `insert <id> <username> <email>`
+pub const DEFAULT_TABLE: &str = "users";
+/// Prefix of the unique indexes created automatically to enforce primary keys other than an
+/// INTEGER PRIMARY KEY, followed by the name of the table
+const AUTOINDEX_PREFIX: &str = "flakedb_autoindex_";
+
+/// Tables and indexes of a database. Each modification is committed as it is made, unless a
+/// transaction is open, in which case they are all committed or rolled back together.
 pub struct Database {
-    // single fixed schema table for now
-    table: Table,
+    pager: Pager,
+    tables: BTreeMap<String, Table>,
+    indexes: BTreeMap<String, Index>,
+    /// Rowid of each table's and index's entry in the catalog, for updating or removing it
+    catalog_rowids: HashMap<String, i64>,
+    in_transaction: bool,
+    /// Whether a modification is being applied, which any nested in it are part of
+    in_modification: bool,
 }
 
 impl Database {
     pub fn open(path: Option<&PathBuf>) -> Result<Self> {
-        Ok(Self {
-            table: Table::open(path)?,
+        Self::open_with(path, &Options::default())
+    }
+
+    pub fn open_with(path: Option<&PathBuf>, options: &Options) -> Result<Self> {
+        let pager = Pager::with_options(path, options, DEFAULT_CACHE_SIZE)?;
+        let created = pager.created();
+        let mut db = Self {
+            pager,
+            tables: BTreeMap::new(),
+            indexes: BTreeMap::new(),
+            catalog_rowids: HashMap::new(),
+            in_transaction: false,
+            in_modification: false,
+        };
+        if created {
+            db.autocommit(|db| {
+                // another connection may have created the database since it was found empty
+                if db.tables.contains_key(CATALOG_TABLE) {
+                    return Ok(());
+                }
+                let catalog = Table::create(&db.pager, CATALOG_TABLE, catalog::schema())?;
+                assert_eq!(catalog.root(), CATALOG_ROOT);
+                db.tables.insert(CATALOG_TABLE.into(), catalog);
+                db.create_table(DEFAULT_TABLE, default_schema())
+            })?;
+        } else {
+            db.reload()?;
+        }
+        Ok(db)
+    }
+
+    /// Read the tables and indexes from the catalog, replacing those held in memory
+    fn load(&mut self) -> Result<()> {
+        // the catalog is small, so its own stats are simply recounted
+        let catalog =
+            Table::open_and_count(&self.pager, CATALOG_TABLE, catalog::schema(), CATALOG_ROOT)?;
+        let mut tables = BTreeMap::new();
+        let mut index_entries = Vec::new();
+        let mut catalog_rowids = HashMap::new();
+        for row in catalog.select(&self.pager)? {
+            let (rowid, row) = row?;
+            let entry = Entry::from_row(&row)?;
+            catalog_rowids.insert(entry.name.clone(), rowid);
+            match entry.entry_type {
+                EntryType::Table => {
+                    let schema = entry_schema(&entry)?;
+                    let table = Table::open(&entry.name, schema, entry.root, entry.stats);
+                    tables.insert(entry.name, table);
+                }
+                // indexes are opened once all the tables they refer to are known
+                EntryType::Index => index_entries.push(entry),
+            }
+        }
+        let mut indexes = BTreeMap::new();
+        for entry in index_entries {
+            let index = entry_index(&entry, &tables)?;
+            indexes.insert(entry.name, index);
+        }
+        tables.insert(CATALOG_TABLE.into(), catalog);
+        self.tables = tables;
+        self.indexes = indexes;
+        self.catalog_rowids = catalog_rowids;
+        Ok(())
+    }
+
+    /// Read the tables and indexes from the catalog as last committed
+    fn reload(&mut self) -> Result<()> {
+        self.pager.begin_read()?;
+        let result = self.load();
+        self.pager.end_read()?;
+        result
+    }
+
+    /// Run a query against the database as last committed, which other connections cannot modify
+    /// until it is done, unless they are using WAL mode
+    pub fn read<T>(&mut self, query: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        let result = match self.pager.begin_read() {
+            Ok(true) => self.load().and_then(|()| query(self)),
+            Ok(false) => query(self),
+            Err(error) => Err(error),
+        };
+        // a transaction keeps reading until it ends
+        if !self.in_transaction {
+            self.pager.end_read()?;
+        }
+        result
+    }
+
+    /// Write every committed modification into the database file itself, rather than leaving it in
+    /// the write-ahead log. A transaction still in progress is not affected.
+    pub fn flush(&self) -> Result<()> {
+        self.pager.flush()
+    }
+
+    /// Close the database, rolling back a transaction still in progress and reporting any error
+    /// that dropping the database would have to ignore
+    pub fn close(self) -> Result<()> {
+        self.pager.close()
+    }
+
+    /// Whether a transaction was begun and not yet committed or rolled back
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Start a transaction, holding back every modification until it is committed
+    pub fn begin(&mut self) -> Result<()> {
+        if self.in_transaction {
+            return Err(Error::ExecutionError(
+                "cannot start a transaction within a transaction".into(),
+            ));
+        }
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    /// Make every modification of the current transaction durable at once
+    pub fn commit(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            return Err(Error::ExecutionError(
+                "cannot commit - no transaction is active".into(),
+            ));
+        }
+        self.pager.commit()?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    /// Undo every modification of the current transaction
+    pub fn rollback(&mut self) -> Result<()> {
+        if !self.in_transaction {
+            return Err(Error::ExecutionError(
+                "cannot rollback - no transaction is active".into(),
+            ));
+        }
+        self.in_transaction = false;
+        self.discard()
+    }
+
+    /// Return to the state of the last commit, including the tables and indexes held in memory
+    fn discard(&mut self) -> Result<()> {
+        self.pager.rollback()?;
+        self.reload()
+    }
+
+    /// Apply a modification as a whole: if it fails part way, whatever it did is undone. Outside a
+    /// transaction, it is committed if it succeeds; within one, it is left to the transaction,
+    /// whose earlier modifications are kept either way. Fails with [`Error::DatabaseLocked`] if
+    /// another connection is writing.
+    pub fn autocommit<T>(&mut self, modify: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        match self.pager.begin_write() {
+            Ok(true) => self.load()?,
+            Ok(false) => {}
+            Err(error) => {
+                if !self.in_transaction {
+                    self.pager.end_read()?;
+                }
+                return Err(error);
+            }
+        }
+        // nested modifications are part of the one around them
+        if self.in_modification {
+            return modify(self);
+        }
+        self.in_modification = true;
+        if self.in_transaction {
+            self.pager.savepoint();
+        }
+        let result = modify(self);
+        self.in_modification = false;
+        if self.in_transaction {
+            if result.is_ok() {
+                self.pager.release_savepoint();
+            } else {
+                self.pager.rollback_to_savepoint()?;
+                self.load()?;
+            }
+            return result;
+        }
+        match result.and_then(|value| self.pager.commit().map(|()| value)) {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.discard()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Check that no table or index already goes by a name, as they share a namespace
+    fn check_name_free(&self, name: &str) -> Result<()> {
+        let kind = if self.tables.contains_key(name) {
+            "table"
+        } else if self.indexes.contains_key(name) {
+            "index"
+        } else {
+            return Ok(());
+        };
+        Err(Error::ExecutionError(format!(
+            "{} {} already exists",
+            kind, name
+        )))
+    }
+
+    pub fn create_table(&mut self, name: &str, schema: Schema) -> Result<()> {
+        self.autocommit(|db| {
+            db.check_name_free(name)?;
+            let table = Table::create(&db.pager, name, schema)?;
+            let entry = Entry::table(name, table.root(), &table.schema, table.stats());
+            db.add_entry(&entry)?;
+            let primary_key = table
+                .schema
+                .columns
+                .iter()
+                .find(|column| column.primary_key && !column.is_rowid())
+                .map(|column| column.name.clone());
+            db.tables.insert(name.into(), table);
+            // only an INTEGER PRIMARY KEY is unique by virtue of being the key of the table's tree
+            if let Some(column) = primary_key {
+                let index = format!("{}{}", AUTOINDEX_PREFIX, name);
+                db.create_index(&index, name, vec![column], true)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove a table along with its indexes, freeing their pages
+    pub fn drop_table(&mut self, name: &str, if_exists: bool) -> Result<()> {
+        self.autocommit(|db| {
+            if !db.tables.contains_key(name) && if_exists {
+                return Ok(());
+            }
+            db.check_writable(name)?;
+            let indexes: Vec<String> = db.indexes(name).map(|index| index.name.clone()).collect();
+            for index in indexes {
+                db.remove_index(&index)?;
+            }
+            db.remove_entry(name)?;
+            let table = db.tables.remove(name).unwrap();
+            table.destroy(&db.pager)?;
+            Ok(())
+        })
+    }
+
+    /// Create an index over columns of a table, filled with the rows the table already holds
+    pub fn create_index(
+        &mut self,
+        name: &str,
+        table: &str,
+        columns: Vec<String>,
+        unique: bool,
+    ) -> Result<()> {
+        self.autocommit(|db| {
+            db.check_name_free(name)?;
+            db.check_writable(table)?;
+            let index = Index::create(&db.pager, name, &db.tables[table], columns, unique)?;
+            db.add_entry(&Entry::index(&index))?;
+            db.indexes.insert(name.into(), index);
+            Ok(())
+        })
+    }
+
+    pub fn drop_index(&mut self, name: &str, if_exists: bool) -> Result<()> {
+        self.autocommit(|db| {
+            if !db.indexes.contains_key(name) {
+                if if_exists {
+                    return Ok(());
+                }
+                return Err(Error::ExecutionError(format!("no such index: {}", name)));
+            }
+            if name.starts_with(AUTOINDEX_PREFIX) {
+                return Err(Error::ExecutionError(format!(
+                    "index {} enforces a primary key and cannot be dropped",
+                    name
+                )));
+            }
+            db.remove_index(name)?;
+            Ok(())
+        })
+    }
+
+    fn remove_index(&mut self, name: &str) -> Result<()> {
+        self.remove_entry(name)?;
+        let index = self.indexes.remove(name).unwrap();
+        index.destroy(&self.pager)
+    }
+
+    /// Shrink the database to the pages in use: those past the end are moved into the gaps left by
+    /// free pages, and the file is truncated once committed (in WAL mode, once checkpointed)
+    pub fn vacuum(&mut self) -> Result<()> {
+        if self.in_transaction {
+            return Err(Error::ExecutionError(
+                "cannot VACUUM from within a transaction".into(),
+            ));
+        }
+        self.autocommit(|db| {
+            let free = db.pager.take_free_pages()?;
+            let limit = db.pager.num_pages() - free.len();
+            let mut destinations: Vec<usize> =
+                free.into_iter().filter(|&page| page < limit).collect();
+            let mut moved_tables = Vec::new();
+            for (name, table) in &mut db.tables {
+                let root = table.root();
+                table.relocate(&db.pager, limit, &mut destinations)?;
+                if table.root() != root {
+                    moved_tables.push(name.clone());
+                }
+            }
+            let mut moved_indexes = Vec::new();
+            for index in db.indexes.values_mut() {
+                let root = index.root();
+                index.relocate(&db.pager, limit, &mut destinations)?;
+                if index.root() != root {
+                    moved_indexes.push(Entry::index(index));
+                }
+            }
+            debug_assert!(destinations.is_empty(), "pages in use were not found");
+            // the catalog is rewritten in place, so this allocates no pages past the limit
+            for name in &moved_tables {
+                db.save_stats(name)?;
+            }
+            for entry in &moved_indexes {
+                db.replace_entry(entry)?;
+            }
+            if !moved_tables.is_empty() || !moved_indexes.is_empty() {
+                db.pager.bump_schema_cookie()?;
+            }
+            db.pager.truncate(limit)
+        })
+    }
+
+    /// Record a new table or index in the catalog
+    fn add_entry(&mut self, entry: &Entry) -> Result<()> {
+        let catalog = self.tables.get_mut(CATALOG_TABLE).unwrap();
+        let rowid = catalog.insert(&self.pager, entry.to_row())?;
+        self.pager.bump_schema_cookie()?;
+        self.catalog_rowids.insert(entry.name.clone(), rowid);
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, name: &str) -> Result<()> {
+        let rowid = self.catalog_rowids.remove(name).unwrap();
+        let catalog = self.tables.get_mut(CATALOG_TABLE).unwrap();
+        catalog.delete(&self.pager, rowid)?;
+        self.pager.bump_schema_cookie()
+    }
+
+    /// Write a table's current stats back to its catalog entry
+    fn save_stats(&mut self, name: &str) -> Result<()> {
+        let table = &self.tables[name];
+        let entry = Entry::table(name, table.root(), &table.schema, table.stats());
+        self.replace_entry(&entry)
+    }
+
+    /// Overwrite the catalog entry of a table or index, which must have the same size so that it
+    /// is rewritten in place
+    fn replace_entry(&mut self, entry: &Entry) -> Result<()> {
+        let rowid = self.catalog_rowids[&entry.name];
+        let catalog = self.tables.get_mut(CATALOG_TABLE).unwrap();
+        if !catalog.replace(&self.pager, rowid, &entry.to_row())? {
+            return Err(Error::ExecutionError(format!(
+                "missing catalog entry for {}",
+                entry.name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn table(&self, name: &str) -> Result<&Table> {
+        self.tables
+            .get(name)
+            .ok_or_else(|| Error::ExecutionError(format!("no such table: {}", name)))
+    }
+
+    /// Check that a table exists and may be modified, which the catalog may not
+    fn check_writable(&self, name: &str) -> Result<()> {
+        if name == CATALOG_TABLE {
+            return Err(Error::ExecutionError(format!(
+                "table {} may not be modified",
+                name
+            )));
+        }
+        self.table(name).map(|_| ())
+    }
+
+    /// Indexes defined on a table
+    pub fn indexes<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a Index> + 'a {
+        self.indexes
+            .values()
+            .filter(move |index| index.table == table)
+    }
+
+    /// Convert values to the table's schema and insert them as a new row
+    pub fn insert(&mut self, name: &str, values: Vec<Value>) -> Result<()> {
+        self.insert_rows(name, vec![values])
+    }
+
+    /// Convert the values of each row to the table's schema and insert them as new rows, either
+    /// all of them or none
+    pub fn insert_rows(&mut self, name: &str, rows: Vec<Vec<Value>>) -> Result<()> {
+        self.autocommit(|db| {
+            db.check_writable(name)?;
+            let table = db.tables.get_mut(name).unwrap();
+            let mut next_rowid = table.stats().next_rowid;
+            let mut writes = Vec::with_capacity(rows.len());
+            for values in rows {
+                let mut row = Row::new(table.schema.coerce_row(values)?);
+                let rowid = table.assign_rowid(&mut row, next_rowid);
+                next_rowid = next_rowid.max(rowid.saturating_add(1));
+                writes.push(Write {
+                    old: None,
+                    rowid,
+                    row,
+                });
+            }
+            let indexes: Vec<_> = db.indexes.values().filter(|i| i.table == name).collect();
+            // constraints are checked before anything is written, so a failed insert changes
+            // nothing even within a transaction
+            check_writes(&db.pager, table, &indexes, &writes)?;
+            for Write { rowid, row, .. } in writes {
+                for index in &indexes {
+                    index.insert(&db.pager, rowid, &row)?;
+                }
+                table.insert(&db.pager, row)?;
+            }
+            db.save_stats(name)?;
+            Ok(())
         })
     }
 
-    pub fn insert(&mut self, row: &ValidatedRow) -> Result<()> {
-        self.table.insert(row)
+    /// Overwrite existing rows, each given with its current rowid, returning how many existed.
+    /// Either all of them are overwritten or none.
+    pub fn update(&mut self, name: &str, rows: Vec<(i64, Row)>) -> Result<usize> {
+        self.autocommit(|db| {
+            db.check_writable(name)?;
+            let table = db.tables.get_mut(name).unwrap();
+            let mut next_rowid = table.stats().next_rowid;
+            let mut writes = Vec::with_capacity(rows.len());
+            for (rowid, mut row) in rows {
+                let Some(old) = table.get(&db.pager, rowid)? else {
+                    continue;
+                };
+                let new_rowid = match table.schema.rowid_column() {
+                    Some(_) => table.assign_rowid(&mut row, next_rowid),
+                    None => rowid,
+                };
+                next_rowid = next_rowid.max(new_rowid.saturating_add(1));
+                writes.push(Write {
+                    old: Some((rowid, old)),
+                    rowid: new_rowid,
+                    row,
+                });
+            }
+            let indexes: Vec<_> = db.indexes.values().filter(|i| i.table == name).collect();
+            check_writes(&db.pager, table, &indexes, &writes)?;
+            let updated = writes.len();
+            for Write { old, rowid, row } in writes {
+                let (old_rowid, old) = old.unwrap();
+                for index in &indexes {
+                    index.remove(&db.pager, old_rowid, &old)?;
+                    index.insert(&db.pager, rowid, &row)?;
+                }
+                table.update(&db.pager, old_rowid, row)?;
+            }
+            db.save_stats(name)?;
+            Ok(updated)
+        })
+    }
+
+    /// Remove the rows with the given rowids, returning how many existed
+    pub fn delete(&mut self, name: &str, rowids: &[i64]) -> Result<usize> {
+        self.autocommit(|db| {
+            db.check_writable(name)?;
+            let table = db.tables.get_mut(name).unwrap();
+            let indexes: Vec<_> = db.indexes.values().filter(|i| i.table == name).collect();
+            let mut deleted = 0;
+            for &rowid in rowids {
+                let Some(row) = table.get(&db.pager, rowid)? else {
+                    continue;
+                };
+                for index in &indexes {
+                    index.remove(&db.pager, rowid, &row)?;
+                }
+                table.delete(&db.pager, rowid)?;
+                deleted += 1;
+            }
+            db.save_stats(name)?;
+            Ok(deleted)
+        })
+    }
+
+    pub fn select(&self, table: &str) -> Result<Results<'_>> {
+        self.table(table)?.select(&self.pager)
+    }
+
+    /// Rows of a table found through one of its indexes, in index order
+    pub fn index_scan<'a>(
+        &'a self,
+        index: &'a Index,
+        range: &KeyRange,
+    ) -> Result<impl Iterator<Item = Result<(i64, Row)>> + 'a> {
+        let table = self.table(&index.table)?;
+        let rowids = index.scan(&self.pager, range)?;
+        Ok(rowids.map(move |rowid| {
+            let rowid = rowid?;
+            let row = table.get(&self.pager, rowid)?.ok_or_else(|| {
+                Error::ExecutionError(format!("index {} refers to a missing row", index.name))
+            })?;
+            Ok((rowid, row))
+        }))
+    }
+}
+
+/// Row about to be written to a table, either as a new row or over an existing one
+struct Write {
+    /// Rowid and values of the row it overwrites, if any
+    old: Option<(i64, Row)>,
+    rowid: i64,
+    row: Row,
+}
+
+/// Check that writing rows to a table one after the other would not duplicate a rowid or the
+/// values of a unique index, before any of them is written. A row written over an existing one
+/// frees the rowid and values of that row for those written after it.
+fn check_writes(pager: &Pager, table: &Table, indexes: &[&Index], writes: &[Write]) -> Result<()> {
+    let mut overwritten = HashSet::new();
+    let mut rowids = HashSet::new();
+    let mut values = vec![HashSet::new(); indexes.len()];
+    for write in writes {
+        let old_rowid = write.old.as_ref().map(|(rowid, _)| *rowid);
+        if let Some(rowid) = old_rowid {
+            overwritten.insert(rowid);
+        }
+        if old_rowid != Some(write.rowid) && !overwritten.contains(&write.rowid) {
+            table.check_rowid(pager, write.rowid)?;
+        }
+        table.check_pending_rowid(&mut rowids, write.rowid)?;
+        for (index, values) in indexes.iter().zip(&mut values) {
+            if let Some(rowid) = index.duplicate_of(pager, &write.row)? {
+                if !overwritten.contains(&rowid) {
+                    return Err(index.duplicate(&write.row));
+                }
+            }
+            index.check_pending(values, &write.row)?;
+        }
+    }
+    Ok(())
+}
+
+/// Recover a table's schema by parsing the statement recorded in its catalog entry
+fn entry_schema(entry: &Entry) -> Result<Schema> {
+    match Statement::parse(Tokens::from(entry.sql.as_str()))? {
+        Statement::CreateTable { columns, .. } => Schema::new(columns),
+        _ => Err(Error::ExecutionError(format!(
+            "corrupt catalog entry for {}",
+            entry.name
+        ))),
+    }
+}
+
+/// Recover an index by parsing the statement recorded in its catalog entry
+fn entry_index(entry: &Entry, tables: &BTreeMap<String, Table>) -> Result<Index> {
+    let corrupt = || Error::ExecutionError(format!("corrupt catalog entry for {}", entry.name));
+    match Statement::parse(Tokens::from(entry.sql.as_str()))? {
+        Statement::CreateIndex(create) => {
+            let table = tables.get(&create.table).ok_or_else(corrupt)?;
+            Index::open(
+                &entry.name,
+                table,
+                create.columns,
+                create.unique,
+                entry.root,
+            )
+        }
+        _ => Err(corrupt()),
+    }
+}
+
+fn default_schema() -> Schema {
+    Schema::new(vec![
+        Column::new("id", ColumnType::Integer).primary_key(),
+        Column::new("username", ColumnType::Text).max_length(32),
+        Column::new("email", ColumnType::Text).max_length(255),
+    ])
+    .unwrap()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sql::pager::{JournalMode, PAGE_SIZE};
+    use assert_fs::NamedTempFile;
+    use test_case::test_case;
+
+    /// In-memory database whose default table holds a user for each name, with ids counting from 1
+    pub fn with_users(names: &[&str]) -> Database {
+        let mut db = Database::open(None).unwrap();
+        for (id, name) in (1..).zip(names) {
+            let values = vec![
+                Value::Integer(id),
+                Value::Text(name.to_string()),
+                Value::Text(format!("{}@example.com", name)),
+            ];
+            db.insert(DEFAULT_TABLE, values).unwrap();
+        }
+        db
+    }
+
+    fn select_all(db: &Database, table: &str) -> Vec<String> {
+        db.select(table)
+            .unwrap()
+            .map(|row| row.unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn tables_persist() {
+        let file = NamedTempFile::new("tables_persist.flake").unwrap();
+        let path = file.path().to_path_buf();
+        {
+            let mut db = Database::open(Some(&path)).unwrap();
+            let schema = Schema::new(vec![Column::new("name", ColumnType::Text)]).unwrap();
+            db.create_table("names", schema).unwrap();
+            db.insert("names", vec![Value::Text("karl".into())])
+                .unwrap();
+            let values = vec![
+                Value::Integer(1),
+                Value::Text("fri".into()),
+                Value::Text("day".into()),
+            ];
+            db.insert(DEFAULT_TABLE, values).unwrap();
+        }
+        let db = Database::open(Some(&path)).unwrap();
+        assert_eq!(db.table("names").unwrap().stats().row_count, 1);
+        assert_eq!(select_all(&db, "names"), vec!["karl"]);
+        assert_eq!(select_all(&db, DEFAULT_TABLE), vec!["1,fri,day"]);
+        assert_eq!(
+            select_all(&db, CATALOG_TABLE),
+            vec![
+                "table,users,users,2,create table users (id integer primary key, username text(32), email text(255)),1,2",
+                "table,names,names,3,create table names (name text),1,2",
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_rows() {
+        let mut db = Database::open(None).unwrap();
+        for id in 1..=3 {
+            let values = vec![
+                Value::Integer(id),
+                Value::Text(format!("user{}", id)),
+                Value::Text("x@y.z".into()),
+            ];
+            db.insert(DEFAULT_TABLE, values).unwrap();
+        }
+        assert_eq!(db.delete(DEFAULT_TABLE, &[1, 3, 4]).unwrap(), 2);
+        assert_eq!(db.table(DEFAULT_TABLE).unwrap().stats().row_count, 1);
+        assert_eq!(select_all(&db, DEFAULT_TABLE), vec!["2,user2,x@y.z"]);
+        assert!(db.delete(CATALOG_TABLE, &[1]).is_err());
+    }
+
+    #[test]
+    fn update_moves_rowid() {
+        let mut db = Database::open(None).unwrap();
+        for id in [1, 5] {
+            let values = vec![
+                Value::Integer(id),
+                Value::Text("karl".into()),
+                Value::Text("x@y.z".into()),
+            ];
+            db.insert(DEFAULT_TABLE, values).unwrap();
+        }
+        let row = |id| {
+            Row::new(vec![
+                Value::Integer(id),
+                Value::Text("fri".into()),
+                Value::Text("x@y.z".into()),
+            ])
+        };
+        assert_eq!(
+            db.update(DEFAULT_TABLE, vec![(1, row(1)), (5, row(9))])
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            select_all(&db, DEFAULT_TABLE),
+            vec!["1,fri,x@y.z", "9,fri,x@y.z"]
+        );
+        let stats = db.table(DEFAULT_TABLE).unwrap().stats();
+        assert_eq!((stats.row_count, stats.next_rowid), (2, 10));
+    }
+
+    fn user(id: i64, name: &str) -> Vec<Value> {
+        vec![
+            Value::Integer(id),
+            Value::Text(name.into()),
+            Value::Text(format!("{}@y.z", name)),
+        ]
+    }
+
+    fn find_by_name(db: &Database, name: &str) -> Vec<i64> {
+        let index = db.indexes(DEFAULT_TABLE).next().unwrap();
+        let range = KeyRange {
+            equal: vec![Value::Text(name.into())],
+            lower: std::ops::Bound::Unbounded,
+            upper: std::ops::Bound::Unbounded,
+        };
+        db.index_scan(index, &range)
+            .unwrap()
+            .map(|row| row.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn indexes_persist_and_stay_current() {
+        let file = NamedTempFile::new("indexes_persist.flake").unwrap();
+        let path = file.path().to_path_buf();
+        {
+            let mut db = Database::open(Some(&path)).unwrap();
+            db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+            db.create_index("by_name", DEFAULT_TABLE, vec!["username".into()], true)
+                .unwrap();
+            db.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+        }
+        let mut db = Database::open(Some(&path)).unwrap();
+        assert_eq!(
+            select_all(&db, CATALOG_TABLE)[1],
+            "index,by_name,users,3,create unique index by_name on users (username),0,1"
+        );
+        assert_eq!(find_by_name(&db, "fri"), vec![2]);
+        assert_eq!(
+            db.insert(DEFAULT_TABLE, user(3, "karl"))
+                .unwrap_err()
+                .to_string(),
+            "constraint violation: duplicate key karl for username in table users"
+        );
+        assert_eq!(db.table(DEFAULT_TABLE).unwrap().stats().row_count, 2);
+
+        let renamed = Row::new(user(7, "day"));
+        assert_eq!(db.update(DEFAULT_TABLE, vec![(1, renamed)]).unwrap(), 1);
+        assert_eq!(find_by_name(&db, "karl"), Vec::<i64>::new());
+        assert_eq!(find_by_name(&db, "day"), vec![7]);
+        let clash = Row::new(user(2, "day"));
+        assert!(db.update(DEFAULT_TABLE, vec![(2, clash)]).is_err());
+        assert_eq!(find_by_name(&db, "fri"), vec![2]);
+
+        assert_eq!(db.delete(DEFAULT_TABLE, &[2]).unwrap(), 1);
+        assert_eq!(find_by_name(&db, "fri"), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn writes_checked_before_any_is_made() {
+        let mut db = Database::open(None).unwrap();
+        db.insert_rows(
+            DEFAULT_TABLE,
+            vec![user(1, "karl"), user(2, "fri"), user(3, "day")],
+        )
+        .unwrap();
+        db.create_index("by_name", DEFAULT_TABLE, vec!["username".into()], true)
+            .unwrap();
+        let row = |id, name| Row::new(user(id, name));
+        let unchanged = select_all(&db, DEFAULT_TABLE);
+        // rows clash with each other rather than with those stored
+        assert!(db
+            .update(DEFAULT_TABLE, vec![(1, row(1, "x")), (2, row(2, "x"))])
+            .is_err());
+        assert!(db
+            .insert_rows(
+                DEFAULT_TABLE,
+                vec![user(5, "a"), user(6, "b"), user(5, "c")]
+            )
+            .is_err());
+        // a row clashes with one stored, after others were changed
+        assert!(db
+            .update(DEFAULT_TABLE, vec![(1, row(4, "x")), (2, row(3, "fri"))])
+            .is_err());
+        assert_eq!(select_all(&db, DEFAULT_TABLE), unchanged);
+        assert_eq!(find_by_name(&db, "x"), Vec::<i64>::new());
+
+        // rowids and values are free once the rows holding them were overwritten
+        let moved = vec![(3, row(4, "day")), (2, row(3, "fri"))];
+        assert_eq!(db.update(DEFAULT_TABLE, moved).unwrap(), 2);
+        assert_eq!(
+            select_all(&db, DEFAULT_TABLE),
+            vec!["1,karl,karl@y.z", "3,fri,fri@y.z", "4,day,day@y.z"]
+        );
+        assert_eq!(find_by_name(&db, "fri"), vec![3]);
+        let unnumbered = |name: &str| {
+            let mut values = user(0, name);
+            values[0] = Value::Null;
+            values
+        };
+        db.insert_rows(DEFAULT_TABLE, vec![unnumbered("a"), unnumbered("b")])
+            .unwrap();
+        assert_eq!(find_by_name(&db, "b"), vec![6]);
+    }
+
+    #[test]
+    fn drop_frees_pages() {
+        let mut db = Database::open(None).unwrap();
+        for id in 1..=200 {
+            db.insert(DEFAULT_TABLE, user(id, &format!("user{}", id)))
+                .unwrap();
+        }
+        db.create_index("by_name", DEFAULT_TABLE, vec!["username".into()], false)
+            .unwrap();
+        assert!(db
+            .create_index("by_name", DEFAULT_TABLE, vec!["email".into()], false)
+            .is_err());
+        assert!(db
+            .create_index("by_nope", DEFAULT_TABLE, vec!["nope".into()], false)
+            .is_err());
+        let num_pages = db.pager.num_pages();
+        db.drop_table(DEFAULT_TABLE, false).unwrap();
+        assert!(db.table(DEFAULT_TABLE).is_err());
+        assert!(db.drop_index("by_name", false).is_err());
+        db.drop_index("by_name", true).unwrap();
+        assert_eq!(select_all(&db, CATALOG_TABLE).len(), 0);
+        db.create_table(DEFAULT_TABLE, default_schema()).unwrap();
+        for id in 1..=200 {
+            db.insert(DEFAULT_TABLE, user(id, &format!("user{}", id)))
+                .unwrap();
+        }
+        assert!(db.pager.num_pages() <= num_pages);
+    }
+
+    #[test]
+    fn primary_keys_unique() {
+        let mut db = Database::open(None).unwrap();
+        db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+        db.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+        let error = db.insert(DEFAULT_TABLE, user(1, "day")).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::ConstraintViolation { ref table, ref column, ref key }
+                if table == "users" && column == "id" && key == "1"
+        ));
+        assert!(db
+            .update(DEFAULT_TABLE, vec![(2, Row::new(user(1, "fri")))])
+            .is_err());
+        assert_eq!(
+            select_all(&db, DEFAULT_TABLE),
+            vec!["1,karl,karl@y.z", "2,fri,fri@y.z"]
+        );
+
+        let schema = Schema::new(vec![
+            Column::new("code", ColumnType::Text).primary_key(),
+            Column::new("count", ColumnType::Integer),
+        ])
+        .unwrap();
+        db.create_table("codes", schema).unwrap();
+        let code = |code: &str| vec![Value::Text(code.into()), Value::Integer(1)];
+        db.insert("codes", code("x")).unwrap();
+        assert_eq!(
+            db.insert("codes", code("x")).unwrap_err().to_string(),
+            "constraint violation: duplicate key x for code in table codes"
+        );
+        assert!(db.drop_index("flakedb_autoindex_codes", false).is_err());
+        db.drop_table("codes", false).unwrap();
+        assert_eq!(db.indexes("codes").count(), 0);
+    }
+
+    #[test]
+    fn catalog_is_read_only() {
+        let mut db = Database::open(None).unwrap();
+        let values = vec![
+            Value::Text("table".into()),
+            Value::Text("t".into()),
+            Value::Text("t".into()),
+            Value::Integer(9),
+            Value::Text("".into()),
+        ];
+        assert!(db.insert(CATALOG_TABLE, values).is_err());
+    }
+
+    #[test]
+    fn rollback_discards_changes() {
+        let mut db = Database::open(None).unwrap();
+        db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+        db.begin().unwrap();
+        assert!(db.begin().is_err());
+        // enough rows to spill modified pages out of the cache
+        for id in 2..=2000 {
+            db.insert(DEFAULT_TABLE, user(id, &format!("user{}", id)))
+                .unwrap();
+        }
+        db.create_index("by_name", DEFAULT_TABLE, vec!["username".into()], true)
+            .unwrap();
+        db.delete(DEFAULT_TABLE, &[1]).unwrap();
+        db.rollback().unwrap();
+        assert!(!db.in_transaction());
+        assert!(db.rollback().is_err());
+        assert_eq!(select_all(&db, DEFAULT_TABLE), vec!["1,karl,karl@y.z"]);
+        assert_eq!(db.table(DEFAULT_TABLE).unwrap().stats().row_count, 1);
+        assert_eq!(db.indexes(DEFAULT_TABLE).count(), 0);
+        db.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+        assert_eq!(select_all(&db, DEFAULT_TABLE).len(), 2);
+    }
+
+    #[test]
+    fn failed_modification_undone() {
+        let mut db = Database::open(None).unwrap();
+        db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+        let result = db.autocommit(|db| {
+            db.insert(DEFAULT_TABLE, user(2, "fri"))?;
+            db.insert(DEFAULT_TABLE, user(1, "day"))
+        });
+        assert!(result.is_err());
+        assert_eq!(select_all(&db, DEFAULT_TABLE), vec!["1,karl,karl@y.z"]);
+    }
+
+    #[test_case(JournalMode::Wal ; "wal")]
+    #[test_case(JournalMode::Rollback ; "rollback journal")]
+    fn transactions_commit_atomically(journal_mode: JournalMode) {
+        let file = NamedTempFile::new("transactions_commit.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = Options {
+            journal_mode,
+            ..Options::default()
+        };
+        {
+            let mut db = Database::open_with(Some(&path), &options).unwrap();
+            db.begin().unwrap();
+            db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+            db.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+            db.commit().unwrap();
+            assert!(db.commit().is_err());
+            db.begin().unwrap();
+            db.insert(DEFAULT_TABLE, user(3, "day")).unwrap();
+            // closed with the transaction still open
+        }
+        let db = Database::open_with(Some(&path), &options).unwrap();
+        assert_eq!(
+            select_all(&db, DEFAULT_TABLE),
+            vec!["1,karl,karl@y.z", "2,fri,fri@y.z"]
+        );
+        assert_eq!(db.table(DEFAULT_TABLE).unwrap().stats().row_count, 2);
+    }
+
+    #[test_case(true ; "synced")]
+    #[test_case(false ; "unsynced")]
+    fn flush_and_close(sync: bool) {
+        let file = NamedTempFile::new("flush_and_close.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let mut wal_path = path.as_os_str().to_owned();
+        wal_path.push("-wal");
+        let options = Options {
+            sync,
+            ..Options::default()
+        };
+        let mut db = Database::open_with(Some(&path), &options).unwrap();
+        db.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+        let len = || std::fs::metadata(&path).unwrap().len();
+        let before = len();
+        db.flush().unwrap();
+        assert!(len() > before);
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+        db.begin().unwrap();
+        db.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+        db.close().unwrap();
+        assert!(!std::path::Path::new(&wal_path).exists());
+        let db = Database::open_with(Some(&path), &options).unwrap();
+        assert_eq!(select_all(&db, DEFAULT_TABLE), vec!["1,karl,karl@y.z"]);
+    }
+
+    #[test_case(JournalMode::Wal ; "wal")]
+    #[test_case(JournalMode::Rollback ; "rollback journal")]
+    fn connections_share_database(journal_mode: JournalMode) {
+        let file = NamedTempFile::new("connections_share_database.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = Options {
+            journal_mode,
+            ..Options::default()
+        };
+        let mut a = Database::open_with(Some(&path), &options).unwrap();
+        let mut b = Database::open_with(Some(&path), &options).unwrap();
+        a.insert(DEFAULT_TABLE, user(1, "karl")).unwrap();
+        let schema = Schema::new(vec![Column::new("code", ColumnType::Text)]).unwrap();
+        a.create_table("codes", schema).unwrap();
+        let rows = b.read(|db| Ok(select_all(db, DEFAULT_TABLE))).unwrap();
+        assert_eq!(rows, vec!["1,karl,karl@y.z"]);
+        assert!(b.table("codes").is_ok());
+
+        b.begin().unwrap();
+        b.insert(DEFAULT_TABLE, user(2, "fri")).unwrap();
+        assert!(matches!(
+            a.insert(DEFAULT_TABLE, user(3, "day")),
+            Err(Error::DatabaseLocked)
+        ));
+        b.commit().unwrap();
+        a.insert(DEFAULT_TABLE, user(3, "day")).unwrap();
+        let rows = b.read(|db| Ok(select_all(db, DEFAULT_TABLE))).unwrap();
+        assert_eq!(rows.len(), 3);
     }
 
-    pub fn select(&mut self) -> Result<Results> {
-        self.table.select()
+    #[test_case(JournalMode::Wal ; "wal")]
+    #[test_case(JournalMode::Rollback ; "rollback journal")]
+    fn vacuum_truncates_file(journal_mode: JournalMode) {
+        let file = NamedTempFile::new("vacuum_truncates_file.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = Options {
+            journal_mode,
+            ..Options::default()
+        };
+        let len = || std::fs::metadata(&path).unwrap().len() as usize;
+        {
+            let mut db = Database::open_with(Some(&path), &options).unwrap();
+            db.create_table("scratch", default_schema()).unwrap();
+            db.create_index("by_name", DEFAULT_TABLE, vec!["username".into()], false)
+                .unwrap();
+            // interleave the pages of both tables, so that dropping one leaves gaps throughout
+            db.begin().unwrap();
+            for id in 1..=1000 {
+                let name = format!("user{}", id);
+                db.insert(DEFAULT_TABLE, user(id, &name)).unwrap();
+                db.insert("scratch", user(id, &name)).unwrap();
+            }
+            db.commit().unwrap();
+            db.drop_table("scratch", false).unwrap();
+            db.flush().unwrap();
+            let before = len();
+            assert!(db.pager.header().free_count > 0);
+            assert!(db.vacuum().is_ok());
+            db.flush().unwrap();
+            assert_eq!(db.pager.header().free_count, 0);
+            assert_eq!(len(), db.pager.num_pages() * PAGE_SIZE);
+            assert!(len() < before);
+            db.begin().unwrap();
+            assert!(db.vacuum().is_err());
+            db.rollback().unwrap();
+        }
+        let mut db = Database::open_with(Some(&path), &options).unwrap();
+        assert_eq!(select_all(&db, DEFAULT_TABLE).len(), 1000);
+        assert_eq!(find_by_name(&db, "user500"), vec![500]);
+        db.insert(DEFAULT_TABLE, user(1001, "karl")).unwrap();
     }
 }
@@ -0,0 +1,381 @@
+use super::aggregate;
+use super::ast::{BinaryOperator, Expr, UnaryOperator};
+use super::functions;
+use super::schema::Schema;
+use super::value::Value;
+use super::{Error, Result};
+use std::cmp::Ordering;
+
+/// Column visible to expressions, optionally qualified by the table (or alias) providing it.
+/// Columns without a table hold values computed by the query, such as aggregates.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScopeColumn {
+    pub table: Option<String>,
+    pub name: String,
+}
+
+/// Columns of the rows an expression is evaluated against, in the order their values appear
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    pub columns: Vec<ScopeColumn>,
+}
+
+impl Scope {
+    /// Scope of rows read from a table, referred to by its name or alias
+    pub fn table(name: &str, schema: &Schema) -> Self {
+        Self {
+            columns: schema
+                .columns
+                .iter()
+                .map(|column| ScopeColumn {
+                    table: Some(name.into()),
+                    name: column.name.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Position of the value of a column reference within each row
+    pub fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize> {
+        let mut matches = self.columns.iter().enumerate().filter(|(_, column)| {
+            column.name.eq_ignore_ascii_case(name)
+                && table.is_none_or(|table| column.table.as_deref() == Some(table))
+        });
+        let describe = || match table {
+            Some(table) => format!("{}.{}", table, name),
+            None => name.into(),
+        };
+        match (matches.next(), matches.next()) {
+            (Some((i, _)), None) => Ok(i),
+            (Some(_), Some(_)) => Err(Error::ExecutionError(format!(
+                "ambiguous column name: {}",
+                describe()
+            ))),
+            (None, _) => Err(Error::ExecutionError(format!(
+                "no such column: {}",
+                describe()
+            ))),
+        }
+    }
+}
+
+/// Evaluate an expression against a row laid out as described by the scope
+pub fn evaluate(expr: &Expr, scope: &Scope, row: &[Value]) -> Result<Value> {
+    let eval = |expr: &Expr| evaluate(expr, scope, row);
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Column { table, name } => Ok(row[scope.resolve(table.as_deref(), name)?].clone()),
+        Expr::Unary { operator, operand } => unary(*operator, eval(operand)?),
+        Expr::Binary {
+            left,
+            operator: BinaryOperator::And,
+            right,
+        } => {
+            // short circuit, as the right side cannot change a false result
+            let left = eval(left)?.truth();
+            if left == Some(false) {
+                return Ok(Value::Boolean(false));
+            }
+            Ok(truth_value(and(left, eval(right)?.truth())))
+        }
+        Expr::Binary {
+            left,
+            operator: BinaryOperator::Or,
+            right,
+        } => {
+            let left = eval(left)?.truth();
+            if left == Some(true) {
+                return Ok(Value::Boolean(true));
+            }
+            Ok(truth_value(or(left, eval(right)?.truth())))
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => binary(eval(left)?, *operator, eval(right)?),
+        Expr::IsNull { operand, negated } => {
+            Ok(Value::Boolean((eval(operand)? == Value::Null) != *negated))
+        }
+        Expr::InList {
+            operand,
+            list,
+            negated,
+        } => {
+            let operand = eval(operand)?;
+            // false unless a match is found, unknown if any comparison was unknown
+            let mut found = Some(false);
+            for item in list {
+                found = or(found, equals(&operand, &eval(item)?));
+                if found == Some(true) {
+                    break;
+                }
+            }
+            Ok(negate(found, *negated))
+        }
+        Expr::Between {
+            operand,
+            low,
+            high,
+            negated,
+        } => {
+            let operand = eval(operand)?;
+            let above = compare(&operand, &eval(low)?).map(Ordering::is_ge);
+            let below = compare(&operand, &eval(high)?).map(Ordering::is_le);
+            Ok(negate(and(above, below), *negated))
+        }
+        Expr::Like {
+            operand,
+            pattern,
+            negated,
+        } => {
+            let matched = match (eval(operand)?, eval(pattern)?) {
+                (Value::Null, _) | (_, Value::Null) => None,
+                (operand, pattern) => Some(like(&pattern.to_string(), &operand.to_string())),
+            };
+            Ok(negate(matched, *negated))
+        }
+        // aggregates are computed over groups of rows beforehand, see `Aggregation`
+        Expr::Function { name, .. } if aggregate::is_aggregate(name) => Err(Error::ExecutionError(
+            format!("misuse of aggregate function {}()", name),
+        )),
+        Expr::Function {
+            name,
+            args,
+            distinct: false,
+            wildcard: false,
+        } => functions::call(name, args.iter().map(eval).collect::<Result<_>>()?),
+        Expr::Function { name, .. } => Err(Error::ExecutionError(format!(
+            "misuse of function {}()",
+            name
+        ))),
+    }
+}
+
+/// Whether a row satisfies a condition, with an unknown result counting as false
+pub fn matches(condition: &Expr, scope: &Scope, row: &[Value]) -> Result<bool> {
+    Ok(evaluate(condition, scope, row)?.truth() == Some(true))
+}
+
+fn truth_value(truth: Option<bool>) -> Value {
+    truth.map_or(Value::Null, Value::Boolean)
+}
+
+fn and(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn or(left: Option<bool>, right: Option<bool>) -> Option<bool> {
+    match (left, right) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+/// Result of a predicate with an optional `not`, keeping unknown results unknown
+fn negate(truth: Option<bool>, negated: bool) -> Value {
+    truth_value(truth.map(|truth| truth != negated))
+}
+
+/// Ordering of two values for comparison operators, unknown when either is NULL
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (left, right) => Some(left.compare(right)),
+    }
+}
+
+fn equals(left: &Value, right: &Value) -> Option<bool> {
+    compare(left, right).map(Ordering::is_eq)
+}
+
+fn unary(operator: UnaryOperator, operand: Value) -> Result<Value> {
+    match (operator, operand) {
+        (_, Value::Null) => Ok(Value::Null),
+        (UnaryOperator::Not, operand) => Ok(truth_value(operand.truth().map(|truth| !truth))),
+        (UnaryOperator::Negate, Value::Integer(i)) => i
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| Error::ExecutionError("integer overflow".into())),
+        (UnaryOperator::Negate, Value::Real(r)) => Ok(Value::Real(-r)),
+        (UnaryOperator::Plus, operand @ (Value::Integer(_) | Value::Real(_))) => Ok(operand),
+        (operator, operand) => Err(Error::ExecutionError(format!(
+            "cannot apply '{}' to {}",
+            operator,
+            operand.type_name()
+        ))),
+    }
+}
+
+fn binary(left: Value, operator: BinaryOperator, right: Value) -> Result<Value> {
+    let ordering = |accept: fn(Ordering) -> bool| truth_value(compare(&left, &right).map(accept));
+    match operator {
+        BinaryOperator::Equal => return Ok(ordering(Ordering::is_eq)),
+        BinaryOperator::NotEqual => return Ok(ordering(Ordering::is_ne)),
+        BinaryOperator::Less => return Ok(ordering(Ordering::is_lt)),
+        BinaryOperator::LessEqual => return Ok(ordering(Ordering::is_le)),
+        BinaryOperator::Greater => return Ok(ordering(Ordering::is_gt)),
+        BinaryOperator::GreaterEqual => return Ok(ordering(Ordering::is_ge)),
+        _ => (),
+    }
+    if left == Value::Null || right == Value::Null {
+        return Ok(Value::Null);
+    }
+    if operator == BinaryOperator::Concat {
+        return Ok(Value::Text(format!("{}{}", left, right)));
+    }
+    arithmetic(left, operator, right)
+}
+
+/// Sum of two non-NULL numbers, as computed by `+`
+pub fn add(left: Value, right: Value) -> Result<Value> {
+    arithmetic(left, BinaryOperator::Add, right)
+}
+
+fn arithmetic(left: Value, operator: BinaryOperator, right: Value) -> Result<Value> {
+    let overflow = || Error::ExecutionError("integer overflow".into());
+    let integer = |value: &Value| match value {
+        Value::Integer(i) => Some(*i),
+        Value::Boolean(b) => Some(*b as i64),
+        _ => None,
+    };
+    if let (Some(a), Some(b)) = (integer(&left), integer(&right)) {
+        let result = match operator {
+            BinaryOperator::Add => a.checked_add(b).ok_or_else(overflow)?,
+            BinaryOperator::Subtract => a.checked_sub(b).ok_or_else(overflow)?,
+            BinaryOperator::Multiply => a.checked_mul(b).ok_or_else(overflow)?,
+            // division by zero gives NULL rather than an error, as in SQLite
+            BinaryOperator::Divide | BinaryOperator::Modulo if b == 0 => return Ok(Value::Null),
+            BinaryOperator::Divide => a.checked_div(b).ok_or_else(overflow)?,
+            BinaryOperator::Modulo => a.checked_rem(b).ok_or_else(overflow)?,
+            _ => unreachable!("not an arithmetic operator: {:?}", operator),
+        };
+        return Ok(Value::Integer(result));
+    }
+    match (left.as_number(), right.as_number()) {
+        (Some(a), Some(b)) => Ok(match operator {
+            BinaryOperator::Add => Value::Real(a + b),
+            BinaryOperator::Subtract => Value::Real(a - b),
+            BinaryOperator::Multiply => Value::Real(a * b),
+            BinaryOperator::Divide | BinaryOperator::Modulo if b == 0.0 => Value::Null,
+            BinaryOperator::Divide => Value::Real(a / b),
+            BinaryOperator::Modulo => Value::Real(a % b),
+            _ => unreachable!("not an arithmetic operator: {:?}", operator),
+        }),
+        _ => Err(Error::ExecutionError(format!(
+            "cannot apply '{}' to {} and {}",
+            operator,
+            left.type_name(),
+            right.type_name()
+        ))),
+    }
+}
+
+/// Match text against a LIKE pattern, where `%` matches any run of characters and `_` any single
+/// character, ignoring ASCII case.
+///
+/// Runs in linear space and quadratic time at worst: on a mismatch, only the last `%` seen needs to
+/// absorb one more character, since any earlier one could be replaced by it.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position in the pattern after the last `%`, and in the text where it stopped matching
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == '_' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match &mut backtrack {
+                Some((after, start)) => {
+                    *start += 1;
+                    (p, t) = (*after, *start);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+    use crate::sql::schema::{Column, ColumnType};
+    use crate::tokens::Tokens;
+    use test_case::test_case;
+
+    fn eval(raw: &str) -> Value {
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer),
+            Column::new("name", ColumnType::Text),
+            Column::new("missing", ColumnType::Real),
+        ])
+        .unwrap();
+        let row = [Value::Integer(3), Value::Text("Karl".into()), Value::Null];
+        let expr = Parser::new(Tokens::from(raw)).parse_expr().unwrap();
+        evaluate(&expr, &Scope::table("t", &schema), &row).unwrap()
+    }
+
+    #[test_case("id = 3" => Value::Boolean(true) ; "equal")]
+    #[test_case("t.id <> 3.0" => Value::Boolean(false) ; "qualified not equal")]
+    #[test_case("id * 2 + 1" => Value::Integer(7) ; "integer arithmetic")]
+    #[test_case("id / 2.0" => Value::Real(1.5) ; "real division")]
+    #[test_case("id / 0" => Value::Null ; "division by zero")]
+    #[test_case("-id % 2" => Value::Integer(-1) ; "modulo")]
+    #[test_case("name || '!'" => Value::Text("Karl!".into()) ; "concat")]
+    #[test_case("missing = 1" => Value::Null ; "null comparison")]
+    #[test_case("missing = 1 and id = 4" => Value::Boolean(false) ; "null and false")]
+    #[test_case("missing = 1 and id = 3" => Value::Null ; "null and true")]
+    #[test_case("missing = 1 or id = 3" => Value::Boolean(true) ; "null or true")]
+    #[test_case("not missing = 1" => Value::Null ; "not null")]
+    #[test_case("missing is null and id is not null" => Value::Boolean(true) ; "is null")]
+    #[test_case("name like 'k_r%'" => Value::Boolean(true) ; "like pattern")]
+    #[test_case("name not like '%z%'" => Value::Boolean(true) ; "not like")]
+    #[test_case("'é' like '_'" => Value::Boolean(true) ; "like multibyte character")]
+    #[test_case("'%' like '%%%'" => Value::Boolean(true) ; "like only wildcards")]
+    #[test_case("'abcabd' like 'a%b_'" => Value::Boolean(true) ; "like backtracking")]
+    #[test_case("name like 'k%a'" => Value::Boolean(false) ; "like unmatched suffix")]
+    #[test_case("id in (1, 2, 3)" => Value::Boolean(true) ; "in list")]
+    #[test_case("id in (1, null)" => Value::Null ; "in with null")]
+    #[test_case("id not in (1, 2)" => Value::Boolean(true) ; "not in")]
+    #[test_case("id between 1 and 3" => Value::Boolean(true) ; "between range")]
+    #[test_case("id not between 4 and missing" => Value::Boolean(true) ; "not between with null")]
+    fn evaluate_expr(raw: &str) -> Value {
+        eval(raw)
+    }
+
+    #[test]
+    fn like_without_exponential_backtracking() {
+        let text = "a".repeat(2000);
+        assert!(!like("%a%a%a%a%a%a%b", &text));
+        assert!(like("%a%a%a%a%a%a%", &text));
+    }
+
+    #[test_case("nope = 1" ; "unknown column")]
+    #[test_case("u.id = 1" ; "unknown table")]
+    #[test_case("name + 1" ; "text arithmetic")]
+    #[test_case("9223372036854775807 + id" ; "overflow")]
+    fn evaluate_invalid(raw: &str) {
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer),
+            Column::new("name", ColumnType::Text),
+        ])
+        .unwrap();
+        let row = [Value::Integer(3), Value::Text("Karl".into())];
+        let expr = Parser::new(Tokens::from(raw)).parse_expr().unwrap();
+        assert!(evaluate(&expr, &Scope::table("t", &schema), &row).is_err());
+    }
+}
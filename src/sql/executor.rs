@@ -0,0 +1,295 @@
+use super::aggregate::Aggregation;
+use super::ast::{Expr, Select};
+use super::db::Database;
+use super::eval::{self, Scope};
+use super::index::{Index, KeyRange};
+use super::join;
+use super::planner::Rows;
+use super::projection::Projection;
+use super::sort::{Sort, SortTerm};
+use super::table::Results;
+use super::value::Value;
+use super::{Error, Result};
+use std::vec;
+
+/// Node of a query plan, producing its rows one at a time as the node above pulls them.
+///
+/// The values of each row are laid out as described by the scope the operator was planned with.
+pub trait Operator {
+    /// Produce the next row, or None once there are no more
+    fn next(&mut self) -> Result<Option<Vec<Value>>>;
+}
+
+/// Operator owned by the node above it in a query plan
+pub type Node<'a> = Box<dyn Operator + 'a>;
+
+/// Plan a query as a tree of operators, returning it along with the names of the columns of its
+/// results
+pub fn plan<'a>(db: &'a Database, select: &'a Select) -> Result<(Vec<String>, Node<'a>)> {
+    // without a from clause the select list is evaluated once, against an empty row
+    let (mut scope, mut node): (_, Node) = match &select.from {
+        Some(table) => join::plan(db, table, &select.joins, select.filter.as_ref())?,
+        None => (Scope::default(), Box::new(Values::new(vec![Vec::new()]))),
+    };
+    if let Some(filter) = &select.filter {
+        node = Box::new(Filter::new(node, filter.clone(), scope.clone()));
+    }
+    let aggregation = Aggregation::plan(select, &scope)?;
+    let select = match &aggregation {
+        Some(aggregation) => {
+            node = Box::new(aggregation.operator(node, scope));
+            scope = aggregation.scope.clone();
+            if let Some(having) = &aggregation.select.having {
+                node = Box::new(Filter::new(node, having.clone(), scope.clone()));
+            }
+            &aggregation.select
+        }
+        None => select,
+    };
+    // like in SQLite, a negative limit means no limit and a negative offset is ignored
+    let limit = select
+        .limit
+        .as_ref()
+        .map(|expr| evaluate_count(expr, "LIMIT"))
+        .transpose()?
+        .and_then(|limit| usize::try_from(limit).ok());
+    let skip = select
+        .offset
+        .as_ref()
+        .map(|expr| evaluate_count(expr, "OFFSET"))
+        .transpose()?
+        .map_or(0, |offset| offset.max(0) as usize);
+    let mut projection = Projection::new(&select.projection, &scope)?;
+    let names = projection.names.clone();
+    let terms = SortTerm::resolve(&select.order_by, &mut projection)?;
+    node = Box::new(Project::new(node, projection, scope));
+    if !terms.is_empty() {
+        // only the first rows need sorting when the rest are cut off
+        let count = limit.map(|limit| limit.saturating_add(skip));
+        node = Box::new(Sort::new(node, terms, names.len(), count));
+    }
+    if skip > 0 || limit.is_some() {
+        node = Box::new(Limit::new(node, skip, limit));
+    }
+    Ok((names, node))
+}
+
+/// Evaluate the expression of a LIMIT or OFFSET clause, which must be a constant integer
+fn evaluate_count(expr: &Expr, clause: &str) -> Result<i64> {
+    match eval::evaluate(expr, &Scope::default(), &[])? {
+        Value::Integer(count) => Ok(count),
+        _ => Err(Error::ExecutionError(format!(
+            "{} must be an integer",
+            clause
+        ))),
+    }
+}
+
+/// Rows given up front
+pub struct Values {
+    rows: vec::IntoIter<Vec<Value>>,
+}
+
+impl Values {
+    pub fn new(rows: Vec<Vec<Value>>) -> Self {
+        Self {
+            rows: rows.into_iter(),
+        }
+    }
+}
+
+impl Operator for Values {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        Ok(self.rows.next())
+    }
+}
+
+/// Every row of a table, in rowid order
+pub struct Scan<'a> {
+    rows: Results<'a>,
+}
+
+impl<'a> Scan<'a> {
+    pub fn new(db: &'a Database, table: &str) -> Result<Self> {
+        Ok(Self {
+            rows: db.select(table)?,
+        })
+    }
+}
+
+impl Operator for Scan<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        Ok(self.rows.next().transpose()?.map(|(_, row)| row.values))
+    }
+}
+
+/// Rows of a table within a range of one of its indexes, in index order
+pub struct IndexScan<'a> {
+    rows: Rows<'a>,
+}
+
+impl<'a> IndexScan<'a> {
+    pub fn new(db: &'a Database, index: &'a Index, range: &KeyRange) -> Result<Self> {
+        Ok(Self {
+            rows: Box::new(db.index_scan(index, range)?),
+        })
+    }
+}
+
+impl Operator for IndexScan<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        Ok(self.rows.next().transpose()?.map(|(_, row)| row.values))
+    }
+}
+
+/// Rows of its input that satisfy a condition
+pub struct Filter<'a> {
+    input: Node<'a>,
+    condition: Expr,
+    scope: Scope,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(input: Node<'a>, condition: Expr, scope: Scope) -> Self {
+        Self {
+            input,
+            condition,
+            scope,
+        }
+    }
+}
+
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        while let Some(row) = self.input.next()? {
+            if eval::matches(&self.condition, &self.scope, &row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Output columns computed from each row of its input
+pub struct Project<'a> {
+    input: Node<'a>,
+    projection: Projection,
+    scope: Scope,
+}
+
+impl<'a> Project<'a> {
+    pub fn new(input: Node<'a>, projection: Projection, scope: Scope) -> Self {
+        Self {
+            input,
+            projection,
+            scope,
+        }
+    }
+}
+
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        self.input
+            .next()?
+            .map(|row| self.projection.apply(&self.scope, &row))
+            .transpose()
+    }
+}
+
+/// Rows of its input after skipping some, up to an optional count, no longer pulling rows from
+/// its input once it has produced them all
+pub struct Limit<'a> {
+    input: Node<'a>,
+    skip: usize,
+    remaining: Option<usize>,
+}
+
+impl<'a> Limit<'a> {
+    pub fn new(input: Node<'a>, skip: usize, count: Option<usize>) -> Self {
+        Self {
+            input,
+            skip,
+            remaining: count,
+        }
+    }
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        while self.skip > 0 {
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+            self.skip -= 1;
+        }
+        let row = self.input.next()?;
+        if let (Some(remaining), Some(_)) = (&mut self.remaining, &row) {
+            *remaining -= 1;
+        }
+        Ok(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::db::tests::with_users;
+    use crate::sql::parser::tests::parse_select;
+
+    /// Operator counting how many rows were pulled from it
+    struct Counted<'a> {
+        input: Node<'a>,
+        pulled: &'a mut usize,
+    }
+
+    impl Operator for Counted<'_> {
+        fn next(&mut self) -> Result<Option<Vec<Value>>> {
+            *self.pulled += 1;
+            self.input.next()
+        }
+    }
+
+    fn numbers(count: i64) -> Values {
+        Values::new((0..count).map(|i| vec![Value::Integer(i)]).collect())
+    }
+
+    fn collect(mut node: Node) -> Vec<Vec<Value>> {
+        let mut rows = Vec::new();
+        while let Some(row) = node.next().unwrap() {
+            rows.push(row);
+        }
+        rows
+    }
+
+    #[test]
+    fn limit_stops_pulling() {
+        let mut pulled = 0;
+        let input = Box::new(Counted {
+            input: Box::new(numbers(100)),
+            pulled: &mut pulled,
+        });
+        let rows = collect(Box::new(Limit::new(input, 3, Some(2))));
+        assert_eq!(rows, vec![vec![Value::Integer(3)], vec![Value::Integer(4)]]);
+        assert_eq!(pulled, 5);
+    }
+
+    #[test]
+    fn operators_compose() {
+        let select = parse_select(
+            "select id * 10 from users where id % 2 = 1 order by id desc limit 2 offset 1",
+        )
+        .unwrap();
+        let db = with_users(&[
+            "karl", "fri", "day", "flake", "milk", "bowl", "spoon", "cup", "oat",
+        ]);
+        let (names, node) = plan(&db, &select).unwrap();
+        assert_eq!(names, vec!["id * 10"]);
+        assert_eq!(
+            collect(node),
+            vec![vec![Value::Integer(70)], vec![Value::Integer(50)]]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use super::value::Value;
+use super::{Error, Result};
+
+/// Call a scalar function by its lower-case name on already evaluated arguments
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+    let arity = |valid: bool| {
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::ExecutionError(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )))
+        }
+    };
+    match name {
+        "upper" | "lower" | "length" | "abs" | "trim" | "typeof" => arity(args.len() == 1)?,
+        "ifnull" | "nullif" => arity(args.len() == 2)?,
+        "round" => arity(matches!(args.len(), 1 | 2))?,
+        "substr" => arity(matches!(args.len(), 2 | 3))?,
+        "coalesce" => arity(!args.is_empty())?,
+        _ => return Err(Error::ExecutionError(format!("no such function: {}", name))),
+    }
+    let mut args = args.into_iter();
+    let first = args.next().unwrap();
+    if name == "typeof" {
+        return Ok(Value::Text(first.type_name().into()));
+    }
+    if name == "coalesce" || name == "ifnull" {
+        return Ok(std::iter::once(first)
+            .chain(args)
+            .find(|value| *value != Value::Null)
+            .unwrap_or(Value::Null));
+    }
+    if name == "nullif" {
+        let second = args.next().unwrap();
+        return Ok(if first.compare(&second).is_eq() {
+            Value::Null
+        } else {
+            first
+        });
+    }
+    if first == Value::Null {
+        return Ok(Value::Null);
+    }
+    let rest: Vec<Value> = args.collect();
+    Ok(match name {
+        "upper" => Value::Text(first.to_string().to_uppercase()),
+        "lower" => Value::Text(first.to_string().to_lowercase()),
+        "trim" => Value::Text(first.to_string().trim().into()),
+        "length" => Value::Integer(match &first {
+            Value::Blob(bytes) => bytes.len(),
+            value => value.to_string().chars().count(),
+        } as i64),
+        "abs" => match first {
+            Value::Integer(i) => Value::Integer(
+                i.checked_abs()
+                    .ok_or_else(|| Error::ExecutionError("integer overflow".into()))?,
+            ),
+            Value::Real(r) => Value::Real(r.abs()),
+            value => return Err(argument_error(name, &value)),
+        },
+        "round" => {
+            let number = first
+                .as_number()
+                .ok_or_else(|| argument_error(name, &first))?;
+            let digits = match rest.first() {
+                Some(Value::Integer(digits)) => (*digits).clamp(0, 15) as i32,
+                Some(value) => return Err(argument_error(name, value)),
+                None => 0,
+            };
+            let scale = 10f64.powi(digits);
+            Value::Real((number * scale).round() / scale)
+        }
+        "substr" => {
+            let text: Vec<char> = first.to_string().chars().collect();
+            let integer = |value: &Value| match value {
+                Value::Integer(i) => Ok(*i),
+                value => Err(argument_error(name, value)),
+            };
+            // like in SQLite, positions count from 1, a negative start counts back from the end
+            // and a negative length takes the characters before the start
+            let len = text.len() as i64;
+            let mut start = integer(&rest[0])?;
+            let mut count = match rest.get(1) {
+                Some(count) => integer(count)?,
+                // without a length, everything to the end is taken, even from position 0
+                None => i64::MAX,
+            };
+            if start < 0 {
+                start = start.saturating_add(len);
+                if start < 0 {
+                    count = count.saturating_add(start).max(0);
+                    start = 0;
+                }
+            } else if start > 0 {
+                start -= 1;
+            } else if count > 0 {
+                // position 0 comes before the first character, using up one of the length
+                count -= 1;
+            }
+            if count < 0 {
+                start = start.saturating_add(count);
+                count = count.saturating_neg();
+                if start < 0 {
+                    count = count.saturating_add(start);
+                    start = 0;
+                }
+            }
+            let start = start.min(len) as usize;
+            let count = count.clamp(0, len) as usize;
+            Value::Text(text.iter().skip(start).take(count).collect())
+        }
+        _ => unreachable!("arity checked for unknown function {}", name),
+    })
+}
+
+fn argument_error(name: &str, value: &Value) -> Error {
+    Error::ExecutionError(format!(
+        "invalid {} argument to function {}()",
+        value.type_name(),
+        name
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("upper", vec![Value::Text("karl".into())] => Value::Text("KARL".into()) ; "upper")]
+    #[test_case("lower", vec![Value::Null] => Value::Null ; "null argument")]
+    #[test_case("length", vec![Value::Text("héllo".into())] => Value::Integer(5) ; "length in characters")]
+    #[test_case("abs", vec![Value::Integer(-4)] => Value::Integer(4) ; "abs")]
+    #[test_case("round", vec![Value::Real(2.567), Value::Integer(2)] => Value::Real(2.57) ; "round digits")]
+    #[test_case("coalesce", vec![Value::Null, Value::Integer(2), Value::Integer(3)] => Value::Integer(2) ; "coalesce")]
+    #[test_case("nullif", vec![Value::Integer(1), Value::Real(1.0)] => Value::Null ; "nullif")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(2), Value::Integer(4)] => Value::Text("lake".into()) ; "substr")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(-2)] => Value::Text("db".into()) ; "substr from end")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(0), Value::Integer(3)] => Value::Text("fl".into()) ; "substr from zero")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(-9), Value::Integer(4)] => Value::Text("fl".into()) ; "substr before start")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(4), Value::Integer(-2)] => Value::Text("la".into()) ; "substr negative length")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(9)] => Value::Text("".into()) ; "substr past end")]
+    #[test_case("substr", vec![Value::Text("flakedb".into()), Value::Integer(0)] => Value::Text("flakedb".into()) ; "substr from zero to end")]
+    #[test_case("typeof", vec![Value::Real(1.0)] => Value::Text("real".into()) ; "type name")]
+    fn call_valid(name: &str, args: Vec<Value>) -> Value {
+        call(name, args).unwrap()
+    }
+
+    #[test_case("nope", vec![] ; "unknown function")]
+    #[test_case("upper", vec![] ; "too few arguments")]
+    #[test_case("abs", vec![Value::Text("x".into())] ; "wrong argument type")]
+    fn call_invalid(name: &str, args: Vec<Value>) {
+        assert!(call(name, args).is_err());
+    }
+}
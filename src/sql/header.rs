@@ -0,0 +1,148 @@
+use super::pager::{JournalMode, PAGE_SIZE};
+use super::{Error, Result};
+use std::ops::Range;
+
+pub const MAGIC: &[u8; 16] = b"flakedb format\0\0";
+pub const FORMAT_VERSION: u32 = 1;
+
+const RANGE_MAGIC: Range<usize> = 0..16;
+const OFFSET_VERSION: usize = 16;
+const OFFSET_PAGE_SIZE: usize = 20;
+const OFFSET_PAGE_COUNT: usize = 24;
+const OFFSET_FREE_LIST: usize = 28;
+const OFFSET_SCHEMA_COOKIE: usize = 32;
+const OFFSET_CHANGE_COUNTER: usize = 36;
+const OFFSET_FREE_COUNT: usize = 40;
+const OFFSET_JOURNAL_MODE: usize = 44;
+
+/// Database file header occupying page 0
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub page_count: usize,
+    /// First page of the free-page list, or 0 if there are no free pages. Each free page starts
+    /// with the number of the next one.
+    pub free_list: usize,
+    /// Number of pages in the free-page list
+    pub free_count: usize,
+    /// Incremented whenever the catalog changes so cached schemas can be invalidated
+    pub schema_cookie: u32,
+    /// Incremented by every commit so other connections can tell their cached pages are stale
+    pub change_counter: u32,
+    /// How the database is kept consistent, which every connection to it follows. Fixed when the
+    /// database is created.
+    pub journal_mode: JournalMode,
+}
+
+impl Default for Header {
+    /// Header for a new database containing only the header page itself
+    fn default() -> Self {
+        Self {
+            page_count: 1,
+            free_list: 0,
+            free_count: 0,
+            schema_cookie: 0,
+            change_counter: 0,
+            journal_mode: JournalMode::default(),
+        }
+    }
+}
+
+impl Header {
+    pub fn read(page: &[u8]) -> Result<Self> {
+        if &page[RANGE_MAGIC] != MAGIC {
+            return Err(Error::NotADatabase);
+        }
+        let version = read_u32(page, OFFSET_VERSION);
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let page_size = read_u32(page, OFFSET_PAGE_SIZE) as usize;
+        if page_size != PAGE_SIZE {
+            return Err(Error::UnsupportedPageSize(page_size));
+        }
+        Ok(Self {
+            page_count: read_u32(page, OFFSET_PAGE_COUNT) as usize,
+            free_list: read_u32(page, OFFSET_FREE_LIST) as usize,
+            free_count: read_u32(page, OFFSET_FREE_COUNT) as usize,
+            schema_cookie: read_u32(page, OFFSET_SCHEMA_COOKIE),
+            change_counter: read_u32(page, OFFSET_CHANGE_COUNTER),
+            journal_mode: match read_u32(page, OFFSET_JOURNAL_MODE) {
+                0 => JournalMode::Wal,
+                1 => JournalMode::Rollback,
+                _ => return Err(Error::Corrupt),
+            },
+        })
+    }
+
+    pub fn write(&self, page: &mut [u8]) {
+        page[RANGE_MAGIC].copy_from_slice(MAGIC);
+        write_u32(page, OFFSET_VERSION, FORMAT_VERSION);
+        write_u32(page, OFFSET_PAGE_SIZE, PAGE_SIZE as u32);
+        write_u32(page, OFFSET_PAGE_COUNT, self.page_count as u32);
+        write_u32(page, OFFSET_FREE_LIST, self.free_list as u32);
+        write_u32(page, OFFSET_FREE_COUNT, self.free_count as u32);
+        write_u32(page, OFFSET_SCHEMA_COOKIE, self.schema_cookie);
+        write_u32(page, OFFSET_CHANGE_COUNTER, self.change_counter);
+        let journal_mode = match self.journal_mode {
+            JournalMode::Wal => 0,
+            JournalMode::Rollback => 1,
+        };
+        write_u32(page, OFFSET_JOURNAL_MODE, journal_mode);
+    }
+}
+
+fn read_u32(page: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(page[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(page: &mut [u8], offset: usize, value: u32) {
+    page[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let header = Header {
+            page_count: 12,
+            free_list: 7,
+            free_count: 2,
+            schema_cookie: 3,
+            change_counter: 5,
+            journal_mode: JournalMode::Rollback,
+        };
+        let mut page = [0; PAGE_SIZE];
+        header.write(&mut page);
+        assert_eq!(Header::read(&page).unwrap(), header);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let page = [b'x'; PAGE_SIZE];
+        assert!(matches!(Header::read(&page), Err(Error::NotADatabase)));
+    }
+
+    #[test]
+    fn future_version() {
+        let mut page = [0; PAGE_SIZE];
+        Header::default().write(&mut page);
+        write_u32(&mut page, OFFSET_VERSION, FORMAT_VERSION + 1);
+        assert!(matches!(
+            Header::read(&page),
+            Err(Error::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn different_page_size() {
+        let mut page = [0; PAGE_SIZE];
+        Header::default().write(&mut page);
+        write_u32(&mut page, OFFSET_PAGE_SIZE, 512);
+        assert!(matches!(
+            Header::read(&page),
+            Err(Error::UnsupportedPageSize(512))
+        ));
+    }
+}
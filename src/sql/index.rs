@@ -0,0 +1,461 @@
+use super::btree::{BTree, Cursor};
+use super::pager::Pager;
+use super::row::Row;
+use super::table::{decode_key, encode_key, Table};
+use super::value::Value;
+use super::{Error, Result};
+use std::collections::HashSet;
+use std::ops::Bound;
+
+// leading byte of each encoded value, ordered like `Value::compare`
+const TAG_NULL: u8 = 0;
+const TAG_NUMBER: u8 = 1;
+const TAG_TEXT: u8 = 2;
+const TAG_BLOB: u8 = 3;
+
+/// Secondary index over some columns of a table.
+///
+/// The index is a B+tree whose keys are the indexed values, encoded so that byte-wise comparison
+/// orders them like `Value::compare`, followed by the rowid of the row they were taken from. The
+/// rowid makes every key distinct and leads straight back to the row, so values are left empty.
+pub struct Index {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    /// Position of each indexed column in the table's schema
+    pub positions: Vec<usize>,
+    root: usize,
+}
+
+/// Keys of an index to visit: those equal to some leading values, optionally further narrowed by
+/// bounds on the value of the column following them
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyRange {
+    pub equal: Vec<Value>,
+    pub lower: Bound<Value>,
+    pub upper: Bound<Value>,
+}
+
+impl Index {
+    /// Load an index whose tree already exists at `root`
+    pub fn open(
+        name: &str,
+        table: &Table,
+        columns: Vec<String>,
+        unique: bool,
+        root: usize,
+    ) -> Result<Self> {
+        let positions = columns
+            .iter()
+            .map(|column| {
+                table
+                    .schema
+                    .columns
+                    .iter()
+                    .position(|c| &c.name == column)
+                    .ok_or_else(|| {
+                        Error::ExecutionError(format!(
+                            "table {} has no column named {}",
+                            table.name, column
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            name: name.into(),
+            table: table.name.clone(),
+            columns,
+            unique,
+            positions,
+            root,
+        })
+    }
+
+    /// Create an index in newly allocated pages, filled with the table's existing rows
+    pub fn create(
+        pager: &Pager,
+        name: &str,
+        table: &Table,
+        columns: Vec<String>,
+        unique: bool,
+    ) -> Result<Self> {
+        let root = BTree::create(pager)?.root();
+        let index = Self::open(name, table, columns, unique, root)
+            .and_then(|index| index.fill(pager, table).map(|_| index));
+        if index.is_err() {
+            BTree::new(pager, root).destroy()?;
+        }
+        index
+    }
+
+    fn fill(&self, pager: &Pager, table: &Table) -> Result<()> {
+        for row in table.select(pager)? {
+            let (rowid, row) = row?;
+            self.check(pager, &row)?;
+            self.insert(pager, rowid, &row)?;
+        }
+        Ok(())
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Statement that recreates the index, as recorded in the catalog
+    pub fn sql(&self) -> String {
+        format!(
+            "create {}index {} on {} ({})",
+            if self.unique { "unique " } else { "" },
+            self.name,
+            self.table,
+            self.columns.join(", ")
+        )
+    }
+
+    fn tree<'a>(&self, pager: &'a Pager) -> BTree<'a> {
+        BTree::new(pager, self.root)
+    }
+
+    fn values<'a>(&'a self, row: &'a Row) -> impl Iterator<Item = &'a Value> + 'a {
+        self.positions.iter().map(move |&i| &row.values[i])
+    }
+
+    fn key(&self, rowid: i64, row: &Row) -> Vec<u8> {
+        let mut key = Vec::new();
+        for value in self.values(row) {
+            encode_value(&mut key, value);
+        }
+        key.extend_from_slice(&encode_key(rowid));
+        key
+    }
+
+    /// Check that adding a row would not duplicate the values of another row in a unique index
+    pub fn check(&self, pager: &Pager, row: &Row) -> Result<()> {
+        match self.duplicate_of(pager, row)? {
+            Some(_) => Err(self.duplicate(row)),
+            None => Ok(()),
+        }
+    }
+
+    /// Rowid of the row whose values a row would duplicate in a unique index, if there is one
+    pub fn duplicate_of(&self, pager: &Pager, row: &Row) -> Result<Option<i64>> {
+        let Some(prefix) = self.unique_prefix(row) else {
+            return Ok(None);
+        };
+        if let Some(entry) = Cursor::seek(pager, self.root, &prefix)?.next() {
+            let (key, _) = entry?;
+            if key.starts_with(&prefix) {
+                return Ok(Some(decode_key(&key[key.len() - 8..])));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check that a row about to be written does not duplicate the values of another one being
+    /// written in the same statement, whose unique values are collected in `pending`
+    pub fn check_pending(&self, pending: &mut HashSet<Vec<u8>>, row: &Row) -> Result<()> {
+        match self.unique_prefix(row) {
+            Some(prefix) if pending.contains(&prefix) => Err(self.duplicate(row)),
+            Some(prefix) => {
+                pending.insert(prefix);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Encoded values of a row in a unique index, which every entry for the row starts with.
+    ///
+    /// As in SQLite, rows with a NULL in any indexed column never count as duplicates, so they
+    /// have none.
+    fn unique_prefix(&self, row: &Row) -> Option<Vec<u8>> {
+        if !self.unique || self.values(row).any(|value| *value == Value::Null) {
+            return None;
+        }
+        let mut prefix = Vec::new();
+        for value in self.values(row) {
+            encode_value(&mut prefix, value);
+        }
+        Some(prefix)
+    }
+
+    /// Error for a row duplicating the values of another in a unique index
+    pub fn duplicate(&self, row: &Row) -> Error {
+        let key: Vec<_> = self.values(row).map(Value::to_string).collect();
+        Error::ConstraintViolation {
+            table: self.table.clone(),
+            column: self.columns.join(", "),
+            key: key.join(", "),
+        }
+    }
+
+    /// Add the entry for a row, which should have been checked for uniqueness first
+    pub fn insert(&self, pager: &Pager, rowid: i64, row: &Row) -> Result<()> {
+        self.tree(pager).insert(&self.key(rowid, row), &[])
+    }
+
+    /// Remove the entry for a row, returning false if there is none
+    pub fn remove(&self, pager: &Pager, rowid: i64, row: &Row) -> Result<bool> {
+        self.tree(pager).remove(&self.key(rowid, row))
+    }
+
+    /// Hand the pages of the index back to the pager
+    pub fn destroy(self, pager: &Pager) -> Result<()> {
+        self.tree(pager).destroy()
+    }
+
+    /// Move the pages of the index numbered `limit` or above into free pages from `destinations`,
+    /// including the root
+    pub fn relocate(
+        &mut self,
+        pager: &Pager,
+        limit: usize,
+        destinations: &mut Vec<usize>,
+    ) -> Result<()> {
+        self.root = self.tree(pager).relocate(limit, destinations)?;
+        Ok(())
+    }
+
+    /// Rowids of the rows whose indexed values fall within a range, in index order
+    pub fn scan<'a>(&self, pager: &'a Pager, range: &KeyRange) -> Result<Rowids<'a>> {
+        let mut prefix = Vec::new();
+        for value in &range.equal {
+            encode_value(&mut prefix, value);
+        }
+        let bound = |bound: &Bound<Value>| match bound {
+            Bound::Included(value) => Bound::Included(prefixed(&prefix, value)),
+            Bound::Excluded(value) => Bound::Excluded(prefixed(&prefix, value)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let (lower, upper) = (bound(&range.lower), bound(&range.upper));
+        let start = match &lower {
+            Bound::Included(key) | Bound::Excluded(key) => key.clone(),
+            // comparisons are never true for NULL, so a range skips past them
+            Bound::Unbounded if range.upper != Bound::Unbounded => {
+                let mut start = prefix.clone();
+                start.push(TAG_NULL + 1);
+                start
+            }
+            Bound::Unbounded => prefix.clone(),
+        };
+        Ok(Rowids {
+            cursor: Cursor::seek(pager, self.root, &start)?,
+            prefix,
+            lower,
+            upper,
+        })
+    }
+}
+
+fn prefixed(prefix: &[u8], value: &Value) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    encode_value(&mut key, value);
+    key
+}
+
+/// Rowids found by scanning a range of an index
+pub struct Rowids<'a> {
+    cursor: Cursor<'a>,
+    prefix: Vec<u8>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+}
+
+impl Iterator for Rowids<'_> {
+    type Item = Result<i64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = match self.cursor.next()? {
+                Ok((key, _)) => key,
+                Err(error) => return Some(Err(error)),
+            };
+            if !key.starts_with(&self.prefix) {
+                return None;
+            }
+            // keys carry more than the bounding values, so bounds compare on a prefix of the key
+            let head = |bound: &[u8]| &key[..bound.len().min(key.len())];
+            match &self.upper {
+                Bound::Included(upper) if head(upper) > upper.as_slice() => return None,
+                Bound::Excluded(upper) if head(upper) >= upper.as_slice() => return None,
+                _ => {}
+            }
+            if let Bound::Excluded(lower) = &self.lower {
+                if head(lower) == lower.as_slice() {
+                    continue;
+                }
+            }
+            return Some(Ok(decode_key(&key[key.len() - 8..])));
+        }
+    }
+}
+
+/// Append a value in an encoding whose byte-wise order matches `Value::compare` between values of
+/// the same column. Every encoding is self-delimiting, so that values can be concatenated into
+/// composite keys without one value's bytes spilling into the comparison of the next.
+fn encode_value(key: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => key.push(TAG_NULL),
+        Value::Integer(i) => {
+            key.push(TAG_NUMBER);
+            key.extend_from_slice(&encode_key(*i));
+        }
+        Value::Real(r) => {
+            key.push(TAG_NUMBER);
+            // flip the sign bit of positive numbers and every bit of negative ones
+            let bits = r.to_bits();
+            let bits = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits ^ (1 << 63)
+            };
+            key.extend_from_slice(&bits.to_be_bytes());
+        }
+        Value::Boolean(b) => {
+            key.push(TAG_NUMBER);
+            key.push(*b as u8);
+        }
+        Value::Text(s) => {
+            key.push(TAG_TEXT);
+            encode_bytes(key, s.as_bytes());
+        }
+        Value::Blob(bytes) => {
+            key.push(TAG_BLOB);
+            encode_bytes(key, bytes);
+        }
+    }
+}
+
+/// Escape zero bytes as `00 ff` and terminate with `00 00`, which sorts before any continuation
+fn encode_bytes(key: &mut Vec<u8>, bytes: &[u8]) {
+    for &byte in bytes {
+        key.push(byte);
+        if byte == 0 {
+            key.push(0xff);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::schema::{Column, ColumnType, Schema};
+    use test_case::test_case;
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut key = Vec::new();
+        encode_value(&mut key, value);
+        key
+    }
+
+    #[test]
+    fn encoding_preserves_order() {
+        let groups = [
+            vec![
+                Value::Integer(i64::MIN),
+                Value::Integer(-1),
+                Value::Integer(0),
+                Value::Integer(7),
+            ],
+            vec![
+                Value::Real(-2.5),
+                Value::Real(-0.5),
+                Value::Real(0.0),
+                Value::Real(1e10),
+            ],
+            vec![
+                Value::Text("".into()),
+                Value::Text("a".into()),
+                Value::Text("a\0b".into()),
+                Value::Text("ab".into()),
+            ],
+            vec![Value::Null, Value::Boolean(false), Value::Boolean(true)],
+        ];
+        for values in groups {
+            for pair in values.windows(2) {
+                assert!(encoded(&pair[0]) < encoded(&pair[1]), "{:?}", pair);
+            }
+        }
+    }
+
+    fn sample() -> (Pager, Table) {
+        let pager = Pager::open(None).unwrap();
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer).primary_key(),
+            Column::new("name", ColumnType::Text),
+            Column::new("score", ColumnType::Integer),
+        ])
+        .unwrap();
+        let mut table = Table::create(&pager, "t", schema).unwrap();
+        for (name, score) in [("karl", 3), ("fri", 9), ("day", 3), ("lou", 5)] {
+            let row = vec![Value::Null, Value::Text(name.into()), Value::Integer(score)];
+            table.insert(&pager, Row::new(row)).unwrap();
+        }
+        table
+            .insert(
+                &pager,
+                Row::new(vec![Value::Null, Value::Text("nil".into()), Value::Null]),
+            )
+            .unwrap();
+        (pager, table)
+    }
+
+    #[test_case(vec![Value::Integer(3)], Bound::Unbounded, Bound::Unbounded => vec![1, 3] ; "equal")]
+    #[test_case(vec![], Bound::Excluded(Value::Integer(3)), Bound::Unbounded => vec![4, 2] ; "greater")]
+    #[test_case(vec![], Bound::Unbounded, Bound::Included(Value::Integer(5)) => vec![1, 3, 4] ; "at most skipping nulls")]
+    #[test_case(vec![], Bound::Included(Value::Integer(4)), Bound::Excluded(Value::Integer(9)) => vec![4] ; "between")]
+    #[test_case(vec![], Bound::Unbounded, Bound::Unbounded => vec![5, 1, 3, 4, 2] ; "everything")]
+    fn scan_range(equal: Vec<Value>, lower: Bound<Value>, upper: Bound<Value>) -> Vec<i64> {
+        let (pager, table) = sample();
+        let index = Index::create(&pager, "i", &table, vec!["score".into()], false).unwrap();
+        let range = KeyRange {
+            equal,
+            lower,
+            upper,
+        };
+        index
+            .scan(&pager, &range)
+            .unwrap()
+            .map(|rowid| rowid.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn unique_rejects_duplicates() {
+        let (pager, table) = sample();
+        assert!(Index::create(&pager, "i", &table, vec!["score".into()], true).is_err());
+        let index = Index::create(&pager, "i", &table, vec!["name".into()], true).unwrap();
+        let row = |name: &str| Row::new(vec![Value::Null, Value::Text(name.into()), Value::Null]);
+        assert_eq!(
+            index.check(&pager, &row("fri")).unwrap_err().to_string(),
+            "constraint violation: duplicate key fri for name in table t"
+        );
+        assert!(index.check(&pager, &row("fr")).is_ok());
+        let composite = Index::create(
+            &pager,
+            "j",
+            &table,
+            vec!["score".into(), "name".into()],
+            true,
+        )
+        .unwrap();
+        assert!(composite.check(&pager, &row("fri")).is_ok());
+    }
+
+    #[test]
+    fn entries_removed() {
+        let (pager, table) = sample();
+        let index = Index::create(&pager, "i", &table, vec!["name".into()], false).unwrap();
+        let row = table.get(&pager, 2).unwrap().unwrap();
+        assert!(index.remove(&pager, 2, &row).unwrap());
+        assert!(!index.remove(&pager, 2, &row).unwrap());
+        let range = KeyRange {
+            equal: vec![Value::Text("fri".into())],
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        };
+        assert_eq!(index.scan(&pager, &range).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,209 @@
+use super::ast::{Expr, Join, JoinKind, TableRef};
+use super::db::Database;
+use super::eval::{self, Scope};
+use super::executor::{Node, Operator};
+use super::planner;
+use super::value::Value;
+use super::Result;
+
+/// Plan reading the rows of the tables of a from clause, joining each table to the ones before
+/// it. The joined rows are laid out as the columns of the tables follow each other in the scope.
+///
+/// Only the first table is narrowed down by the where clause, which must still be applied to the
+/// joined rows.
+pub fn plan<'a>(
+    db: &'a Database,
+    from: &'a TableRef,
+    joins: &'a [Join],
+    filter: Option<&Expr>,
+) -> Result<(Scope, Node<'a>)> {
+    let mut scope = table_scope(db, from)?;
+    let mut rows = planner::access(db, &from.name, &scope, filter)?;
+    for join in joins {
+        let right = table_scope(db, &join.table)?;
+        let left_width = scope.columns.len();
+        scope.columns.extend(right.columns.iter().cloned());
+        rows = Box::new(NestedLoop {
+            db,
+            left: rows,
+            join,
+            scope: scope.clone(),
+            right,
+            left_width,
+            current: None,
+        });
+    }
+    Ok((scope, rows))
+}
+
+fn table_scope(db: &Database, table: &TableRef) -> Result<Scope> {
+    let schema = &db.table(&table.name)?.schema;
+    Ok(Scope::table(
+        table.alias.as_ref().unwrap_or(&table.name),
+        schema,
+    ))
+}
+
+/// Nested-loop join, pairing each row of the left side with the rows of a table that meet the
+/// join condition.
+///
+/// For each row of the left side, its values are substituted into the condition, so that the
+/// table is read through an index whenever one covers the columns the condition then compares
+/// with constants, as in an index nested-loop join. Otherwise the whole table is scanned again.
+struct NestedLoop<'a> {
+    db: &'a Database,
+    left: Node<'a>,
+    join: &'a Join,
+    /// Scope of the joined rows
+    scope: Scope,
+    /// Scope of the rows of the joined table alone
+    right: Scope,
+    /// Number of values in each row of the left side
+    left_width: usize,
+    /// Row of the left side being joined, the rows of the table it may be paired with, and
+    /// whether any have met the condition so far
+    current: Option<(Vec<Value>, Node<'a>, bool)>,
+}
+
+impl<'a> NestedLoop<'a> {
+    /// Rows of the joined table that may meet the join condition for a row of the left side
+    fn candidates(&self, left: &[Value]) -> Result<Node<'a>> {
+        let condition = self
+            .join
+            .on
+            .as_ref()
+            .map(|on| self.bind(on, left))
+            .transpose()?;
+        planner::access(
+            self.db,
+            &self.join.table.name,
+            &self.right,
+            condition.as_ref(),
+        )
+    }
+
+    /// Replace references to columns of the left side in a condition with their values
+    fn bind(&self, condition: &Expr, left: &[Value]) -> Result<Expr> {
+        condition.transform(&mut |expr| match expr {
+            Expr::Column { table, name } => {
+                let position = self.scope.resolve(table.as_deref(), name)?;
+                Ok((position < self.left_width).then(|| Expr::Literal(left[position].clone())))
+            }
+            _ => Ok(None),
+        })
+    }
+}
+
+impl Operator for NestedLoop<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        loop {
+            let (left, candidates, matched) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let Some(left) = self.left.next()? else {
+                        return Ok(None);
+                    };
+                    let candidates = self.candidates(&left)?;
+                    self.current.insert((left, candidates, false))
+                }
+            };
+            match candidates.next()? {
+                Some(right) => {
+                    let mut row = left.clone();
+                    row.extend(right);
+                    if let Some(on) = &self.join.on {
+                        if !eval::matches(on, &self.scope, &row)? {
+                            continue;
+                        }
+                    }
+                    *matched = true;
+                    return Ok(Some(row));
+                }
+                None => {
+                    let (mut left, _, matched) = self.current.take().unwrap();
+                    if self.join.kind == JoinKind::Left && !matched {
+                        left.resize(self.scope.columns.len(), Value::Null);
+                        return Ok(Some(left));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::db::tests::with_users;
+    use crate::sql::parser::tests::parse_select;
+    use crate::sql::schema::{Column, ColumnType, Schema};
+
+    fn database(indexed: bool) -> Database {
+        let mut db = with_users(&["karl", "dangerous", "fri"]);
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer).primary_key(),
+            Column::new("user_id", ColumnType::Integer),
+            Column::new("item", ColumnType::Text),
+        ])
+        .unwrap();
+        db.create_table("orders", schema).unwrap();
+        for (user, item) in [(1, "flakes"), (3, "milk"), (1, "bowl"), (4, "spoon")] {
+            let values = vec![Value::Null, Value::Integer(user), Value::Text(item.into())];
+            db.insert("orders", values).unwrap();
+        }
+        if indexed {
+            db.create_index("by_user", "orders", vec!["user_id".into()], false)
+                .unwrap();
+        }
+        db
+    }
+
+    fn join(db: &Database, raw: &str) -> Result<Vec<String>> {
+        let select = parse_select(raw)?;
+        let (scope, mut rows) = plan(db, select.from.as_ref().unwrap(), &select.joins, None)?;
+        let name = scope.resolve(Some("u"), "username")?;
+        let item = scope.resolve(Some("o"), "item")?;
+        let mut joined = Vec::new();
+        while let Some(row) = rows.next()? {
+            joined.push(format!("{}:{}", row[name], row[item]));
+        }
+        Ok(joined)
+    }
+
+    #[test]
+    fn inner_and_left_joins() {
+        for indexed in [false, true] {
+            let db = database(indexed);
+            assert_eq!(
+                join(
+                    &db,
+                    "select * from users u join orders o on o.user_id = u.id"
+                )
+                .unwrap(),
+                vec!["karl:flakes", "karl:bowl", "fri:milk"]
+            );
+            assert_eq!(
+                join(
+                    &db,
+                    "select * from users u left join orders o on u.id = user_id"
+                )
+                .unwrap(),
+                vec!["karl:flakes", "karl:bowl", "dangerous:", "fri:milk"]
+            );
+        }
+    }
+
+    #[test]
+    fn cross_join() {
+        let db = database(false);
+        let rows = join(&db, "select * from users u, orders o").unwrap();
+        assert_eq!(rows.len(), 12);
+        assert_eq!(rows[..2], ["karl:flakes", "karl:milk"]);
+    }
+
+    #[test]
+    fn ambiguous_columns_rejected() {
+        let db = database(true);
+        assert!(join(&db, "select * from users u join orders o on id = user_id").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::sql::pager::{Page, PAGE_SIZE};
+use crate::sql::wal::{checksum, read_at, read_u32};
+use crate::sql::Result;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"flakejnl";
+// journal header layout
+const HEADER_SIZE: usize = 16;
+const OFFSET_PAGE_SIZE: usize = 8;
+/// Length of the database file in pages when the journal was started
+const OFFSET_PAGE_COUNT: usize = 12;
+// record header layout, followed by the original page
+const RECORD_HEADER_SIZE: usize = 8;
+const OFFSET_PAGE: usize = 0;
+const OFFSET_CHECKSUM: usize = 4;
+const RECORD_SIZE: usize = RECORD_HEADER_SIZE + PAGE_SIZE;
+
+/// Rollback journal kept in a `-journal` file next to a database file.
+///
+/// Before a page of the database file is first overwritten in a transaction, its original
+/// contents are appended to the journal, which is synced before the database file is touched.
+/// Deleting the journal commits the transaction. A journal still present when a database is opened
+/// is hot: its transaction never committed, so the original pages are copied back.
+pub struct Journal {
+    path: PathBuf,
+    /// Open while the current transaction has started writing to the database file
+    file: Option<File>,
+    /// Pages whose originals the journal holds
+    saved: HashSet<usize>,
+    /// Length of the database file in pages when the journal was started. Pages past it were added
+    /// by the transaction, so rolling back simply truncates them.
+    page_count: usize,
+    len: u64,
+    synced: bool,
+    /// Whether syncing waits for the journal and database file to reach the disk
+    sync: bool,
+}
+
+impl Journal {
+    /// Journal belonging to a database file, which is only created once a transaction needs it
+    pub fn new(db_path: &Path) -> Self {
+        let mut path = OsString::from(db_path);
+        path.push("-journal");
+        Self {
+            path: path.into(),
+            file: None,
+            saved: HashSet::new(),
+            page_count: 0,
+            len: 0,
+            synced: true,
+            sync: true,
+        }
+    }
+
+    /// Choose whether syncing waits for the journal and database file to reach the disk
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Whether the current transaction has started writing to the database file
+    pub fn is_active(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Whether a journal was left behind by a connection that never finished its transaction,
+    /// which only the holder of an exclusive lock on the database can have started
+    pub fn is_hot(&self) -> bool {
+        !self.is_active() && self.path.exists()
+    }
+
+    /// Start the journal for a transaction, given the length of the database file in pages
+    pub fn start(&mut self, page_count: usize) -> Result<()> {
+        if self.is_active() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        let mut header = [0; HEADER_SIZE];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[OFFSET_PAGE_SIZE..OFFSET_PAGE_COUNT]
+            .copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        header[OFFSET_PAGE_COUNT..].copy_from_slice(&(page_count as u32).to_be_bytes());
+        file.write_all(&header)?;
+        self.file = Some(file);
+        self.page_count = page_count;
+        self.len = HEADER_SIZE as u64;
+        self.synced = false;
+        Ok(())
+    }
+
+    /// Whether a page's original contents must be saved before it is overwritten
+    pub fn needs(&self, index: usize) -> bool {
+        self.is_active() && index < self.page_count && !self.saved.contains(&index)
+    }
+
+    /// Append the original contents of a page of the database file
+    pub fn save(&mut self, index: usize, original: &Page) -> Result<()> {
+        debug_assert!(self.needs(index), "saving page {} twice", index);
+        let mut record = Vec::with_capacity(RECORD_SIZE);
+        record.extend_from_slice(&(index as u32).to_be_bytes());
+        record.extend_from_slice(&checksum(&[&record, original.as_slice()]).to_be_bytes());
+        record.extend_from_slice(original.as_slice());
+        let file = self.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(self.len))?;
+        file.write_all(&record)?;
+        self.len += RECORD_SIZE as u64;
+        self.saved.insert(index);
+        self.synced = false;
+        Ok(())
+    }
+
+    /// Make the saved pages durable, which must happen before the pages are overwritten
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_mut().filter(|_| !self.synced) {
+            if self.sync {
+                file.sync_data()?;
+            }
+            self.synced = true;
+        }
+        Ok(())
+    }
+
+    /// Delete the journal once the database file holds the whole transaction and is synced
+    pub fn commit(&mut self) -> Result<()> {
+        if self.file.take().is_some() {
+            fs::remove_file(&self.path)?;
+        }
+        self.saved.clear();
+        Ok(())
+    }
+
+    /// Copy the original pages saved in the journal, whether by this process or one that crashed,
+    /// back into the database file and delete the journal. Returns the length in pages the file
+    /// was restored to, if there was a journal to roll back.
+    pub fn rollback(&mut self, db: &mut File) -> Result<Option<usize>> {
+        self.file = None;
+        self.saved.clear();
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let mut header = [0; HEADER_SIZE];
+        // the header is synced along with the first record, so without one the database file was
+        // never written to
+        let page_count = if read_at(&mut file, 0, &mut header)?
+            && &header[..MAGIC.len()] == MAGIC
+            && read_u32(&header, OFFSET_PAGE_SIZE) as usize == PAGE_SIZE
+        {
+            let mut record = vec![0; RECORD_SIZE];
+            let mut offset = HEADER_SIZE as u64;
+            while read_at(&mut file, offset, &mut record)? {
+                let index = &record[OFFSET_PAGE..OFFSET_CHECKSUM];
+                let page = &record[RECORD_HEADER_SIZE..];
+                if read_u32(&record, OFFSET_CHECKSUM) != checksum(&[index, page]) {
+                    // a torn record was never followed by a write to the database file
+                    break;
+                }
+                let index = read_u32(&record, OFFSET_PAGE) as u64;
+                db.seek(SeekFrom::Start(index * PAGE_SIZE as u64))?;
+                db.write_all(page)?;
+                offset += RECORD_SIZE as u64;
+            }
+            let page_count = read_u32(&header, OFFSET_PAGE_COUNT) as usize;
+            db.set_len((page_count * PAGE_SIZE) as u64)?;
+            if self.sync {
+                db.sync_all()?;
+            }
+            Some(page_count)
+        } else {
+            None
+        };
+        fs::remove_file(&self.path)?;
+        Ok(page_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+
+    fn page(byte: u8) -> Page {
+        Page::from_vec(vec![byte; PAGE_SIZE])
+    }
+
+    /// Database file of `count` pages, each filled with its own index
+    fn database(file: &NamedTempFile, count: u8) -> File {
+        let data: Vec<u8> = (0..count).flat_map(|i| vec![i; PAGE_SIZE]).collect();
+        fs::write(file.path(), data).unwrap();
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file.path())
+            .unwrap()
+    }
+
+    fn overwrite(db: &mut File, index: usize, page: &Page) {
+        db.seek(SeekFrom::Start((index * PAGE_SIZE) as u64))
+            .unwrap();
+        db.write_all(page.as_slice()).unwrap();
+    }
+
+    #[test]
+    fn hot_journal_rolled_back() {
+        let file = NamedTempFile::new("hot_journal_rolled_back.flake").unwrap();
+        let mut db = database(&file, 3);
+        {
+            let mut journal = Journal::new(file.path());
+            journal.start(3).unwrap();
+            assert!(journal.needs(1) && !journal.needs(3));
+            journal.save(1, &page(1)).unwrap();
+            assert!(!journal.needs(1));
+            journal.sync().unwrap();
+            overwrite(&mut db, 1, &page(9));
+            overwrite(&mut db, 3, &page(9));
+            // dropped without committing, as if the process had crashed
+        }
+        let mut journal = Journal::new(file.path());
+        assert_eq!(journal.rollback(&mut db).unwrap(), Some(3));
+        assert_eq!(
+            fs::read(file.path()).unwrap(),
+            [[0; PAGE_SIZE], [1; PAGE_SIZE], [2; PAGE_SIZE]].concat()
+        );
+        assert!(!file
+            .path()
+            .with_file_name("hot_journal_rolled_back.flake-journal")
+            .exists());
+        assert_eq!(journal.rollback(&mut db).unwrap(), None);
+    }
+
+    #[test]
+    fn commit_deletes_journal() {
+        let file = NamedTempFile::new("commit_deletes_journal.flake").unwrap();
+        let mut db = database(&file, 2);
+        let mut journal = Journal::new(file.path());
+        journal.start(2).unwrap();
+        journal.save(1, &page(1)).unwrap();
+        journal.sync().unwrap();
+        overwrite(&mut db, 1, &page(9));
+        journal.commit().unwrap();
+        assert!(!journal.is_active());
+        assert_eq!(journal.rollback(&mut db).unwrap(), None);
+        assert_eq!(fs::read(file.path()).unwrap()[PAGE_SIZE], 9);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::sql::{Error, Result};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest pause between attempts to take a lock held by another connection
+const MAX_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Access a connection holds to a database file, following SQLite's lock states
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    Unlocked,
+    /// Reading, which any number of connections may do at once
+    Shared,
+    /// Reading while preparing to write. Other connections may still read, but only one may hold
+    /// this lock and go on to write.
+    Reserved,
+    /// Writing the database file itself, which no other connection may then read
+    Exclusive,
+}
+
+/// Advisory locks held by one connection on a database file.
+///
+/// Shared and exclusive locks are taken on the database file, and a reserved lock on a `-lock` file
+/// next to it. An exclusive lock may only be taken while holding the reserved one, which means
+/// that two connections never wait on each other for it. Unlike SQLite, there is no pending
+/// state, so a writer may have to wait out a steady stream of readers.
+pub struct Lock {
+    db: File,
+    reserved: File,
+    level: LockLevel,
+    /// How long to keep retrying a lock held by another connection before giving up
+    timeout: Duration,
+}
+
+impl Lock {
+    pub fn open(db_path: &Path, timeout: Duration) -> Result<Self> {
+        let mut path = OsString::from(db_path);
+        path.push("-lock");
+        let reserved = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self {
+            db: File::open(db_path)?,
+            reserved,
+            level: LockLevel::Unlocked,
+            timeout,
+        })
+    }
+
+    pub fn level(&self) -> LockLevel {
+        self.level
+    }
+
+    /// Raise the lock to at least the given level, waiting for other connections to release theirs
+    /// for as long as the busy timeout allows
+    pub fn acquire(&mut self, level: LockLevel) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let mut backoff = Duration::from_millis(1);
+        while !self.try_acquire(level)? {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::DatabaseLocked);
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        Ok(())
+    }
+
+    /// Raise the lock to at least the given level if no other connection is in the way, returning
+    /// whether it was. Levels reached on the way are kept either way.
+    pub fn try_acquire(&mut self, level: LockLevel) -> Result<bool> {
+        while self.level < level {
+            let acquired = match self.level {
+                LockLevel::Unlocked => held(self.db.try_lock_shared())?,
+                LockLevel::Shared => held(self.reserved.try_lock())?,
+                LockLevel::Reserved => {
+                    let acquired = held(self.db.try_lock())?;
+                    if !acquired {
+                        // converting a lock may drop it when the conversion fails, but holding the
+                        // reserved lock means no one else can be taking an exclusive one
+                        self.db.lock_shared()?;
+                    }
+                    acquired
+                }
+                LockLevel::Exclusive => unreachable!(),
+            };
+            if !acquired {
+                return Ok(false);
+            }
+            self.level = match self.level {
+                LockLevel::Unlocked => LockLevel::Shared,
+                LockLevel::Shared => LockLevel::Reserved,
+                _ => LockLevel::Exclusive,
+            };
+        }
+        Ok(true)
+    }
+
+    /// Lower the lock to at most the given level
+    pub fn release(&mut self, level: LockLevel) -> Result<()> {
+        if self.level == LockLevel::Exclusive && level < LockLevel::Exclusive {
+            self.db.lock_shared()?;
+        }
+        if self.level >= LockLevel::Reserved && level < LockLevel::Reserved {
+            self.reserved.unlock()?;
+        }
+        if self.level >= LockLevel::Shared && level < LockLevel::Shared {
+            self.db.unlock()?;
+        }
+        self.level = self.level.min(level);
+        Ok(())
+    }
+}
+
+/// Whether a lock was taken, as opposed to being held elsewhere
+fn held(result: std::result::Result<(), TryLockError>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(error)) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+
+    fn locks(file: &NamedTempFile, timeout: Duration) -> (Lock, Lock) {
+        std::fs::write(file.path(), "").unwrap();
+        (
+            Lock::open(file.path(), timeout).unwrap(),
+            Lock::open(file.path(), timeout).unwrap(),
+        )
+    }
+
+    #[test]
+    fn readers_share() {
+        let file = NamedTempFile::new("readers_share.flake").unwrap();
+        let (mut a, mut b) = locks(&file, Duration::ZERO);
+        a.acquire(LockLevel::Shared).unwrap();
+        b.acquire(LockLevel::Shared).unwrap();
+        a.acquire(LockLevel::Reserved).unwrap();
+        // one writer at a time, but readers may carry on
+        assert!(matches!(
+            b.acquire(LockLevel::Reserved),
+            Err(Error::DatabaseLocked)
+        ));
+        assert_eq!(b.level(), LockLevel::Shared);
+        assert!(!a.try_acquire(LockLevel::Exclusive).unwrap());
+        assert_eq!(a.level(), LockLevel::Reserved);
+        b.release(LockLevel::Unlocked).unwrap();
+        assert!(a.try_acquire(LockLevel::Exclusive).unwrap());
+        assert!(!b.try_acquire(LockLevel::Shared).unwrap());
+        a.release(LockLevel::Shared).unwrap();
+        b.acquire(LockLevel::Shared).unwrap();
+        a.release(LockLevel::Unlocked).unwrap();
+        b.acquire(LockLevel::Exclusive).unwrap();
+    }
+
+    #[test]
+    fn waits_for_busy_timeout() {
+        let file = NamedTempFile::new("waits_for_busy_timeout.flake").unwrap();
+        let timeout = Duration::from_millis(100);
+        let (mut a, mut b) = locks(&file, timeout);
+        a.acquire(LockLevel::Exclusive).unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            b.acquire(LockLevel::Shared),
+            Err(Error::DatabaseLocked)
+        ));
+        assert!(start.elapsed() >= timeout);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            a.release(LockLevel::Unlocked).unwrap();
+        });
+        b.acquire(LockLevel::Shared).unwrap();
+        handle.join().unwrap();
+    }
+}
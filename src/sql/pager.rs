@@ -1,9 +1,17 @@
-use crate::sql::Result;
-use std::cell::{Ref, RefCell, RefMut};
-use std::fs::{File, OpenOptions};
+use crate::sql::header::Header;
+use crate::sql::journal::Journal;
+use crate::sql::lock::{Lock, LockLevel};
+use crate::sql::wal::Wal;
+use crate::sql::{Error, Result};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::iter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicUsize};
+use std::time::Duration;
+use std::{env, iter, process};
 
 struct PageFile {
     file: File,
@@ -16,6 +24,7 @@ impl PageFile {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path)?;
         let len = file.seek(SeekFrom::End(0))? as usize;
         file.seek(SeekFrom::Start(0))?;
@@ -29,89 +38,952 @@ impl PageFile {
         }
         Ok(())
     }
+
+    fn shrink(&mut self, new_len: usize) -> Result<()> {
+        if new_len < self.len {
+            self.file.set_len(new_len as u64)?;
+            self.len = new_len;
+        }
+        Ok(())
+    }
+}
+
+/// Where pages live when they are not held in the cache
+enum Storage {
+    File(PageFile),
+    /// Pages evicted from an in-memory database, keyed by page index, as of the last commit and
+    /// since then
+    Memory {
+        committed: HashMap<usize, Page>,
+        pending: HashMap<usize, Page>,
+    },
+}
+
+impl Storage {
+    fn read(&mut self, index: usize) -> Result<Page> {
+        match self {
+            Self::File(file) => {
+                let offset = index * PAGE_SIZE;
+                if offset + PAGE_SIZE <= file.len {
+                    Page::from_file(file, offset)
+                } else {
+                    Ok(Page::new())
+                }
+            }
+            Self::Memory { committed, pending } => Ok(pending
+                .get(&index)
+                .or_else(|| committed.get(&index))
+                .cloned()
+                .unwrap_or_else(Page::new)),
+        }
+    }
+
+    fn write(&mut self, index: usize, page: &Page) -> Result<()> {
+        match self {
+            Self::File(file) => page.to_file(file, index * PAGE_SIZE),
+            Self::Memory { pending, .. } => {
+                pending.insert(index, page.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Keep the pages written to memory since the last commit
+    fn commit(&mut self) {
+        if let Self::Memory { committed, pending } = self {
+            committed.extend(pending.drain());
+        }
+    }
+
+    /// Forget the pages written to memory since the last commit
+    fn rollback(&mut self) {
+        if let Self::Memory { pending, .. } = self {
+            pending.clear();
+        }
+    }
+}
+
+/// How a database file is kept consistent while a transaction modifies it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Modified pages are appended to a write-ahead log and copied into the file by checkpoints
+    #[default]
+    Wal,
+    /// Modified pages are written over the file once the originals are saved to a rollback journal
+    Rollback,
+}
+
+impl FromStr for JournalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wal" => Ok(Self::Wal),
+            "rollback" => Ok(Self::Rollback),
+            _ => Err(format!(
+                "unknown journal mode {} (expected wal or rollback)",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for opening a database file
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// How the file is kept consistent while a transaction modifies it, if this connection creates
+    /// the database. An existing database stays in the mode recorded in its header, which every
+    /// connection follows.
+    pub journal_mode: JournalMode,
+    /// Whether commits wait for their data to reach the disk. Without syncing, commits are much
+    /// faster and still survive the process crashing, but a power loss may undo or corrupt them.
+    pub sync: bool,
+    /// How long to keep retrying when another connection holds a conflicting lock, before failing
+    /// with [`Error::DatabaseLocked`]
+    pub busy_timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            journal_mode: JournalMode::default(),
+            sync: true,
+            busy_timeout: Duration::ZERO,
+        }
+    }
+}
+
+/// Where the modifications made to a database file since the last commit are tracked
+enum Log {
+    Wal(Wal),
+    Journal(Journal),
+}
+
+/// Cached copy of a page along with whether it was modified since it was loaded
+struct Frame {
+    index: usize,
+    page: Page,
+    dirty: bool,
+}
+
+/// Originals of the pages modified since a savepoint was set, for undoing a single statement
+/// without the rest of its transaction
+struct Savepoint {
+    header: Header,
+    /// Page of the scratch file holding the original of each page modified since
+    originals: HashMap<usize, usize>,
+    /// Scratch file holding the originals, created once the first one is saved
+    scratch: Option<Box<Pager>>,
 }
 
+/// Page cache over a database file.
+///
+/// Pages are loaded on demand into a fixed number of slots. When every slot is occupied, the least
+/// recently used page that is not currently borrowed is evicted, being written back first if dirty.
+///
+/// Connections to the same file coordinate through advisory locks. Reading takes a shared lock,
+/// after which commits made by other connections are picked up; writing takes the reserved lock
+/// that only one connection may hold; and writing over the database file itself, as checkpoints
+/// and rollback mode do, waits for an exclusive lock. Every lock is released by the end of the
+/// transaction.
+///
+/// In WAL mode, database files never have pages written over them directly. Modified pages go to a
+/// write-ahead log instead, which a commit syncs and a checkpoint later copies into the database
+/// file. In rollback mode, pages are written over the file once their originals are safely in a
+/// rollback journal, which a commit deletes. The mode is recorded in the header when the database
+/// is created, and every connection follows it whatever mode it was opened in, since connections
+/// in different modes would not see each other's commits.
 pub struct Pager {
-    pages: Vec<RefCell<Option<Page>>>,
-    file: Option<RefCell<PageFile>>,
+    slots: Vec<RefCell<Option<Frame>>>,
+    last_used: Vec<Cell<u64>>,
+    clock: Cell<u64>,
+    lookup: RefCell<HashMap<usize, usize>>,
+    storage: RefCell<Storage>,
+    /// Database file, if the database is stored in one
+    path: Option<PathBuf>,
+    /// Record of modified pages, for databases stored in a file
+    log: RefCell<Option<Log>>,
+    /// Locks on the database file, for coordinating with other connections to it
+    lock: RefCell<Option<Lock>>,
+    savepoint: RefCell<Option<Savepoint>>,
+    header: Cell<Header>,
+    created: bool,
+    sync: bool,
+    /// Set once closed explicitly, so that dropping the pager does not close it again
+    closed: bool,
 }
 
 impl Pager {
     pub fn open(path: Option<&PathBuf>) -> Result<Self> {
-        let file = if let Some(path) = path {
-            Some(RefCell::new(PageFile::open(path)?))
+        Self::with_cache_size(path, DEFAULT_CACHE_SIZE)
+    }
+
+    pub fn with_cache_size(path: Option<&PathBuf>, cache_size: usize) -> Result<Self> {
+        Self::with_options(path, &Options::default(), cache_size)
+    }
+
+    pub fn with_options(
+        path: Option<&PathBuf>,
+        options: &Options,
+        cache_size: usize,
+    ) -> Result<Self> {
+        let (storage, log, lock) = if let Some(path) = path {
+            let mut file = PageFile::open(path)?;
+            let mut lock = Lock::open(path, options.busy_timeout)?;
+            lock.acquire(LockLevel::Shared)?;
+            // before anything is read, a transaction interrupted by a crash is undone if it was
+            // journaled, or replayed if it was committed to a log, whatever the mode now
+            let mut journal = Journal::new(path).with_sync(options.sync);
+            if journal.is_hot() {
+                lock.acquire(LockLevel::Exclusive)?;
+                journal.rollback(&mut file.file)?;
+            }
+            let mut log = match options.journal_mode {
+                JournalMode::Wal => Log::Wal(Wal::open(path)?.with_sync(options.sync)),
+                JournalMode::Rollback => Log::Journal(journal),
+            };
+            file.len = file.file.metadata()?.len() as usize;
+            if file.len > 0 && file.len < PAGE_SIZE {
+                return Err(Error::NotADatabase);
+            }
+            follow_journal_mode(path, options.sync, &mut log, &mut file)?;
+            (Storage::File(file), Some(log), Some(lock))
         } else {
-            None
+            let storage = Storage::Memory {
+                committed: HashMap::new(),
+                pending: HashMap::new(),
+            };
+            (storage, None, None)
+        };
+        Self::with_storage(path.cloned(), storage, log, lock, cache_size, options.sync)
+    }
+
+    /// Pager over a scratch file, deleted as soon as it is opened, for data that only lives as long
+    /// as the pager, such as sorted runs spilled out of memory. Pages evicted from the cache are
+    /// written straight to the file, and nothing is ever locked or committed.
+    pub fn temporary(cache_size: usize) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "flakedb-{}-{}.tmp",
+            process::id(),
+            COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
+        );
+        let path = env::temp_dir().join(name);
+        let file = PageFile::open(&path)?;
+        fs::remove_file(&path)?;
+        Self::with_storage(None, Storage::File(file), None, None, cache_size, false)
+    }
+
+    fn with_storage(
+        path: Option<PathBuf>,
+        storage: Storage,
+        log: Option<Log>,
+        lock: Option<Lock>,
+        cache_size: usize,
+        sync: bool,
+    ) -> Result<Self> {
+        let mut pager = Self {
+            slots: iter::repeat_with(|| RefCell::new(None))
+                .take(cache_size)
+                .collect(),
+            last_used: iter::repeat_with(|| Cell::new(0))
+                .take(cache_size)
+                .collect(),
+            clock: Cell::new(0),
+            lookup: RefCell::new(HashMap::new()),
+            storage: RefCell::new(storage),
+            path,
+            log: RefCell::new(log),
+            lock: RefCell::new(lock),
+            savepoint: RefCell::new(None),
+            header: Cell::new(Header::default()),
+            created: false,
+            sync,
+            closed: false,
         };
-        Ok(Self {
-            pages: iter::repeat_with(|| RefCell::new(None)).take(MAX_PAGES).collect(),
-            file,
+        match pager.committed_header()? {
+            Some(header) => pager.header.set(header),
+            None => {
+                pager.created = true;
+                pager.write_header(pager.empty_header())?;
+            }
+        }
+        pager.unlock(LockLevel::Unlocked)?;
+        Ok(pager)
+    }
+
+    /// Whether the pager was opened on a database with nothing committed yet (or in memory) and
+    /// wrote a fresh header
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    pub fn header(&self) -> Header {
+        self.header.get()
+    }
+
+    /// How the database is kept consistent, as recorded in it if anything was committed yet
+    pub fn journal_mode(&self) -> JournalMode {
+        match &*self.log.borrow() {
+            Some(Log::Journal(_)) => JournalMode::Rollback,
+            _ => JournalMode::Wal,
+        }
+    }
+
+    /// Header of a database with nothing committed yet, which records the current journal mode
+    fn empty_header(&self) -> Header {
+        Header {
+            journal_mode: self.journal_mode(),
+            ..Header::default()
+        }
+    }
+
+    fn write_header(&self, header: Header) -> Result<()> {
+        header.write(self.borrow_page_mut(HEADER_PAGE)?.as_mut_slice());
+        self.header.set(header);
+        Ok(())
+    }
+
+    /// Number of pages in use, whether or not they have been written to disk yet
+    pub fn num_pages(&self) -> usize {
+        self.header.get().page_count
+    }
+
+    /// Reserve a page, reusing a freed one if possible or else growing the file, and return its
+    /// index
+    pub fn allocate_page(&self) -> Result<usize> {
+        let mut header = self.header.get();
+        let index = if header.free_list != 0 {
+            let index = header.free_list;
+            let mut page = self.borrow_page_mut(index)?;
+            header.free_list = read_link(page.as_slice());
+            header.free_count -= 1;
+            page.as_mut_slice().fill(0);
+            index
+        } else {
+            header.page_count += 1;
+            header.page_count - 1
+        };
+        self.write_header(header)?;
+        Ok(index)
+    }
+
+    /// Release a page that is no longer part of any tree so it can be allocated again, by pushing
+    /// it onto the free-page list
+    pub fn free_page(&self, index: usize) -> Result<()> {
+        debug_assert!(index != HEADER_PAGE && index < self.num_pages());
+        let mut header = self.header.get();
+        let mut page = self.borrow_page_mut(index)?;
+        page.as_mut_slice()[..4].copy_from_slice(&(header.free_list as u32).to_be_bytes());
+        drop(page);
+        header.free_list = index;
+        header.free_count += 1;
+        self.write_header(header)
+    }
+
+    /// Empty the free-page list, returning the pages it held, in no particular order
+    pub fn take_free_pages(&self) -> Result<Vec<usize>> {
+        let mut header = self.header.get();
+        let mut pages = Vec::with_capacity(header.free_count);
+        while header.free_list != 0 {
+            pages.push(header.free_list);
+            header.free_list = read_link(self.borrow_page(header.free_list)?.as_slice());
+        }
+        header.free_count = 0;
+        self.write_header(header)?;
+        Ok(pages)
+    }
+
+    /// Cut the database down to its first `page_count` pages, which must hold everything still in
+    /// use. The file itself is truncated once the change is committed.
+    pub fn truncate(&self, page_count: usize) -> Result<()> {
+        debug_assert!(page_count <= self.num_pages());
+        self.forget_pages_from(page_count);
+        let mut header = self.header.get();
+        header.page_count = page_count;
+        self.write_header(header)
+    }
+
+    /// Drop the cached pages numbered `page_count` or above, so that they are never written back,
+    /// even if modified
+    fn forget_pages_from(&self, page_count: usize) {
+        for slot in &self.slots {
+            let mut frame = slot.borrow_mut();
+            if frame
+                .as_ref()
+                .is_some_and(|frame| frame.index >= page_count)
+            {
+                self.lookup
+                    .borrow_mut()
+                    .remove(&frame.as_ref().unwrap().index);
+                *frame = None;
+            }
+        }
+    }
+
+    /// Start tracking the modifications made from now on, so that they alone can be undone by
+    /// [`Pager::rollback_to_savepoint`]. Replaces any savepoint already set.
+    pub fn savepoint(&self) {
+        *self.savepoint.borrow_mut() = Some(Savepoint {
+            header: self.header.get(),
+            originals: HashMap::new(),
+            scratch: None,
+        });
+    }
+
+    /// Keep the modifications made since the savepoint, leaving them to the transaction
+    pub fn release_savepoint(&self) {
+        *self.savepoint.borrow_mut() = None;
+    }
+
+    /// Undo the modifications made since the savepoint, which is then released, while keeping
+    /// those made before it
+    pub fn rollback_to_savepoint(&self) -> Result<()> {
+        let Some(savepoint) = self.savepoint.borrow_mut().take() else {
+            return Ok(());
+        };
+        // pages allocated since are past the end of the database again
+        self.forget_pages_from(savepoint.header.page_count);
+        if let Some(scratch) = &savepoint.scratch {
+            for (&index, &saved) in &savepoint.originals {
+                let original = scratch.borrow_page(saved)?;
+                self.borrow_page_mut(index)?
+                    .as_mut_slice()
+                    .copy_from_slice(original.as_slice());
+            }
+        }
+        self.header.set(savepoint.header);
+        Ok(())
+    }
+
+    /// Save the original of a page about to be modified for the first time since the savepoint,
+    /// unless it was allocated since
+    fn save_original(&self, index: usize, slot: usize) -> Result<()> {
+        let mut savepoint = self.savepoint.borrow_mut();
+        let Some(savepoint) = savepoint.as_mut() else {
+            return Ok(());
+        };
+        if index >= savepoint.header.page_count || savepoint.originals.contains_key(&index) {
+            return Ok(());
+        }
+        let scratch = match &mut savepoint.scratch {
+            Some(scratch) => scratch,
+            None => savepoint
+                .scratch
+                .insert(Box::new(Pager::temporary(SAVEPOINT_CACHE_SIZE)?)),
+        };
+        let saved = scratch.allocate_page()?;
+        let frame = self.slots[slot].borrow();
+        scratch
+            .borrow_page_mut(saved)?
+            .as_mut_slice()
+            .copy_from_slice(frame.as_ref().unwrap().page.as_slice());
+        savepoint.originals.insert(index, saved);
+        Ok(())
+    }
+
+    /// Record a change to the catalog in the header
+    pub fn bump_schema_cookie(&self) -> Result<()> {
+        let mut header = self.header.get();
+        header.schema_cookie = header.schema_cookie.wrapping_add(1);
+        self.write_header(header)
+    }
+
+    /// Header as last committed, or None if nothing was ever committed to the database
+    fn committed_header(&self) -> Result<Option<Header>> {
+        let page = self.load(HEADER_PAGE)?;
+        if page.as_slice().iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        Header::read(page.as_slice()).map(Some)
+    }
+
+    fn lock_level(&self) -> LockLevel {
+        // an in-memory database has a single connection, which may as well hold every lock
+        self.lock
+            .borrow()
+            .as_ref()
+            .map_or(LockLevel::Exclusive, Lock::level)
+    }
+
+    /// Raise the lock on the database file, waiting for other connections up to the busy timeout
+    fn lock(&self, level: LockLevel) -> Result<()> {
+        match self.lock.borrow_mut().as_mut() {
+            Some(lock) => lock.acquire(level),
+            None => Ok(()),
+        }
+    }
+
+    /// Raise the lock on the database file if no other connection is in the way
+    fn try_lock(&self, level: LockLevel) -> Result<bool> {
+        match self.lock.borrow_mut().as_mut() {
+            Some(lock) => lock.try_acquire(level),
+            None => Ok(true),
+        }
+    }
+
+    fn unlock(&self, level: LockLevel) -> Result<()> {
+        match self.lock.borrow_mut().as_mut() {
+            Some(lock) => lock.release(level),
+            None => Ok(()),
+        }
+    }
+
+    /// Start reading, unless already doing so. Returns whether other connections committed since
+    /// this one last read, in which case every cached page was dropped.
+    pub fn begin_read(&self) -> Result<bool> {
+        if self.lock_level() >= LockLevel::Shared {
+            return Ok(false);
+        }
+        self.lock(LockLevel::Shared)?;
+        self.refresh().inspect_err(|_| {
+            let _ = self.unlock(LockLevel::Unlocked);
         })
     }
 
-    pub fn len(&self) -> usize {
-        self.file.as_ref().map_or(0, |file| file.borrow().len)
+    /// Stop reading, unless writing as well
+    pub fn end_read(&self) -> Result<()> {
+        if self.lock_level() == LockLevel::Shared {
+            self.unlock(LockLevel::Unlocked)?;
+        }
+        Ok(())
     }
 
-    fn load_page_if_missing(&self, index: usize) -> Result<()> {
-        if index > MAX_PAGES {
-            panic!("page {} out of bounds (max {})", index, MAX_PAGES);
+    /// Start writing, unless already doing so, which only one connection may do at a time.
+    /// Returns whether other connections committed since this one last read, in which case every
+    /// cached page was dropped.
+    pub fn begin_write(&self) -> Result<bool> {
+        let changed = self.begin_read()?;
+        if self.lock_level() >= LockLevel::Reserved {
+            return Ok(changed);
         }
-        let mut page = self.pages[index].borrow_mut();
-        if page.is_none() {
-            *page = Some(if let Some(file) = &self.file {
-                let mut file = file.borrow_mut();
-                let offset = index * PAGE_SIZE;
-                if offset + PAGE_SIZE <= file.len {
-                    Page::from_file(&mut file, offset)?
-                } else {
-                    Page::new()
+        self.lock(LockLevel::Reserved)?;
+        // in WAL mode, another connection may have committed since the shared lock was taken
+        Ok(self.refresh()? || changed)
+    }
+
+    /// Catch up with the commits of other connections, dropping cached pages if there were any
+    fn refresh(&self) -> Result<bool> {
+        let level = self.lock_level();
+        {
+            let mut log = self.log.borrow_mut();
+            let mut storage = self.storage.borrow_mut();
+            let Storage::File(file) = &mut *storage else {
+                return Ok(false);
+            };
+            if let Some(Log::Journal(journal)) = log.as_mut() {
+                if journal.is_hot() {
+                    // a crashed writer left its journal behind, which is rolled back with no one
+                    // else reading
+                    self.lock(LockLevel::Exclusive)?;
+                    journal.rollback(&mut file.file)?;
+                    self.unlock(level)?;
                 }
-            } else {
-                Page::new()
-            });
+            }
+            file.len = file.file.metadata()?.len() as usize;
+            if let (Some(log), Some(path)) = (log.as_mut(), &self.path) {
+                follow_journal_mode(path, self.sync, log, file)?;
+            }
+        }
+        let header = match self.committed_header()? {
+            Some(header) if header.change_counter != self.header.get().change_counter => header,
+            _ => return Ok(false),
+        };
+        self.clear_cache();
+        self.header.set(header);
+        Ok(true)
+    }
+
+    fn clear_cache(&self) {
+        for slot in &self.slots {
+            *slot.borrow_mut() = None;
+        }
+        self.lookup.borrow_mut().clear();
+    }
+
+    /// Slots holding pages modified since they were loaded
+    fn dirty_slots(&self) -> Vec<usize> {
+        (0..self.slots.len())
+            .filter(|&slot| {
+                let frame = self.slots[slot].borrow();
+                frame.as_ref().is_some_and(|frame| frame.dirty)
+            })
+            .collect()
+    }
+
+    /// Make every modification since the last commit durable, by appending the modified pages to
+    /// the log and syncing it, or by writing them over the database file and deleting the journal
+    /// holding their originals. Ends the transaction, releasing every lock.
+    pub fn commit(&self) -> Result<()> {
+        self.release_savepoint();
+        let pending = match &*self.log.borrow() {
+            Some(Log::Wal(wal)) => wal.has_uncommitted(),
+            Some(Log::Journal(journal)) => journal.is_active(),
+            None => false,
         };
+        if pending || !self.dirty_slots().is_empty() {
+            self.lock(LockLevel::Reserved)?;
+            // other connections notice the commit by the header changing, which also makes sure
+            // there is a page to mark as the commit
+            let mut header = self.header.get();
+            header.change_counter = header.change_counter.wrapping_add(1);
+            self.write_header(header)?;
+            self.write_dirty()?;
+        }
+        self.unlock(LockLevel::Unlocked)
+    }
+
+    fn write_dirty(&self) -> Result<()> {
+        let dirty = self.dirty_slots();
+        let mut log = self.log.borrow_mut();
+        let mut storage = self.storage.borrow_mut();
+        match (log.as_mut(), &mut *storage) {
+            (Some(Log::Wal(wal)), Storage::File(file)) => {
+                self.commit_to_wal(wal, &dirty)?;
+                // readers may be using the log, so it is only emptied when none are
+                if wal.frame_count() >= CHECKPOINT_FRAMES && self.try_lock(LockLevel::Exclusive)? {
+                    checkpoint(wal, file, self.sync)?;
+                }
+            }
+            (Some(Log::Journal(journal)), Storage::File(file)) => {
+                self.lock(LockLevel::Exclusive)?;
+                for &slot in &dirty {
+                    let mut frame = self.slots[slot].borrow_mut();
+                    let frame = frame.as_mut().unwrap();
+                    overwrite(journal, file, frame.index, &frame.page)?;
+                    frame.dirty = false;
+                }
+                truncate(journal, file, self.num_pages())?;
+                if self.sync {
+                    file.file.sync_all()?;
+                }
+                journal.commit()?;
+            }
+            (_, storage) => {
+                // an in-memory database only has to keep its modified pages around
+                for &slot in &dirty {
+                    let mut frame = self.slots[slot].borrow_mut();
+                    let frame = frame.as_mut().unwrap();
+                    storage.write(frame.index, &frame.page)?;
+                    frame.dirty = false;
+                }
+                storage.commit();
+            }
+        }
+        Ok(())
+    }
+
+    /// Append the modified pages to the log, the last one marked as the commit
+    fn commit_to_wal(&self, wal: &mut Wal, dirty: &[usize]) -> Result<()> {
+        let page_count = self.num_pages();
+        for (i, &slot) in dirty.iter().enumerate() {
+            let mut frame = self.slots[slot].borrow_mut();
+            let frame = frame.as_mut().unwrap();
+            let commit = (i + 1 == dirty.len()).then_some(page_count);
+            wal.append(frame.index, &frame.page, commit)?;
+            frame.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Undo every modification since the last commit. Ends the transaction, releasing every lock.
+    pub fn rollback(&self) -> Result<()> {
+        self.release_savepoint();
+        // cached pages may have been modified or loaded from uncommitted frames, so drop them all
+        self.clear_cache();
+        {
+            let mut log = self.log.borrow_mut();
+            let mut storage = self.storage.borrow_mut();
+            match (log.as_mut(), &mut *storage) {
+                (Some(Log::Wal(wal)), _) => {
+                    wal.rollback()?;
+                    // without a lock, other connections may have emptied the log since this one
+                    // last read it
+                    if self.lock_level() < LockLevel::Shared {
+                        wal.refresh()?;
+                    }
+                }
+                (Some(Log::Journal(journal)), Storage::File(file)) => {
+                    if journal.is_active() {
+                        journal.rollback(&mut file.file)?;
+                        file.len = file.file.metadata()?.len() as usize;
+                    }
+                }
+                (_, storage) => storage.rollback(),
+            }
+        }
+        match self.committed_header()? {
+            Some(header) => self.header.set(header),
+            None => self.write_header(self.empty_header())?,
+        }
+        self.unlock(LockLevel::Unlocked)
+    }
+
+    /// Copy every committed page held in the log into the database file, so that the file holds
+    /// the whole database by itself. Uncommitted modifications are left alone.
+    pub fn flush(&self) -> Result<()> {
+        if !matches!(&*self.log.borrow(), Some(Log::Wal(_))) {
+            return Ok(());
+        }
+        let level = self.lock_level();
+        self.lock(LockLevel::Exclusive)?;
+        let result = self.checkpoint_latest(level);
+        self.unlock(level)?;
+        result
+    }
+
+    /// Checkpoint while holding the exclusive lock, first catching up with the log unless this
+    /// connection was writing and so already up to date
+    fn checkpoint_latest(&self, level: LockLevel) -> Result<()> {
+        if level < LockLevel::Reserved {
+            // other connections may have committed to the log, or checkpointed and emptied it,
+            // since this one last read it
+            self.refresh()?;
+        }
+        self.checkpoint()
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        let mut log = self.log.borrow_mut();
+        let mut storage = self.storage.borrow_mut();
+        if let (Some(Log::Wal(wal)), Storage::File(file)) = (log.as_mut(), &mut *storage) {
+            checkpoint(wal, file, self.sync)?;
+        }
         Ok(())
     }
 
-    pub fn borrow_page(&self, index: usize) -> Result<Ref<Page>> {
-        self.load_page_if_missing(index)?;
-        Ok(Ref::map(self.pages[index].borrow(), |page| page.as_ref().unwrap()))
+    /// Undo any uncommitted modifications, flush the log into the database file and delete it
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.shut_down()
+    }
+
+    fn shut_down(&self) -> Result<()> {
+        self.rollback()?;
+        // the log is left for other connections still using it
+        if matches!(&*self.log.borrow(), Some(Log::Wal(_)))
+            && self.try_lock(LockLevel::Exclusive)?
+        {
+            self.checkpoint_latest(LockLevel::Unlocked)?;
+            if let Some(Log::Wal(wal)) = self.log.borrow_mut().as_mut() {
+                wal.remove()?;
+            }
+        }
+        self.unlock(LockLevel::Unlocked)
+    }
+
+    /// Read a page from the log if it has a version there, or else from storage
+    fn load(&self, index: usize) -> Result<Page> {
+        if let Some(Log::Wal(wal)) = self.log.borrow_mut().as_mut() {
+            if let Some(page) = wal.read(index)? {
+                return Ok(page);
+            }
+        }
+        self.storage.borrow_mut().read(index)
+    }
+
+    /// Write back an evicted page without committing it: to the log, over the database file once
+    /// the original is journaled, or to memory
+    fn store(&self, index: usize, page: &Page) -> Result<()> {
+        let mut log = self.log.borrow_mut();
+        let mut storage = self.storage.borrow_mut();
+        match (log.as_mut(), &mut *storage) {
+            (Some(Log::Wal(wal)), _) => {
+                self.lock(LockLevel::Reserved)?;
+                wal.append(index, page, None)
+            }
+            (Some(Log::Journal(journal)), Storage::File(file)) => {
+                self.lock(LockLevel::Exclusive)?;
+                overwrite(journal, file, index, page)
+            }
+            (_, storage) => storage.write(index, page),
+        }
+    }
+
+    /// Return the cache slot holding the page, loading it (and evicting another) if necessary
+    fn slot(&self, index: usize) -> Result<usize> {
+        let slot = self.lookup.borrow().get(&index).copied();
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                let slot = self.evict()?;
+                let page = self.load(index)?;
+                *self.slots[slot].borrow_mut() = Some(Frame {
+                    index,
+                    page,
+                    dirty: false,
+                });
+                self.lookup.borrow_mut().insert(index, slot);
+                slot
+            }
+        };
+        self.clock.set(self.clock.get() + 1);
+        self.last_used[slot].set(self.clock.get());
+        Ok(slot)
     }
 
-    pub fn borrow_page_mut(&self, index: usize) -> Result<RefMut<Page>> {
-        self.load_page_if_missing(index)?;
-        Ok(RefMut::map(self.pages[index].borrow_mut(), |page| page.as_mut().unwrap()))
+    /// Free up a cache slot, writing its page back to storage if it was modified
+    fn evict(&self) -> Result<usize> {
+        // slots that are currently borrowed cannot be reused, so skip them
+        let victim = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.try_borrow_mut().ok().map(|frame| (i, frame)))
+            .min_by_key(|(i, frame)| (frame.is_some(), self.last_used[*i].get()));
+        let (slot, mut frame) = match victim {
+            Some(victim) => victim,
+            None => panic!("page cache exhausted ({} pages borrowed)", self.slots.len()),
+        };
+        if let Some(evicted) = frame.take() {
+            self.lookup.borrow_mut().remove(&evicted.index);
+            if evicted.dirty {
+                if let Err(error) = self.store(evicted.index, &evicted.page) {
+                    // keep the page cached so the modification is not lost
+                    self.lookup.borrow_mut().insert(evicted.index, slot);
+                    *frame = Some(evicted);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(slot)
+    }
+
+    pub fn borrow_page(&self, index: usize) -> Result<Ref<'_, Page>> {
+        let slot = self.slot(index)?;
+        Ok(Ref::map(self.slots[slot].borrow(), |frame| {
+            &frame.as_ref().unwrap().page
+        }))
+    }
+
+    pub fn borrow_page_mut(&self, index: usize) -> Result<RefMut<'_, Page>> {
+        let slot = self.slot(index)?;
+        self.save_original(index, slot)?;
+        Ok(RefMut::map(self.slots[slot].borrow_mut(), |frame| {
+            let frame = frame.as_mut().unwrap();
+            frame.dirty = true;
+            &mut frame.page
+        }))
     }
 }
 
 impl Drop for Pager {
     fn drop(&mut self) {
-        if let Some(file) = &self.file {
-            let mut file = file.borrow_mut();
-            for (i, page) in self.pages.iter().enumerate() {
-                let page = page.borrow();
-                if let Some(page) = page.as_ref() {
-                    if let Err(error) = page.to_file(&mut file, PAGE_SIZE * i) {
-                        eprintln!(
-                            "WARN: possible data loss. Error flushing page {} to disk ({}).",
-                            i, error
-                        );
-                    }
-                }
+        // committed transactions are already durable and anything left in a log or journal is
+        // recovered on the next open, so failing to tidy up here loses nothing
+        if !self.closed {
+            let _ = self.shut_down();
+        }
+    }
+}
+
+/// Write a page over the database file, once its original is durably saved in the journal. The
+/// exclusive lock must be held, so that no other connection sees the page half way.
+fn overwrite(journal: &mut Journal, file: &mut PageFile, index: usize, page: &Page) -> Result<()> {
+    journal.start(file.len / PAGE_SIZE)?;
+    if journal.needs(index) {
+        let original = Page::from_file(file, index * PAGE_SIZE)?;
+        journal.save(index, &original)?;
+    }
+    journal.sync()?;
+    page.to_file(file, index * PAGE_SIZE)
+}
+
+/// Switch to the journal mode recorded in the database, which every connection to it follows: WAL
+/// mode while a log exists, or else the mode in the header of the database file. A database with
+/// nothing committed yet keeps the mode it was opened in. Picks up the transactions committed to
+/// the log by other connections along the way.
+fn follow_journal_mode(path: &Path, sync: bool, log: &mut Log, file: &mut PageFile) -> Result<()> {
+    match log {
+        Log::Wal(wal) => {
+            wal.refresh()?;
+            if wal.exists() {
+                return Ok(());
+            }
+        }
+        Log::Journal(_) => {
+            let wal = Wal::open(path)?.with_sync(sync);
+            if wal.exists() {
+                *log = Log::Wal(wal);
+                return Ok(());
             }
         }
     }
+    if file.len < PAGE_SIZE {
+        return Ok(());
+    }
+    let header = Header::read(Page::from_file(file, HEADER_PAGE * PAGE_SIZE)?.as_slice())?;
+    match (header.journal_mode, &*log) {
+        (JournalMode::Wal, Log::Journal(_)) => *log = Log::Wal(Wal::open(path)?.with_sync(sync)),
+        (JournalMode::Rollback, Log::Wal(_)) => {
+            *log = Log::Journal(Journal::new(path).with_sync(sync))
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Next page in the free-page list, stored at the start of a free page
+fn read_link(page: &[u8]) -> usize {
+    u32::from_be_bytes(page[..4].try_into().unwrap()) as usize
 }
 
-#[derive(Debug)]
+/// Cut the database file down to a number of pages, once the originals of the pages cut off are
+/// durably saved in the journal
+fn truncate(journal: &mut Journal, file: &mut PageFile, page_count: usize) -> Result<()> {
+    let len = file.len / PAGE_SIZE;
+    if page_count >= len {
+        return Ok(());
+    }
+    journal.start(len)?;
+    for index in page_count..len {
+        if journal.needs(index) {
+            let original = Page::from_file(file, index * PAGE_SIZE)?;
+            journal.save(index, &original)?;
+        }
+    }
+    journal.sync()?;
+    file.shrink(page_count * PAGE_SIZE)
+}
+
+/// Copy the pages committed to a log into the database file, truncating it to the length the
+/// database had as of the last commit, then empty the log once the file is
+/// safely synced
+fn checkpoint(wal: &mut Wal, file: &mut PageFile, sync: bool) -> Result<()> {
+    let pages = wal.committed_pages();
+    if pages.is_empty() {
+        return Ok(());
+    }
+    let mut page_count = None;
+    for index in pages {
+        let page = wal.read_committed(index)?.unwrap();
+        page.to_file(file, index * PAGE_SIZE)?;
+        if index == HEADER_PAGE {
+            page_count = Some(Header::read(page.as_slice())?.page_count);
+        }
+    }
+    // pages past the end of the database as committed were cut off by a vacuum
+    if let Some(page_count) = page_count {
+        file.shrink(page_count * PAGE_SIZE)?;
+    }
+    if sync {
+        file.file.sync_all()?;
+    }
+    // frames of a transaction in progress must stay, so the log is only emptied between them
+    if wal.has_uncommitted() {
+        return Ok(());
+    }
+    wal.reset()
+}
+
+#[derive(Clone, Debug)]
 pub struct Page {
     data: Box<[u8; PAGE_SIZE]>,
 }
 
 impl Page {
     /// Create new page by taking ownership of vector
-    fn from_vec(data: Vec<u8>) -> Self {
+    pub fn from_vec(data: Vec<u8>) -> Self {
         if data.len() != PAGE_SIZE {
             panic!(
                 "Attempted to build page from {} bytes (page size is {}).",
@@ -127,21 +999,12 @@ impl Page {
 
     /// Create new page by zeroing memory
     fn new() -> Self {
-        let mut data: Vec<u8> = Vec::with_capacity(PAGE_SIZE);
-        data.resize(PAGE_SIZE, 0);
-        Self::from_vec(data)
+        Self::from_vec(vec![0; PAGE_SIZE])
     }
 
     /// Create new page by copying byte array
     fn from_file(file: &mut PageFile, offset: usize) -> Result<Self> {
-        let mut data = Vec::with_capacity(PAGE_SIZE);
-        data.resize(PAGE_SIZE, 0);
-        eprintln!(
-            "Reading {} bytes at offset {} (total {}).",
-            data.len(),
-            offset,
-            file.len
-        );
+        let mut data = vec![0; PAGE_SIZE];
         file.file.seek(SeekFrom::Start(offset as u64))?;
         file.file.read_exact(data.as_mut_slice())?;
         Ok(Self::from_vec(data))
@@ -150,13 +1013,7 @@ impl Page {
     fn to_file(&self, file: &mut PageFile, offset: usize) -> Result<()> {
         file.grow(offset + PAGE_SIZE)?;
         file.file.seek(SeekFrom::Start(offset as u64))?;
-        let bytes_written = file.file.write(self.data.as_slice())?;
-        if bytes_written != PAGE_SIZE {
-            panic!(
-                "Wrote {} bytes but PAGE_SIZE is {}.",
-                bytes_written, PAGE_SIZE
-            );
-        }
+        file.file.write_all(self.data.as_slice())?;
         Ok(())
     }
 
@@ -164,10 +1021,394 @@ impl Page {
         self.data.as_slice()
     }
 
-    pub fn as_mut_slice(&mut self) -> &mut[u8] {
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
         self.data.as_mut_slice()
     }
 }
 
 pub const PAGE_SIZE: usize = 4096;
-pub const MAX_PAGES: usize = 100;
+/// Page 0 holds the file header rather than tree nodes
+pub const HEADER_PAGE: usize = 0;
+pub const DEFAULT_CACHE_SIZE: usize = 100;
+/// Number of pages of the scratch file of a savepoint kept in memory
+const SAVEPOINT_CACHE_SIZE: usize = 16;
+/// Number of frames after which the log is checkpointed into the database file
+pub const CHECKPOINT_FRAMES: usize = 1000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+    use test_case::test_case;
+
+    fn fill_pages(pager: &Pager, count: usize) {
+        for _ in 0..count {
+            let index = pager.allocate_page().unwrap();
+            pager.borrow_page_mut(index).unwrap().as_mut_slice()[..8]
+                .copy_from_slice(&(index as u64).to_be_bytes());
+        }
+    }
+
+    fn check_pages(pager: &Pager, count: usize) {
+        for i in 1..=count {
+            let page = pager.borrow_page(i).unwrap();
+            assert_eq!(page.as_slice()[..8], (i as u64).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn evicts_in_memory() {
+        let pager = Pager::with_cache_size(None, 4).unwrap();
+        fill_pages(&pager, 50);
+        check_pages(&pager, 50);
+    }
+
+    #[test]
+    fn evicts_to_file() {
+        let file = NamedTempFile::new("evicts_to_file.flake").unwrap();
+        let path = file.path().to_path_buf();
+        {
+            let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+            fill_pages(&pager, 50);
+            check_pages(&pager, 50);
+            pager.commit().unwrap();
+        }
+        let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+        assert!(!pager.created());
+        assert_eq!(pager.num_pages(), 51);
+        check_pages(&pager, 50);
+    }
+
+    /// Abandon a pager as if its process had been killed: its locks go, but nothing is flushed
+    fn crash(pager: Pager) {
+        *pager.lock.borrow_mut() = None;
+        std::mem::forget(pager);
+    }
+
+    fn wal_path(file: &NamedTempFile) -> PathBuf {
+        let mut path = file.path().as_os_str().to_owned();
+        path.push("-wal");
+        path.into()
+    }
+
+    #[test]
+    fn commits_survive_crash() {
+        let file = NamedTempFile::new("commits_survive_crash.flake").unwrap();
+        let path = file.path().to_path_buf();
+        {
+            let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+            fill_pages(&pager, 20);
+            pager.commit().unwrap();
+            // later changes, partly spilled to the log by evictions, are never committed
+            for i in 1..=20 {
+                pager.borrow_page_mut(i).unwrap().as_mut_slice()[..8].fill(0xff);
+            }
+            fill_pages(&pager, 5);
+            crash(pager);
+        }
+        assert!(wal_path(&file).exists());
+        {
+            let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+            assert_eq!(pager.num_pages(), 21);
+            check_pages(&pager, 20);
+        }
+        assert!(!wal_path(&file).exists());
+    }
+
+    #[test]
+    fn hot_journal_rolled_back() {
+        let file = NamedTempFile::new("hot_journal_rolled_back.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let mut journal_path = path.as_os_str().to_owned();
+        journal_path.push("-journal");
+        {
+            let options = Options {
+                journal_mode: JournalMode::Rollback,
+                ..Options::default()
+            };
+            let pager = Pager::with_options(Some(&path), &options, 4).unwrap();
+            fill_pages(&pager, 20);
+            pager.commit().unwrap();
+            assert!(!PathBuf::from(&journal_path).exists());
+            // later changes, partly written over the file by evictions, are never committed
+            for i in 1..=20 {
+                pager.borrow_page_mut(i).unwrap().as_mut_slice()[..8].fill(0xff);
+            }
+            fill_pages(&pager, 5);
+            crash(pager);
+        }
+        assert!(PathBuf::from(&journal_path).exists());
+        {
+            let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+            assert_eq!(pager.num_pages(), 21);
+            check_pages(&pager, 20);
+        }
+        assert!(!PathBuf::from(&journal_path).exists());
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len() as usize,
+            21 * PAGE_SIZE
+        );
+    }
+
+    #[test]
+    fn clean_pages_not_written() {
+        let file = NamedTempFile::new("clean_pages_not_written.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let pager = Pager::with_cache_size(Some(&path), 4).unwrap();
+        fill_pages(&pager, 3);
+        pager.commit().unwrap();
+        let frames = |pager: &Pager| match pager.log.borrow().as_ref() {
+            Some(Log::Wal(wal)) => wal.frame_count(),
+            _ => unreachable!(),
+        };
+        let written = frames(&pager);
+        check_pages(&pager, 3);
+        pager.commit().unwrap();
+        assert_eq!(frames(&pager), written);
+        pager.borrow_page_mut(2).unwrap().as_mut_slice()[8] = 1;
+        pager.commit().unwrap();
+        // along with the header, for its change counter
+        assert_eq!(frames(&pager), written + 2);
+    }
+
+    #[test_case(JournalMode::Wal ; "wal")]
+    #[test_case(JournalMode::Rollback ; "rollback journal")]
+    fn connections_see_commits(journal_mode: JournalMode) {
+        let file = NamedTempFile::new("connections_see_commits.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = Options {
+            journal_mode,
+            ..Options::default()
+        };
+        let a = Pager::with_options(Some(&path), &options, 4).unwrap();
+        fill_pages(&a, 5);
+        a.commit().unwrap();
+        let b = Pager::with_options(Some(&path), &options, 4).unwrap();
+        assert!(!b.begin_read().unwrap());
+        check_pages(&b, 5);
+        b.end_read().unwrap();
+
+        assert!(!a.begin_write().unwrap());
+        a.borrow_page_mut(3).unwrap().as_mut_slice()[..8].fill(0xff);
+        // only one connection writes at a time
+        assert!(matches!(b.begin_write(), Err(Error::DatabaseLocked)));
+        b.end_read().unwrap();
+        a.commit().unwrap();
+
+        assert!(b.begin_write().unwrap());
+        assert_eq!(b.borrow_page(3).unwrap().as_slice()[..8], [0xff; 8]);
+        b.rollback().unwrap();
+    }
+
+    #[test_case(JournalMode::Wal, JournalMode::Rollback ; "wal")]
+    #[test_case(JournalMode::Rollback, JournalMode::Wal ; "rollback journal")]
+    fn connections_follow_recorded_journal_mode(created: JournalMode, other: JournalMode) {
+        let file = NamedTempFile::new("connections_follow_recorded_journal_mode.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = |journal_mode| Options {
+            journal_mode,
+            ..Options::default()
+        };
+        let a = Pager::with_options(Some(&path), &options(created), 4).unwrap();
+        fill_pages(&a, 5);
+        a.commit().unwrap();
+        let b = Pager::with_options(Some(&path), &options(other), 4).unwrap();
+        assert_eq!(b.journal_mode(), created);
+        assert_eq!(b.header().journal_mode, created);
+        b.begin_write().unwrap();
+        fill_pages(&b, 1);
+        b.commit().unwrap();
+        assert_eq!(wal_path(&file).exists(), created == JournalMode::Wal);
+        // the connection closed last still has to pick up the commits of the other
+        b.close().unwrap();
+        a.close().unwrap();
+        let c = Pager::with_options(Some(&path), &options(other), 4).unwrap();
+        assert_eq!(c.journal_mode(), created);
+        check_pages(&c, 6);
+    }
+
+    #[test]
+    fn first_commit_sets_journal_mode() {
+        let file = NamedTempFile::new("first_commit_sets_journal_mode.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = |journal_mode| Options {
+            journal_mode,
+            ..Options::default()
+        };
+        let a = Pager::with_options(Some(&path), &options(JournalMode::Wal), 4).unwrap();
+        let b = Pager::with_options(Some(&path), &options(JournalMode::Rollback), 4).unwrap();
+        assert_eq!(b.journal_mode(), JournalMode::Rollback);
+        fill_pages(&a, 5);
+        a.commit().unwrap();
+        // the other connection created the database first, in its own mode
+        assert!(b.begin_write().unwrap());
+        assert_eq!(b.journal_mode(), JournalMode::Wal);
+        check_pages(&b, 5);
+        b.rollback().unwrap();
+    }
+
+    #[test]
+    fn readers_block_journal_writes() {
+        let file = NamedTempFile::new("readers_block_journal_writes.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let options = Options {
+            journal_mode: JournalMode::Rollback,
+            ..Options::default()
+        };
+        let a = Pager::with_options(Some(&path), &options, 4).unwrap();
+        fill_pages(&a, 5);
+        a.commit().unwrap();
+        let b = Pager::with_options(Some(&path), &options, 4).unwrap();
+        b.begin_read().unwrap();
+        a.begin_write().unwrap();
+        a.borrow_page_mut(3).unwrap().as_mut_slice()[..8].fill(0xff);
+        assert!(matches!(a.commit(), Err(Error::DatabaseLocked)));
+        // the reader still sees the database as last committed
+        check_pages(&b, 5);
+        b.end_read().unwrap();
+        a.commit().unwrap();
+    }
+
+    #[test]
+    fn log_checkpointed_periodically() {
+        let file = NamedTempFile::new("log_checkpointed_periodically.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let pager = Pager::open(Some(&path)).unwrap();
+        for _ in 0..CHECKPOINT_FRAMES {
+            fill_pages(&pager, 1);
+            pager.commit().unwrap();
+        }
+        let len = std::fs::metadata(wal_path(&file)).unwrap().len() as usize;
+        assert!(len < CHECKPOINT_FRAMES * PAGE_SIZE);
+        assert!(std::fs::metadata(&path).unwrap().len() as usize >= CHECKPOINT_FRAMES * PAGE_SIZE);
+    }
+
+    #[test_case(None ; "memory")]
+    #[test_case(Some(JournalMode::Wal) ; "wal")]
+    #[test_case(Some(JournalMode::Rollback) ; "rollback journal")]
+    fn rollback_restores_pages(mode: Option<JournalMode>) {
+        let file = NamedTempFile::new("rollback_restores_pages.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let pager = match mode {
+            Some(journal_mode) => {
+                let options = Options {
+                    journal_mode,
+                    ..Options::default()
+                };
+                Pager::with_options(Some(&path), &options, 4).unwrap()
+            }
+            None => Pager::with_cache_size(None, 4).unwrap(),
+        };
+        fill_pages(&pager, 10);
+        pager.commit().unwrap();
+        for i in 1..=10 {
+            pager.borrow_page_mut(i).unwrap().as_mut_slice()[..8].fill(0xff);
+        }
+        fill_pages(&pager, 5);
+        pager.free_page(3).unwrap();
+        pager.rollback().unwrap();
+        assert_eq!(pager.num_pages(), 11);
+        assert_eq!(pager.allocate_page().unwrap(), 11);
+        // read twice, so that pages evicted after loading are read back again
+        check_pages(&pager, 10);
+        check_pages(&pager, 10);
+    }
+
+    #[test_case(None ; "memory")]
+    #[test_case(Some(JournalMode::Wal) ; "wal")]
+    #[test_case(Some(JournalMode::Rollback) ; "rollback journal")]
+    fn savepoint_undoes_statement(mode: Option<JournalMode>) {
+        let file = NamedTempFile::new("savepoint_undoes_statement.flake").unwrap();
+        let path = file.path().to_path_buf();
+        let pager = match mode {
+            Some(journal_mode) => {
+                let options = Options {
+                    journal_mode,
+                    ..Options::default()
+                };
+                Pager::with_options(Some(&path), &options, 4).unwrap()
+            }
+            None => Pager::with_cache_size(None, 4).unwrap(),
+        };
+        fill_pages(&pager, 10);
+        pager.commit().unwrap();
+        // modifications of the transaction from before the savepoint are kept
+        fill_pages(&pager, 5);
+        pager.savepoint();
+        for i in 1..=15 {
+            pager.borrow_page_mut(i).unwrap().as_mut_slice()[..8].fill(0xff);
+        }
+        fill_pages(&pager, 5);
+        pager.free_page(3).unwrap();
+        pager.rollback_to_savepoint().unwrap();
+        assert_eq!(pager.num_pages(), 16);
+        check_pages(&pager, 15);
+        check_pages(&pager, 15);
+        pager.commit().unwrap();
+        assert_eq!(pager.allocate_page().unwrap(), 16);
+        check_pages(&pager, 15);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = NamedTempFile::new("rejects_other_files.txt").unwrap();
+        std::fs::write(file.path(), "hello world").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(matches!(Pager::open(Some(&path)), Err(Error::NotADatabase)));
+        std::fs::write(file.path(), [b'x'; PAGE_SIZE * 2]).unwrap();
+        assert!(matches!(Pager::open(Some(&path)), Err(Error::NotADatabase)));
+        // the file must be left untouched
+        assert_eq!(std::fs::read(file.path()).unwrap(), [b'x'; PAGE_SIZE * 2]);
+    }
+
+    #[test]
+    fn freed_pages_reused() {
+        let pager = Pager::open(None).unwrap();
+        fill_pages(&pager, 5);
+        pager.free_page(2).unwrap();
+        pager.free_page(4).unwrap();
+        assert_eq!(pager.allocate_page().unwrap(), 4);
+        assert_eq!(pager.allocate_page().unwrap(), 2);
+        assert_eq!(pager.allocate_page().unwrap(), 6);
+        assert_eq!(pager.num_pages(), 7);
+    }
+
+    #[test]
+    fn free_pages_persist() {
+        let file = NamedTempFile::new("free_pages_persist.flake").unwrap();
+        let path = file.path().to_path_buf();
+        {
+            let pager = Pager::open(Some(&path)).unwrap();
+            fill_pages(&pager, 5);
+            pager.free_page(2).unwrap();
+            pager.free_page(4).unwrap();
+            pager.commit().unwrap();
+        }
+        let pager = Pager::open(Some(&path)).unwrap();
+        assert_eq!(pager.header().free_count, 2);
+        assert_eq!(pager.allocate_page().unwrap(), 4);
+        // reused pages are handed out blank
+        assert!(pager
+            .borrow_page(4)
+            .unwrap()
+            .as_slice()
+            .iter()
+            .all(|&b| b == 0));
+        assert_eq!(pager.take_free_pages().unwrap(), vec![2]);
+        assert_eq!(pager.allocate_page().unwrap(), 6);
+        pager.truncate(3).unwrap();
+        pager.commit().unwrap();
+        assert_eq!(pager.num_pages(), 3);
+        assert_eq!(pager.allocate_page().unwrap(), 3);
+    }
+
+    #[test]
+    fn borrowed_pages_stay_cached() {
+        let pager = Pager::with_cache_size(None, 2).unwrap();
+        fill_pages(&pager, 2);
+        let first = pager.borrow_page(1).unwrap();
+        check_pages(&pager, 2);
+        fill_pages(&pager, 10);
+        assert_eq!(first.as_slice()[..8], 1u64.to_be_bytes());
+    }
+}
@@ -0,0 +1,829 @@
+use super::ast::{
+    BinaryOperator, CreateIndex, Delete, Expr, Insert, Join, JoinKind, OrderBy, Select, SelectItem,
+    TableRef, UnaryOperator, Update,
+};
+use super::db::DEFAULT_TABLE;
+use super::schema::{Column, ColumnType};
+use super::value::Value;
+use super::{Error, Result, Statement, Token, Tokens};
+use crate::tokens::{Keyword, Operator, TokenKind};
+
+/// Recursive-descent parser turning the tokens of a single statement into a `Statement`
+pub struct Parser<'a> {
+    tokens: Tokens<'a>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Tokens<'a>) -> Self {
+        Self { tokens }
+    }
+
+    /// Parse one statement, optionally terminated by a semicolon, consuming all the input
+    pub fn parse_statement(&mut self) -> Result<Statement> {
+        let token = match self.next()? {
+            None => return Ok(Statement::None),
+            Some(token) => token,
+        };
+        let statement = match token.kind {
+            TokenKind::Keyword(Keyword::Select) => {
+                Statement::Select(Box::new(self.parse_select()?))
+            }
+            TokenKind::Keyword(Keyword::Insert) => Statement::Insert(self.parse_insert()?),
+            TokenKind::Keyword(Keyword::Update) => Statement::Update(self.parse_update()?),
+            TokenKind::Keyword(Keyword::Delete) => Statement::Delete(self.parse_delete()?),
+            TokenKind::Keyword(Keyword::Create) => self.parse_create()?,
+            TokenKind::Keyword(Keyword::Drop) => self.parse_drop()?,
+            TokenKind::Keyword(Keyword::Begin) => {
+                self.accept_contextual("transaction");
+                Statement::Begin
+            }
+            TokenKind::Keyword(Keyword::Commit) => {
+                self.accept_contextual("transaction");
+                Statement::Commit
+            }
+            TokenKind::Keyword(Keyword::Rollback) => {
+                self.accept_contextual("transaction");
+                Statement::Rollback
+            }
+            TokenKind::Keyword(Keyword::Vacuum) => Statement::Vacuum,
+            TokenKind::Meta(_) => {
+                return Err(Error::SyntaxError(format!(
+                    "encountered meta command {} when SQL was expected",
+                    token
+                )))
+            }
+            _ => return Err(Self::unexpected(Some(token), "a statement")),
+        };
+        self.end()?;
+        Ok(statement)
+    }
+
+    /// Next token, with input the lexer could not make sense of reported as a syntax error
+    fn next(&mut self) -> Result<Option<Token<'a>>> {
+        match self.tokens.next() {
+            Some(Token {
+                kind: TokenKind::Error(message),
+                span,
+                ..
+            }) => Err(Error::SyntaxError(format!(
+                "{} at position {}",
+                message, span.start
+            ))),
+            token => Ok(token),
+        }
+    }
+
+    fn peek_kind(&mut self) -> Option<&TokenKind> {
+        self.tokens.peek().map(|token| &token.kind)
+    }
+
+    fn peek_keyword(&mut self, keyword: Keyword) -> bool {
+        self.peek_kind() == Some(&TokenKind::Keyword(keyword))
+    }
+
+    /// Whether the statement ends here, ignoring a trailing semicolon
+    fn at_end(&mut self) -> bool {
+        matches!(self.peek_kind(), None | Some(TokenKind::Semicolon))
+    }
+
+    /// Consume the next token if it has the given kind
+    fn accept(&mut self, kind: &TokenKind) -> bool {
+        if self.peek_kind() == Some(kind) {
+            self.tokens.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_keyword(&mut self, keyword: Keyword) -> bool {
+        self.accept(&TokenKind::Keyword(keyword))
+    }
+
+    fn accept_operator(&mut self, operator: Operator) -> bool {
+        self.accept(&TokenKind::Operator(operator))
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<()> {
+        match self.next()? {
+            Some(token) if token.kind == kind => Ok(()),
+            token => Err(Self::unexpected(token, expected)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<()> {
+        self.expect(TokenKind::Keyword(keyword), &format!("'{}'", keyword))
+    }
+
+    fn expect_identifier(&mut self, expected: &str) -> Result<String> {
+        match self.next()? {
+            Some(Token {
+                kind: TokenKind::Identifier(name),
+                ..
+            }) => Ok(name),
+            token => Err(Self::unexpected(token, expected)),
+        }
+    }
+
+    /// Expect a word that is only a keyword in context, such as `key` after `primary`
+    fn expect_contextual(&mut self, word: &str) -> Result<()> {
+        match self.next()? {
+            Some(Token {
+                kind: TokenKind::Identifier(name),
+                ..
+            }) if name.eq_ignore_ascii_case(word) => Ok(()),
+            token => Err(Self::unexpected(token, &format!("'{}'", word))),
+        }
+    }
+
+    /// Consume the next token if it is a word that is only a keyword in context
+    fn accept_contextual(&mut self, word: &str) -> bool {
+        match self.peek_kind() {
+            Some(TokenKind::Identifier(name)) if name.eq_ignore_ascii_case(word) => {
+                self.tokens.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Accept an optional semicolon, after which nothing else may follow
+    fn end(&mut self) -> Result<()> {
+        self.accept(&TokenKind::Semicolon);
+        match self.next()? {
+            None => Ok(()),
+            token => Err(Self::unexpected(token, "end of statement")),
+        }
+    }
+
+    fn unexpected(token: Option<Token>, expected: &str) -> Error {
+        match token {
+            Some(token) => Error::SyntaxError(format!("expected {} but found {}", expected, token)),
+            None => Error::SyntaxError(format!("expected {} but found end of input", expected)),
+        }
+    }
+
+    /// Parse a comma-separated list of one or more items
+    fn comma_separated<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.accept(&TokenKind::Comma) {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    /// Parse a parenthesised, comma-separated list of column names
+    fn parse_column_names(&mut self) -> Result<Vec<String>> {
+        self.expect(TokenKind::LeftParen, "'('")?;
+        let columns = self.comma_separated(|parser| parser.expect_identifier("column name"))?;
+        self.expect(TokenKind::RightParen, "',' or ')'")?;
+        Ok(columns)
+    }
+
+    /// Parse an optional alias, introduced by `as` or given directly after the aliased item
+    fn parse_alias(&mut self) -> Result<Option<String>> {
+        if self.accept_keyword(Keyword::As) {
+            return self.expect_identifier("alias").map(Some);
+        }
+        match self.peek_kind() {
+            Some(TokenKind::Identifier(_)) => self.expect_identifier("alias").map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_table_ref(&mut self) -> Result<TableRef> {
+        Ok(TableRef {
+            name: self.expect_identifier("table name")?,
+            alias: self.parse_alias()?,
+        })
+    }
+
+    /// Parse a table joined to the ones before it, as in `, <table>`, `[inner | cross] join <table>
+    /// [on <expr>]` or `left [outer] join <table> [on <expr>]`
+    fn parse_join(&mut self) -> Result<Option<Join>> {
+        if self.accept(&TokenKind::Comma) {
+            return Ok(Some(Join {
+                kind: JoinKind::Inner,
+                table: self.parse_table_ref()?,
+                on: None,
+            }));
+        }
+        let kind = if self.accept_keyword(Keyword::Left) {
+            self.accept_keyword(Keyword::Outer);
+            JoinKind::Left
+        } else if self.accept_keyword(Keyword::Inner)
+            || self.accept_keyword(Keyword::Cross)
+            || matches!(self.peek_kind(), Some(TokenKind::Keyword(Keyword::Join)))
+        {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_keyword(Keyword::Join)?;
+        let table = self.parse_table_ref()?;
+        let on = if self.accept_keyword(Keyword::On) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(Some(Join { kind, table, on }))
+    }
+
+    /// Parse `select <items> [from <table> [<joins>]] [where <expr>]
+    /// [group by ... [having <expr>]] [order by ...] [limit <n> [offset <n>]]`, or a bare `select` listing the default table
+    fn parse_select(&mut self) -> Result<Select> {
+        if self.at_end() {
+            return Ok(Select {
+                projection: vec![SelectItem::Wildcard(None)],
+                from: Some(TableRef {
+                    name: DEFAULT_TABLE.into(),
+                    alias: None,
+                }),
+                joins: Vec::new(),
+                filter: None,
+                group_by: Vec::new(),
+                having: None,
+                order_by: Vec::new(),
+                limit: None,
+                offset: None,
+            });
+        }
+        let projection = self.comma_separated(Self::parse_select_item)?;
+        let mut joins = Vec::new();
+        let from = if self.accept_keyword(Keyword::From) {
+            let table = self.parse_table_ref()?;
+            while let Some(join) = self.parse_join()? {
+                joins.push(join);
+            }
+            Some(table)
+        } else {
+            None
+        };
+        let filter = self.parse_where()?;
+        let group_by = if self.accept_keyword(Keyword::Group) {
+            self.expect_keyword(Keyword::By)?;
+            self.comma_separated(Self::parse_expr)?
+        } else {
+            Vec::new()
+        };
+        let having = if self.accept_keyword(Keyword::Having) {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        let order_by = if self.accept_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By)?;
+            self.comma_separated(Self::parse_order_by)?
+        } else {
+            Vec::new()
+        };
+        let (mut limit, mut offset) = (None, None);
+        if self.accept_keyword(Keyword::Limit) {
+            limit = Some(self.parse_expr()?);
+            if self.accept_keyword(Keyword::Offset) {
+                offset = Some(self.parse_expr()?);
+            } else if self.accept(&TokenKind::Comma) {
+                // `limit <offset>, <count>` as in SQLite and MySQL
+                offset = limit.replace(self.parse_expr()?);
+            }
+        }
+        Ok(Select {
+            projection,
+            from,
+            joins,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_select_item(&mut self) -> Result<SelectItem> {
+        if self.accept_operator(Operator::Star) {
+            return Ok(SelectItem::Wildcard(None));
+        }
+        // `t.*` needs three tokens of lookahead to tell apart from a column such as `t.id`
+        let mut lookahead = self.tokens.clone();
+        let mut kinds = (0..3).map(|_| lookahead.next().map(|token| token.kind));
+        if let (
+            Some(TokenKind::Identifier(table)),
+            Some(TokenKind::Dot),
+            Some(TokenKind::Operator(Operator::Star)),
+        ) = (
+            kinds.next().flatten(),
+            kinds.next().flatten(),
+            kinds.next().flatten(),
+        ) {
+            self.tokens = lookahead;
+            return Ok(SelectItem::Wildcard(Some(table)));
+        }
+        let expr = self.parse_expr()?;
+        let alias = self.parse_alias()?;
+        Ok(SelectItem::Expr { expr, alias })
+    }
+
+    fn parse_order_by(&mut self) -> Result<OrderBy> {
+        let expr = self.parse_expr()?;
+        let descending = if self.accept_keyword(Keyword::Desc) {
+            true
+        } else {
+            self.accept_keyword(Keyword::Asc);
+            false
+        };
+        // NULL is the smallest value unless placed explicitly
+        let nulls_first = if self.accept_contextual("nulls") {
+            if self.accept_contextual("first") {
+                true
+            } else {
+                self.expect_contextual("last")?;
+                false
+            }
+        } else {
+            !descending
+        };
+        Ok(OrderBy {
+            expr,
+            descending,
+            nulls_first,
+        })
+    }
+
+    fn parse_where(&mut self) -> Result<Option<Expr>> {
+        if self.accept_keyword(Keyword::Where) {
+            self.parse_expr().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parse `insert into <table> [(<column>, ...)] values (<expr>, ...), ...`, or the shorthand
+    /// `insert <value> ...` adding a single row to the default table
+    fn parse_insert(&mut self) -> Result<Insert> {
+        if !self.accept_keyword(Keyword::Into) {
+            let mut row = Vec::new();
+            while !self.at_end() {
+                row.push(self.parse_unary()?);
+            }
+            return Ok(Insert {
+                table: DEFAULT_TABLE.into(),
+                columns: None,
+                rows: vec![row],
+            });
+        }
+        let table = self.expect_identifier("table name")?;
+        let columns = match self.peek_kind() {
+            Some(TokenKind::LeftParen) => Some(self.parse_column_names()?),
+            _ => None,
+        };
+        self.expect_keyword(Keyword::Values)?;
+        let rows = self.comma_separated(|parser| {
+            parser.expect(TokenKind::LeftParen, "'('")?;
+            let row = parser.comma_separated(Self::parse_expr)?;
+            parser.expect(TokenKind::RightParen, "',' or ')'")?;
+            Ok(row)
+        })?;
+        Ok(Insert {
+            table,
+            columns,
+            rows,
+        })
+    }
+
+    /// Parse `update <table> set <column> = <expr>, ... [where <expr>]`
+    fn parse_update(&mut self) -> Result<Update> {
+        let table = self.expect_identifier("table name")?;
+        self.expect_keyword(Keyword::Set)?;
+        let assignments = self.comma_separated(|parser| {
+            let column = parser.expect_identifier("column name")?;
+            parser.expect(TokenKind::Operator(Operator::Equal), "'='")?;
+            Ok((column, parser.parse_expr()?))
+        })?;
+        let filter = self.parse_where()?;
+        Ok(Update {
+            table,
+            assignments,
+            filter,
+        })
+    }
+
+    /// Parse `delete from <table> [where <expr>]`
+    fn parse_delete(&mut self) -> Result<Delete> {
+        self.expect_keyword(Keyword::From)?;
+        let table = self.expect_identifier("table name")?;
+        let filter = self.parse_where()?;
+        Ok(Delete { table, filter })
+    }
+
+    /// Parse `create table ...` or `create [unique] index ...`
+    fn parse_create(&mut self) -> Result<Statement> {
+        let unique = self.accept_keyword(Keyword::Unique);
+        if unique || self.accept_keyword(Keyword::Index) {
+            if unique {
+                self.expect_keyword(Keyword::Index)?;
+            }
+            return self.parse_create_index(unique);
+        }
+        self.expect_keyword(Keyword::Table)?;
+        let name = self.expect_identifier("table name")?;
+        self.expect(TokenKind::LeftParen, "'('")?;
+        let columns = self.comma_separated(Self::parse_column)?;
+        self.expect(TokenKind::RightParen, "',' or ')'")?;
+        Ok(Statement::CreateTable { name, columns })
+    }
+
+    /// Parse a column definition such as `username text(32) not null`
+    fn parse_column(&mut self) -> Result<Column> {
+        let name = self.expect_identifier("column name")?;
+        let column_type = ColumnType::parse(&self.expect_identifier("column type")?)?;
+        let mut column = Column::new(&name, column_type);
+        if self.accept(&TokenKind::LeftParen) {
+            match self.next()? {
+                Some(Token {
+                    kind: TokenKind::Integer(length),
+                    ..
+                }) if length >= 0 => column = column.max_length(length as usize),
+                token => return Err(Self::unexpected(token, "maximum length")),
+            }
+            self.expect(TokenKind::RightParen, "')'")?;
+        }
+        loop {
+            if self.accept_keyword(Keyword::Primary) {
+                self.expect_contextual("key")?;
+                column = column.primary_key();
+            } else if self.accept_keyword(Keyword::Not) {
+                self.expect_keyword(Keyword::Null)?;
+                column.not_null = true;
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    /// Parse the rest of `create [unique] index <name> on <table> (<column>, ...)`
+    fn parse_create_index(&mut self, unique: bool) -> Result<Statement> {
+        let name = self.expect_identifier("index name")?;
+        self.expect_keyword(Keyword::On)?;
+        let table = self.expect_identifier("table name")?;
+        let columns = self.parse_column_names()?;
+        Ok(Statement::CreateIndex(CreateIndex {
+            name,
+            table,
+            columns,
+            unique,
+        }))
+    }
+
+    /// Parse `drop table [if exists] <name>` or `drop index [if exists] <name>`
+    fn parse_drop(&mut self) -> Result<Statement> {
+        let table = match self.next()? {
+            Some(token) if token.is_keyword(Keyword::Table) => true,
+            Some(token) if token.is_keyword(Keyword::Index) => false,
+            token => return Err(Self::unexpected(token, "'table' or 'index'")),
+        };
+        let if_exists = self.accept_keyword(Keyword::If);
+        if if_exists {
+            self.expect_keyword(Keyword::Exists)?;
+        }
+        if table {
+            let name = self.expect_identifier("table name")?;
+            Ok(Statement::DropTable { name, if_exists })
+        } else {
+            let name = self.expect_identifier("index name")?;
+            Ok(Statement::DropIndex { name, if_exists })
+        }
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.accept_keyword(Keyword::Or) {
+            expr = binary(expr, BinaryOperator::Or, self.parse_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.accept_keyword(Keyword::And) {
+            expr = binary(expr, BinaryOperator::And, self.parse_not()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.accept_keyword(Keyword::Not) {
+            return Ok(Expr::Unary {
+                operator: UnaryOperator::Not,
+                operand: Box::new(self.parse_not()?),
+            });
+        }
+        self.parse_equality()
+    }
+
+    /// Parse the operators sharing equality's precedence: `=`, `!=`, `is [not] null`, and the
+    /// optionally negated `in`, `like` and `between`
+    fn parse_equality(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_comparison()?;
+        loop {
+            let operator = match self.peek_kind() {
+                Some(TokenKind::Operator(Operator::Equal)) => Some(BinaryOperator::Equal),
+                Some(TokenKind::Operator(Operator::NotEqual)) => Some(BinaryOperator::NotEqual),
+                _ => None,
+            };
+            if let Some(operator) = operator {
+                self.tokens.next();
+                expr = binary(expr, operator, self.parse_comparison()?);
+                continue;
+            }
+            let operand = Box::new(expr);
+            if self.accept_keyword(Keyword::Is) {
+                let negated = self.accept_keyword(Keyword::Not);
+                self.expect_keyword(Keyword::Null)?;
+                expr = Expr::IsNull { operand, negated };
+                continue;
+            }
+            let negated = self.accept_keyword(Keyword::Not);
+            expr = match self.next_if_keyword(&[Keyword::In, Keyword::Like, Keyword::Between]) {
+                Some(Keyword::In) => {
+                    self.expect(TokenKind::LeftParen, "'('")?;
+                    let list = self.comma_separated(Self::parse_expr)?;
+                    self.expect(TokenKind::RightParen, "',' or ')'")?;
+                    Expr::InList {
+                        operand,
+                        list,
+                        negated,
+                    }
+                }
+                Some(Keyword::Like) => Expr::Like {
+                    operand,
+                    pattern: Box::new(self.parse_comparison()?),
+                    negated,
+                },
+                Some(_) => {
+                    let low = Box::new(self.parse_comparison()?);
+                    self.expect_keyword(Keyword::And)?;
+                    let high = Box::new(self.parse_comparison()?);
+                    Expr::Between {
+                        operand,
+                        low,
+                        high,
+                        negated,
+                    }
+                }
+                None if negated => {
+                    let token = self.next()?;
+                    return Err(Self::unexpected(token, "'in', 'like' or 'between'"));
+                }
+                None => return Ok(*operand),
+            };
+        }
+    }
+
+    /// Consume the next token if it is one of the given keywords
+    fn next_if_keyword(&mut self, keywords: &[Keyword]) -> Option<Keyword> {
+        let keyword = keywords
+            .iter()
+            .copied()
+            .find(|keyword| self.peek_keyword(*keyword))?;
+        self.tokens.next();
+        Some(keyword)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        self.parse_binary(
+            Self::parse_additive,
+            &[
+                (Operator::Less, BinaryOperator::Less),
+                (Operator::LessEqual, BinaryOperator::LessEqual),
+                (Operator::Greater, BinaryOperator::Greater),
+                (Operator::GreaterEqual, BinaryOperator::GreaterEqual),
+            ],
+        )
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr> {
+        self.parse_binary(
+            Self::parse_multiplicative,
+            &[
+                (Operator::Plus, BinaryOperator::Add),
+                (Operator::Minus, BinaryOperator::Subtract),
+            ],
+        )
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr> {
+        self.parse_binary(
+            Self::parse_concat,
+            &[
+                (Operator::Star, BinaryOperator::Multiply),
+                (Operator::Slash, BinaryOperator::Divide),
+                (Operator::Percent, BinaryOperator::Modulo),
+            ],
+        )
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr> {
+        self.parse_binary(
+            Self::parse_unary,
+            &[(Operator::Concat, BinaryOperator::Concat)],
+        )
+    }
+
+    /// Parse a left-associative chain of operands joined by operators of equal precedence
+    fn parse_binary(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expr>,
+        operators: &[(Operator, BinaryOperator)],
+    ) -> Result<Expr> {
+        let mut expr = operand(self)?;
+        loop {
+            let operator = operators
+                .iter()
+                .find(|(token, _)| self.peek_kind() == Some(&TokenKind::Operator(*token)));
+            match operator {
+                Some((_, operator)) => {
+                    let operator = *operator;
+                    self.tokens.next();
+                    expr = binary(expr, operator, operand(self)?);
+                }
+                None => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        let operator = if self.accept_operator(Operator::Minus) {
+            UnaryOperator::Negate
+        } else if self.accept_operator(Operator::Plus) {
+            UnaryOperator::Plus
+        } else {
+            return self.parse_primary();
+        };
+        Ok(Expr::Unary {
+            operator,
+            operand: Box::new(self.parse_unary()?),
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let token = self.next()?;
+        let kind = token.as_ref().map(|token| token.kind.clone());
+        let literal = match kind {
+            Some(TokenKind::Integer(i)) => Value::Integer(i),
+            Some(TokenKind::Real(r)) => Value::Real(r),
+            Some(TokenKind::String(s)) => Value::Text(s),
+            Some(TokenKind::Blob(b)) => Value::Blob(b),
+            Some(TokenKind::Keyword(Keyword::Null)) => Value::Null,
+            Some(TokenKind::Keyword(Keyword::True)) => Value::Boolean(true),
+            Some(TokenKind::Keyword(Keyword::False)) => Value::Boolean(false),
+            Some(TokenKind::LeftParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(TokenKind::RightParen, "')'")?;
+                return Ok(expr);
+            }
+            Some(TokenKind::Identifier(name)) => return self.parse_identifier(name),
+            _ => return Err(Self::unexpected(token, "an expression")),
+        };
+        Ok(Expr::Literal(literal))
+    }
+
+    /// Parse what follows an identifier in an expression: a function call, a qualified column
+    /// reference, or nothing for a plain column reference
+    fn parse_identifier(&mut self, name: String) -> Result<Expr> {
+        if self.accept(&TokenKind::LeftParen) {
+            let (mut args, mut distinct, mut wildcard) = (Vec::new(), false, false);
+            if self.accept_operator(Operator::Star) {
+                wildcard = true;
+            } else if self.peek_kind() != Some(&TokenKind::RightParen) {
+                distinct = self.accept_keyword(Keyword::Distinct);
+                args = self.comma_separated(Self::parse_expr)?;
+            }
+            self.expect(TokenKind::RightParen, "',' or ')'")?;
+            return Ok(Expr::Function {
+                name: name.to_ascii_lowercase(),
+                args,
+                distinct,
+                wildcard,
+            });
+        }
+        if self.accept(&TokenKind::Dot) {
+            let column = self.expect_identifier("column name")?;
+            return Ok(Expr::Column {
+                table: Some(name),
+                name: column,
+            });
+        }
+        Ok(Expr::Column { table: None, name })
+    }
+}
+
+fn binary(left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// Parse a select statement, for tests of how queries are planned and run
+    pub fn parse_select(raw: &str) -> Result<Select> {
+        match Parser::new(Tokens::from(raw)).parse_statement()? {
+            Statement::Select(select) => Ok(*select),
+            statement => panic!("expected select, got {:?}", statement),
+        }
+    }
+
+    /// Render an expression fully parenthesised so precedence is visible
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::Literal(value) => value.to_string(),
+            Expr::Column { table: None, name } => name.clone(),
+            Expr::Column {
+                table: Some(table),
+                name,
+            } => format!("{}.{}", table, name),
+            Expr::Unary { operator, operand } => format!("({:?} {})", operator, show(operand)),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => format!("({} {:?} {})", show(left), operator, show(right)),
+            Expr::IsNull { operand, negated } => format!("({} is null {})", show(operand), negated),
+            Expr::InList {
+                operand,
+                list,
+                negated,
+            } => {
+                let list: Vec<_> = list.iter().map(show).collect();
+                format!("({} in [{}] {})", show(operand), list.join(" "), negated)
+            }
+            Expr::Between {
+                operand,
+                low,
+                high,
+                negated,
+            } => format!(
+                "({} between {} {} {})",
+                show(operand),
+                show(low),
+                show(high),
+                negated
+            ),
+            Expr::Like {
+                operand,
+                pattern,
+                negated,
+            } => format!("({} like {} {})", show(operand), show(pattern), negated),
+            Expr::Function {
+                name,
+                args,
+                distinct,
+                wildcard,
+            } => {
+                let args: Vec<_> = args.iter().map(show).collect();
+                format!("{}({} {} {})", name, args.join(" "), distinct, wildcard)
+            }
+        }
+    }
+
+    #[test_case("1 + 2 * 3" => "(1 Add (2 Multiply 3))" ; "multiplication binds tighter")]
+    #[test_case("1 - 2 - 3" => "((1 Subtract 2) Subtract 3)" ; "left associative")]
+    #[test_case("a or b and not c = 1" => "(a Or (b And (Not (c Equal 1))))" ; "boolean precedence")]
+    #[test_case("a < 1 = b > 2" => "((a Less 1) Equal (b Greater 2))" ; "comparison above equality")]
+    #[test_case("-a || 'x'" => "((Negate a) Concat x)" ; "unary binds tightest")]
+    #[test_case("(1 + 2) * 3" => "((1 Add 2) Multiply 3)" ; "parentheses")]
+    #[test_case("x between 1 and 2 and y" => "((x between 1 2 false) And y)" ; "between and")]
+    #[test_case("x not in (1, 2)" => "(x in [1 2] true)" ; "not in")]
+    #[test_case("name not like 'k%'" => "(name like k% true)" ; "not like")]
+    #[test_case("x is not null" => "(x is null true)" ; "is not null")]
+    #[test_case("u.id" => "u.id" ; "qualified column")]
+    #[test_case("COUNT(*)" => "count( false true)" ; "count star")]
+    #[test_case("count(distinct a)" => "count(a true false)" ; "count distinct")]
+    #[test_case("upper(name)" => "upper(name false false)" ; "function call")]
+    fn parse_expr(raw: &str) -> String {
+        let expr = Parser::new(Tokens::from(raw)).parse_expr().unwrap();
+        show(&expr)
+    }
+
+    #[test_case("1 +" ; "missing operand")]
+    #[test_case("x not null" ; "not without operator")]
+    #[test_case("x in 1" ; "in without list")]
+    #[test_case("x between 1" ; "between without and")]
+    #[test_case("f(1" ; "unclosed call")]
+    fn parse_expr_invalid(raw: &str) {
+        assert!(Parser::new(Tokens::from(raw)).parse_expr().is_err());
+    }
+}
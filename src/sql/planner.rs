@@ -0,0 +1,302 @@
+use super::ast::{BinaryOperator, Expr};
+use super::db::Database;
+use super::eval::{self, Scope};
+use super::executor::{IndexScan, Node, Scan};
+use super::index::{Index, KeyRange};
+use super::row::Row;
+use super::schema::{Column, ColumnType};
+use super::value::Value;
+use super::Result;
+use std::ops::Bound;
+
+/// Rows read from a table, each along with its rowid
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<(i64, Row)>> + 'a>;
+
+/// Read the rows of a table that may satisfy a condition, going through an index when one covers
+/// columns the condition compares with constants and otherwise scanning the whole table.
+///
+/// Rows are only narrowed down, never checked, so the condition must still be applied to each.
+pub fn scan<'a>(
+    db: &'a Database,
+    table: &'a str,
+    scope: &Scope,
+    filter: Option<&Expr>,
+) -> Result<Rows<'a>> {
+    match filter.and_then(|filter| choose_index(db, table, scope, filter)) {
+        Some((index, range)) => Ok(Box::new(db.index_scan(index, &range)?)),
+        None => Ok(Box::new(db.select(table)?)),
+    }
+}
+
+/// Access path to the rows of a table that may satisfy a condition, chosen like in [`scan`]
+pub fn access<'a>(
+    db: &'a Database,
+    table: &'a str,
+    scope: &Scope,
+    filter: Option<&Expr>,
+) -> Result<Node<'a>> {
+    match filter.and_then(|filter| choose_index(db, table, scope, filter)) {
+        Some((index, range)) => Ok(Box::new(IndexScan::new(db, index, &range)?)),
+        None => Ok(Box::new(Scan::new(db, table)?)),
+    }
+}
+
+/// Restriction a condition places on the values of one column of the table
+#[derive(Clone, Debug, PartialEq)]
+enum Constraint {
+    Equal(Value),
+    Lower(Bound<Value>),
+    Upper(Bound<Value>),
+}
+
+/// Pick the index able to narrow down a table's rows the most for a condition, preferring the
+/// longest run of leading columns compared for equality and then a range on the column after it
+pub fn choose_index<'a>(
+    db: &'a Database,
+    table: &'a str,
+    scope: &Scope,
+    filter: &Expr,
+) -> Option<(&'a Index, KeyRange)> {
+    let mut constraints = Vec::new();
+    collect_constraints(filter, scope, &mut constraints);
+    if constraints.is_empty() {
+        return None;
+    }
+    let columns = &db.table(table).ok()?.schema.columns;
+    db.indexes(table)
+        .filter_map(|index| Some((index, key_range(index, columns, &constraints)?)))
+        .max_by_key(|(_, range)| (range.equal.len(), range.lower != Bound::Unbounded))
+}
+
+/// Gather the constraints of the top-level conjuncts of a condition, along with the position in
+/// the scope of the column each applies to
+fn collect_constraints(expr: &Expr, scope: &Scope, constraints: &mut Vec<(usize, Constraint)>) {
+    match expr {
+        Expr::Binary {
+            left,
+            operator: BinaryOperator::And,
+            right,
+        } => {
+            collect_constraints(left, scope, constraints);
+            collect_constraints(right, scope, constraints);
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            let (position, operator, value) = match (
+                column(left, scope),
+                constant(left),
+                column(right, scope),
+                constant(right),
+            ) {
+                (Some(position), _, _, Some(value)) => (position, *operator, value),
+                (_, Some(value), Some(position), _) => (position, flip(*operator), value),
+                _ => return,
+            };
+            let constraint = match operator {
+                BinaryOperator::Equal => Constraint::Equal(value),
+                BinaryOperator::Less => Constraint::Upper(Bound::Excluded(value)),
+                BinaryOperator::LessEqual => Constraint::Upper(Bound::Included(value)),
+                BinaryOperator::Greater => Constraint::Lower(Bound::Excluded(value)),
+                BinaryOperator::GreaterEqual => Constraint::Lower(Bound::Included(value)),
+                _ => return,
+            };
+            constraints.push((position, constraint));
+        }
+        Expr::Between {
+            operand,
+            low,
+            high,
+            negated: false,
+        } => {
+            if let (Some(position), Some(low), Some(high)) =
+                (column(operand, scope), constant(low), constant(high))
+            {
+                constraints.push((position, Constraint::Lower(Bound::Included(low))));
+                constraints.push((position, Constraint::Upper(Bound::Included(high))));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Position of a column of the table referred to by an expression
+fn column(expr: &Expr, scope: &Scope) -> Option<usize> {
+    match expr {
+        Expr::Column { table, name } => scope.resolve(table.as_deref(), name).ok(),
+        _ => None,
+    }
+}
+
+/// Value of an expression that refers to no columns, unless it is NULL, which nothing equals
+fn constant(expr: &Expr) -> Option<Value> {
+    match eval::evaluate(expr, &Scope::default(), &[]) {
+        Ok(Value::Null) | Err(_) => None,
+        Ok(value) => Some(value),
+    }
+}
+
+/// Operator comparing the same operands the other way around
+fn flip(operator: BinaryOperator) -> BinaryOperator {
+    match operator {
+        BinaryOperator::Less => BinaryOperator::Greater,
+        BinaryOperator::LessEqual => BinaryOperator::GreaterEqual,
+        BinaryOperator::Greater => BinaryOperator::Less,
+        BinaryOperator::GreaterEqual => BinaryOperator::LessEqual,
+        operator => operator,
+    }
+}
+
+/// Range of an index holding every row that meets the constraints, if they restrict its leading
+/// column at all
+fn key_range(
+    index: &Index,
+    columns: &[Column],
+    constraints: &[(usize, Constraint)],
+) -> Option<KeyRange> {
+    let mut range = KeyRange {
+        equal: Vec::new(),
+        lower: Bound::Unbounded,
+        upper: Bound::Unbounded,
+    };
+    for &position in &index.positions {
+        let column = &columns[position];
+        let on_column = || {
+            constraints
+                .iter()
+                .filter(move |(p, _)| *p == position)
+                .map(|(_, constraint)| constraint)
+        };
+        let equal = on_column().find_map(|constraint| match constraint {
+            Constraint::Equal(value) => key_value(column, value),
+            _ => None,
+        });
+        if let Some(value) = equal {
+            range.equal.push(value);
+            continue;
+        }
+        for constraint in on_column() {
+            match constraint {
+                Constraint::Lower(bound) if range.lower == Bound::Unbounded => {
+                    range.lower = key_bound(column, bound);
+                }
+                Constraint::Upper(bound) if range.upper == Bound::Unbounded => {
+                    range.upper = key_bound(column, bound);
+                }
+                _ => {}
+            }
+        }
+        break;
+    }
+    let restricted = !range.equal.is_empty()
+        || range.lower != Bound::Unbounded
+        || range.upper != Bound::Unbounded;
+    restricted.then_some(range)
+}
+
+/// A constant as stored in a column, if comparing with it orders the same way as comparing with
+/// its stored form. Anything else, such as text compared with a number, is left to a full scan.
+fn key_value(column: &Column, value: &Value) -> Option<Value> {
+    match (column.column_type, value) {
+        (ColumnType::Real, Value::Integer(i)) => Some(Value::Real(*i as f64)),
+        (ColumnType::Integer, Value::Integer(_))
+        | (ColumnType::Real, Value::Real(_))
+        | (ColumnType::Text, Value::Text(_))
+        | (ColumnType::Blob, Value::Blob(_))
+        | (ColumnType::Boolean, Value::Boolean(_)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn key_bound(column: &Column, bound: &Bound<Value>) -> Bound<Value> {
+    match bound {
+        Bound::Included(value) => {
+            key_value(column, value).map_or(Bound::Unbounded, Bound::Included)
+        }
+        Bound::Excluded(value) => {
+            key_value(column, value).map_or(Bound::Unbounded, Bound::Excluded)
+        }
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+    use crate::sql::schema::Schema;
+    use crate::tokens::Tokens;
+    use test_case::test_case;
+
+    fn database() -> Database {
+        let mut db = Database::open(None).unwrap();
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer).primary_key(),
+            Column::new("name", ColumnType::Text),
+            Column::new("score", ColumnType::Real),
+            Column::new("rank", ColumnType::Integer),
+        ])
+        .unwrap();
+        db.create_table("t", schema).unwrap();
+        for (i, name) in ["karl", "fri", "day"].iter().enumerate() {
+            let values = vec![
+                Value::Null,
+                Value::Text(name.to_string()),
+                Value::Real(i as f64 * 1.5),
+                Value::Integer(i as i64 % 2),
+            ];
+            db.insert("t", values).unwrap();
+        }
+        db.create_index("by_name", "t", vec!["name".into()], true)
+            .unwrap();
+        db.create_index(
+            "by_rank_score",
+            "t",
+            vec!["rank".into(), "score".into()],
+            false,
+        )
+        .unwrap();
+        db
+    }
+
+    fn plan(db: &Database, condition: &str) -> Option<(String, KeyRange)> {
+        let filter = Parser::new(Tokens::from(condition)).parse_expr().unwrap();
+        let scope = Scope::table("t", &db.table("t").unwrap().schema);
+        choose_index(db, "t", &scope, &filter).map(|(index, range)| (index.name.clone(), range))
+    }
+
+    fn range(equal: Vec<Value>, lower: Bound<Value>, upper: Bound<Value>) -> KeyRange {
+        KeyRange {
+            equal,
+            lower,
+            upper,
+        }
+    }
+
+    #[test_case("name = 'fri'" => Some(("by_name".into(), range(vec![Value::Text("fri".into())], Bound::Unbounded, Bound::Unbounded))) ; "equality")]
+    #[test_case("1 = rank and t.score > 2" => Some(("by_rank_score".into(), range(vec![Value::Integer(1)], Bound::Excluded(Value::Real(2.0)), Bound::Unbounded))) ; "equality then range")]
+    #[test_case("rank between 0 and 1" => Some(("by_rank_score".into(), range(vec![], Bound::Included(Value::Integer(0)), Bound::Included(Value::Integer(1))))) ; "between range")]
+    #[test_case("score = 1.5" => None ; "not leading column")]
+    #[test_case("name = 'fri' or rank = 1" => None ; "disjunction")]
+    #[test_case("name = 3" => None ; "mismatched type")]
+    #[test_case("name = null" => None ; "null")]
+    fn choose(condition: &str) -> Option<(String, KeyRange)> {
+        plan(&database(), condition)
+    }
+
+    #[test]
+    fn index_scan_finds_rows() {
+        let db = database();
+        let filter = Parser::new(Tokens::from("rank = 0 and score >= 1"))
+            .parse_expr()
+            .unwrap();
+        let scope = Scope::table("t", &db.table("t").unwrap().schema);
+        let rows: Vec<_> = scan(&db, "t", &scope, Some(&filter))
+            .unwrap()
+            .map(|row| row.unwrap().1.to_string())
+            .collect();
+        assert_eq!(rows, vec!["3,day,3.0,0"]);
+    }
+}
@@ -0,0 +1,123 @@
+use super::ast::{Expr, SelectItem};
+use super::eval::{self, Scope};
+use super::value::Value;
+use super::{Error, Result};
+
+/// How a single output column is computed from an input row
+#[derive(Clone, Debug)]
+enum Output {
+    /// Value copied straight from a position of the input row
+    Column(usize),
+    Expr(Expr),
+}
+
+/// Output columns of a select list, resolved against the scope of the rows it is applied to
+#[derive(Clone, Debug)]
+pub struct Projection {
+    /// Name of each output column, as shown in the header of the results
+    pub names: Vec<String>,
+    outputs: Vec<Output>,
+}
+
+impl Projection {
+    pub fn new(items: &[SelectItem], scope: &Scope) -> Result<Self> {
+        let mut projection = Self {
+            names: Vec::new(),
+            outputs: Vec::new(),
+        };
+        for item in items {
+            match item {
+                SelectItem::Wildcard(table) => {
+                    let start = projection.outputs.len();
+                    for (i, column) in scope.columns.iter().enumerate() {
+                        // columns computed by the query are not part of any table
+                        if column.table.is_some() && (table.is_none() || column.table == *table) {
+                            projection.names.push(column.name.clone());
+                            projection.outputs.push(Output::Column(i));
+                        }
+                    }
+                    if projection.outputs.len() == start {
+                        return Err(Error::ExecutionError(match table {
+                            Some(table) => format!("no such table: {}", table),
+                            None => "no tables specified".into(),
+                        }));
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    let (name, output) = match expr {
+                        // plain columns are named after the column rather than the qualified text
+                        Expr::Column { table, name } => (
+                            name.clone(),
+                            Output::Column(scope.resolve(table.as_deref(), name)?),
+                        ),
+                        expr => (expr.to_string(), Output::Expr(expr.clone())),
+                    };
+                    projection.names.push(alias.clone().unwrap_or(name));
+                    projection.outputs.push(output);
+                }
+            }
+        }
+        Ok(projection)
+    }
+
+    /// Add an output column computed by an expression and named after it, returning its position
+    pub fn push(&mut self, expr: Expr) -> usize {
+        self.names.push(expr.to_string());
+        self.outputs.push(Output::Expr(expr));
+        self.outputs.len() - 1
+    }
+
+    pub fn apply(&self, scope: &Scope, row: &[Value]) -> Result<Vec<Value>> {
+        self.outputs
+            .iter()
+            .map(|output| match output {
+                Output::Column(i) => Ok(row[*i].clone()),
+                Output::Expr(expr) => eval::evaluate(expr, scope, row),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::tests::parse_select;
+    use crate::sql::schema::{Column, ColumnType, Schema};
+
+    fn project(raw: &str) -> Result<(Vec<String>, Vec<Value>)> {
+        let select = parse_select(raw)?;
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer),
+            Column::new("username", ColumnType::Text),
+        ])
+        .unwrap();
+        let scope = Scope::table("u", &schema);
+        let row = [Value::Integer(21), Value::Text("karl".into())];
+        let projection = Projection::new(&select.projection, &scope)?;
+        let values = projection.apply(&scope, &row)?;
+        Ok((projection.names, values))
+    }
+
+    #[test]
+    fn wildcards_and_expressions() {
+        let (names, values) =
+            project("select username, u.*, id*2, upper(username) as shout from u").unwrap();
+        assert_eq!(names, vec!["username", "id", "username", "id * 2", "shout"]);
+        assert_eq!(
+            values,
+            vec![
+                Value::Text("karl".into()),
+                Value::Integer(21),
+                Value::Text("karl".into()),
+                Value::Integer(42),
+                Value::Text("KARL".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_columns_rejected() {
+        assert!(project("select email from u").is_err());
+        assert!(project("select v.* from u").is_err());
+    }
+}
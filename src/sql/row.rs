@@ -1,115 +1,141 @@
+use super::schema::{ColumnType, Schema};
+use super::value::Value;
 use super::{Error, Result};
-use crate::tokens::Tokens;
 use std::fmt::{Display, Formatter};
-use std::io::Write;
-use std::mem;
-use std::ops::Range;
 
-const COLUMN_SIZE_ID: usize = mem::size_of::<u16>();
-const COLUMN_SIZE_USERNAME: usize = 32;
-const COLUMN_SIZE_EMAIL: usize = 255;
-pub const ROW_SIZE: usize = COLUMN_SIZE_ID + COLUMN_SIZE_USERNAME + COLUMN_SIZE_EMAIL;
+// per-column tag preceding each value in an encoded record
+const TAG_NULL: u8 = 0;
+const TAG_PRESENT: u8 = 1;
 
-const RANGE_ID: Range<usize> = 0..COLUMN_SIZE_ID;
-const RANGE_USERNAME: Range<usize> = RANGE_ID.end..RANGE_ID.end + COLUMN_SIZE_USERNAME;
-const RANGE_EMAIL: Range<usize> = RANGE_USERNAME.end..RANGE_USERNAME.end + COLUMN_SIZE_EMAIL;
-
-/// Database row directly parsed from token stream where all values are stored as strings
-#[derive(Debug, Eq, PartialEq)]
-pub struct InputRow {
-    pub id: String,
-    pub username: String,
-    pub email: String,
+/// Database row holding one value per column of the table's schema
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub values: Vec<Value>,
 }
 
-impl InputRow {
-    pub fn parse(tokens: &mut Tokens) -> Result<Self> {
-        Ok(Self {
-            id: tokens
-                .next()
-                .ok_or(Error::ExecutionError("missing id".into()))?
-                .into(),
-            username: tokens
-                .next()
-                .ok_or(Error::ExecutionError("missing username".into()))?
-                .into(),
-            email: tokens
-                .next()
-                .ok_or(Error::ExecutionError("missing email".into()))?
-                .into(),
-        })
+impl Row {
+    pub fn new(values: Vec<Value>) -> Self {
+        Self { values }
     }
 
-    pub fn validate(&self) -> Result<ValidatedRow> {
-        let mut validated = ValidatedRow {
-            id: self
-                .id
-                .parse()
-                .map_err(|e| Error::ExecutionError(format!("failed while parsing id ({})", e)))?,
-            username: [0; COLUMN_SIZE_USERNAME],
-            email: [0; COLUMN_SIZE_EMAIL],
-        };
-        if self.username.as_bytes().len() > COLUMN_SIZE_USERNAME {
-            Err(Error::ExecutionError("username too long".into()))
-        } else if self.email.as_bytes().len() > COLUMN_SIZE_EMAIL {
-            Err(Error::ExecutionError("email too long".into()))
-        } else {
-            validated
-                .username
-                .as_mut_slice()
-                .write(self.username.as_bytes())?;
-            validated
-                .email
-                .as_mut_slice()
-                .write(self.email.as_bytes())?;
-            Ok(validated)
+    /// Serialize the row into a record, with each value encoded according to its column type
+    pub fn encode(&self, schema: &Schema) -> Vec<u8> {
+        let mut record = Vec::new();
+        for (column, value) in schema.columns.iter().zip(&self.values) {
+            if let Value::Null = value {
+                record.push(TAG_NULL);
+                continue;
+            }
+            record.push(TAG_PRESENT);
+            match (column.column_type, value) {
+                (ColumnType::Integer, Value::Integer(i)) => {
+                    record.extend_from_slice(&i.to_be_bytes())
+                }
+                (ColumnType::Real, Value::Real(r)) => record.extend_from_slice(&r.to_be_bytes()),
+                (ColumnType::Boolean, Value::Boolean(b)) => record.push(*b as u8),
+                (ColumnType::Text, Value::Text(s)) => write_bytes(&mut record, s.as_bytes()),
+                (ColumnType::Blob, Value::Blob(b)) => write_bytes(&mut record, b),
+                (column_type, value) => panic!(
+                    "value {:?} does not match column type {}",
+                    value, column_type
+                ),
+            }
         }
+        record
     }
-}
 
-fn fixed_bytes_to_string(bytes: &[u8]) -> String {
-    let last = memchr::memchr(0, bytes).unwrap_or(bytes.len());
-    String::from_utf8_lossy(&bytes[..last]).into_owned()
+    /// Deserialize a record previously produced by `encode` with the same schema
+    pub fn decode(schema: &Schema, record: &[u8]) -> Result<Self> {
+        let mut reader = Reader { record, offset: 0 };
+        let values = schema
+            .columns
+            .iter()
+            .map(|column| {
+                if reader.take(1)?[0] == TAG_NULL {
+                    return Ok(Value::Null);
+                }
+                Ok(match column.column_type {
+                    ColumnType::Integer => {
+                        Value::Integer(i64::from_be_bytes(reader.take(8)?.try_into().unwrap()))
+                    }
+                    ColumnType::Real => {
+                        Value::Real(f64::from_be_bytes(reader.take(8)?.try_into().unwrap()))
+                    }
+                    ColumnType::Boolean => Value::Boolean(reader.take(1)?[0] != 0),
+                    ColumnType::Text => {
+                        Value::Text(String::from_utf8_lossy(reader.take_bytes()?).into_owned())
+                    }
+                    ColumnType::Blob => Value::Blob(reader.take_bytes()?.to_vec()),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { values })
+    }
 }
 
-impl From<&ValidatedRow> for InputRow {
-    fn from(row: &ValidatedRow) -> Self {
-        Self {
-            id: row.id.to_string(),
-            username: fixed_bytes_to_string(&row.username),
-            email: fixed_bytes_to_string(&row.email),
+impl Display for Row {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, value) in self.values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", value)?;
         }
+        Ok(())
     }
 }
 
-impl Display for InputRow {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{},{},{}", self.id, self.username, self.email)
-    }
+fn write_bytes(record: &mut Vec<u8>, bytes: &[u8]) {
+    record.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    record.extend_from_slice(bytes);
 }
 
-/// Row as stack allocated data, with values parsed and validated, read to write into table page
-#[derive(Debug)]
-pub struct ValidatedRow {
-    id: u16,
-    username: [u8; COLUMN_SIZE_USERNAME],
-    email: [u8; COLUMN_SIZE_EMAIL],
+struct Reader<'a> {
+    record: &'a [u8],
+    offset: usize,
 }
 
-impl ValidatedRow {
-    pub fn write(&self, buffer: &mut [u8; ROW_SIZE]) -> Result<()> {
-        (&mut buffer[RANGE_ID]).write(&self.id.to_be_bytes())?;
-        (&mut buffer[RANGE_USERNAME]).write(&self.username)?;
-        (&mut buffer[RANGE_EMAIL]).write(&self.email)?;
-        Ok(())
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.offset + len;
+        if end > self.record.len() {
+            return Err(Error::ExecutionError("truncated record".into()));
+        }
+        let bytes = &self.record[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
     }
 
-    pub fn read(buffer: &[u8; ROW_SIZE]) -> Self {
-        // fine to unwrap here as the slice is guaranteed to be large enough
-        Self {
-            id: u16::from_be_bytes(buffer[RANGE_ID].try_into().unwrap()),
-            username: buffer[RANGE_USERNAME].try_into().unwrap(),
-            email: buffer[RANGE_EMAIL].try_into().unwrap(),
-        }
+    fn take_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::schema::Column;
+
+    #[test]
+    fn roundtrip() {
+        let schema = Schema::new(vec![
+            Column::new("id", ColumnType::Integer).primary_key(),
+            Column::new("name", ColumnType::Text),
+            Column::new("score", ColumnType::Real),
+            Column::new("data", ColumnType::Blob),
+            Column::new("active", ColumnType::Boolean),
+        ])
+        .unwrap();
+        let row = Row::new(vec![
+            Value::Integer(-3),
+            Value::Text("karl".into()),
+            Value::Null,
+            Value::Blob(vec![1, 2, 3]),
+            Value::Boolean(true),
+        ]);
+        let decoded = Row::decode(&schema, &row.encode(&schema)).unwrap();
+        assert_eq!(decoded, row);
+        assert_eq!(decoded.to_string(), "-3,karl,,x'010203',true");
     }
 }
@@ -0,0 +1,264 @@
+use super::value::Value;
+use super::{Error, Result};
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Text,
+    Real,
+    Blob,
+    Boolean,
+}
+
+impl ColumnType {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "integer" | "int" => Ok(Self::Integer),
+            "text" | "varchar" => Ok(Self::Text),
+            "real" | "float" | "double" => Ok(Self::Real),
+            "blob" => Ok(Self::Blob),
+            "boolean" | "bool" => Ok(Self::Boolean),
+            other => Err(Error::SyntaxError(format!(
+                "unknown column type '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Integer => "integer",
+            Self::Text => "text",
+            Self::Real => "real",
+            Self::Blob => "blob",
+            Self::Boolean => "boolean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+    /// Maximum length in bytes for TEXT and BLOB columns declared as e.g. `text(32)`
+    pub max_length: Option<usize>,
+    pub primary_key: bool,
+    pub not_null: bool,
+}
+
+impl Column {
+    pub fn new(name: &str, column_type: ColumnType) -> Self {
+        Self {
+            name: name.into(),
+            column_type,
+            max_length: None,
+            primary_key: false,
+            not_null: false,
+        }
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn primary_key(mut self) -> Self {
+        self.primary_key = true;
+        self
+    }
+
+    /// Whether values of the column are used directly as the key of the table's B+tree
+    pub fn is_rowid(&self) -> bool {
+        self.primary_key && self.column_type == ColumnType::Integer
+    }
+
+    /// Convert a value to the column's type, enforcing its constraints
+    pub fn coerce(&self, value: Value) -> Result<Value> {
+        let mismatch = |reason: &dyn Display| {
+            Error::ExecutionError(format!("failed while parsing {} ({})", self.name, reason))
+        };
+        let value = match (self.column_type, value) {
+            (_, Value::Null) => Value::Null,
+            (ColumnType::Integer, Value::Integer(i)) => Value::Integer(i),
+            (ColumnType::Integer, Value::Text(s)) => {
+                Value::Integer(s.trim().parse().map_err(|e| mismatch(&e))?)
+            }
+            (ColumnType::Real, Value::Real(r)) => Value::Real(r),
+            (ColumnType::Real, Value::Integer(i)) => Value::Real(i as f64),
+            (ColumnType::Real, Value::Text(s)) => {
+                Value::Real(s.trim().parse().map_err(|e| mismatch(&e))?)
+            }
+            (ColumnType::Text, Value::Text(s)) => Value::Text(s),
+            (ColumnType::Text, value @ (Value::Integer(_) | Value::Real(_))) => {
+                Value::Text(value.to_string())
+            }
+            (ColumnType::Blob, Value::Blob(b)) => Value::Blob(b),
+            (ColumnType::Boolean, Value::Boolean(b)) => Value::Boolean(b),
+            (ColumnType::Boolean, Value::Integer(i @ (0 | 1))) => Value::Boolean(i == 1),
+            (ColumnType::Boolean, Value::Text(s)) => match s.to_ascii_lowercase().as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => return Err(mismatch(&"expected true or false")),
+            },
+            (column_type, value) => {
+                return Err(mismatch(&format!(
+                    "expected {} but found {}",
+                    column_type,
+                    value.type_name()
+                )))
+            }
+        };
+        self.validate(value)
+    }
+
+    /// Check a value against the column's constraints
+    pub fn validate(&self, value: Value) -> Result<Value> {
+        let length = match &value {
+            Value::Null if self.not_null || (self.primary_key && !self.is_rowid()) => {
+                return Err(Error::ExecutionError(format!(
+                    "{} may not be null",
+                    self.name
+                )))
+            }
+            Value::Text(s) => s.len(),
+            Value::Blob(b) => b.len(),
+            _ => 0,
+        };
+        match self.max_length {
+            Some(max_length) if length > max_length => {
+                Err(Error::ExecutionError(format!("{} too long", self.name)))
+            }
+            _ => Ok(value),
+        }
+    }
+}
+
+impl Display for Column {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.column_type)?;
+        if let Some(max_length) = self.max_length {
+            write!(f, "({})", max_length)?;
+        }
+        if self.primary_key {
+            write!(f, " primary key")?;
+        }
+        if self.not_null {
+            write!(f, " not null")?;
+        }
+        Ok(())
+    }
+}
+
+/// Ordered list of columns making up a table
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+}
+
+impl Schema {
+    pub fn new(columns: Vec<Column>) -> Result<Self> {
+        if columns.is_empty() {
+            return Err(Error::SyntaxError(
+                "table must have at least one column".into(),
+            ));
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|other| other.name == column.name) {
+                return Err(Error::SyntaxError(format!(
+                    "duplicate column name '{}'",
+                    column.name
+                )));
+            }
+        }
+        if columns.iter().filter(|column| column.primary_key).count() > 1 {
+            return Err(Error::SyntaxError(
+                "table has more than one primary key".into(),
+            ));
+        }
+        Ok(Self { columns })
+    }
+
+    /// Index of the INTEGER PRIMARY KEY column whose values serve as row keys, if there is one
+    pub fn rowid_column(&self) -> Option<usize> {
+        self.columns.iter().position(Column::is_rowid)
+    }
+
+    /// Convert values to the types of the schema's columns, one per column
+    pub fn coerce_row(&self, values: Vec<Value>) -> Result<Vec<Value>> {
+        if values.len() != self.columns.len() {
+            return Err(Error::ExecutionError(format!(
+                "expected {} values but found {}",
+                self.columns.len(),
+                values.len()
+            )));
+        }
+        self.columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| column.coerce(value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(ColumnType::Integer, Value::Integer(-7) => Value::Integer(-7))]
+    #[test_case(ColumnType::Integer, Value::Text(" 42".into()) => Value::Integer(42))]
+    #[test_case(ColumnType::Real, Value::Integer(2) => Value::Real(2.0))]
+    #[test_case(ColumnType::Real, Value::Text("1.5".into()) => Value::Real(1.5))]
+    #[test_case(ColumnType::Text, Value::Integer(12) => Value::Text("12".into()))]
+    #[test_case(ColumnType::Blob, Value::Blob(vec![0, 255]) => Value::Blob(vec![0, 255]))]
+    #[test_case(ColumnType::Boolean, Value::Text("TRUE".into()) => Value::Boolean(true))]
+    #[test_case(ColumnType::Boolean, Value::Integer(0) => Value::Boolean(false))]
+    #[test_case(ColumnType::Text, Value::Null => Value::Null)]
+    fn coerce(column_type: ColumnType, value: Value) -> Value {
+        Column::new("c", column_type).coerce(value).unwrap()
+    }
+
+    #[test_case(ColumnType::Integer, Value::Real(1.43))]
+    #[test_case(ColumnType::Integer, Value::Text("12abc".into()))]
+    #[test_case(ColumnType::Real, Value::Text("one".into()))]
+    #[test_case(ColumnType::Boolean, Value::Integer(2))]
+    #[test_case(ColumnType::Blob, Value::Text("abc".into()))]
+    fn coerce_invalid(column_type: ColumnType, value: Value) {
+        assert!(Column::new("c", column_type).coerce(value).is_err());
+    }
+
+    #[test]
+    fn not_null_enforced() {
+        let mut column = Column::new("c", ColumnType::Text);
+        column.not_null = true;
+        assert!(column.coerce(Value::Null).is_err());
+        assert!(Column::new("id", ColumnType::Text)
+            .primary_key()
+            .coerce(Value::Null)
+            .is_err());
+        assert!(Column::new("id", ColumnType::Integer)
+            .primary_key()
+            .coerce(Value::Null)
+            .is_ok());
+    }
+
+    #[test]
+    fn text_length_enforced() {
+        let column = Column::new("username", ColumnType::Text).max_length(4);
+        assert!(column.coerce(Value::Text("karl".into())).is_ok());
+        assert!(column.coerce(Value::Text("karl2".into())).is_err());
+    }
+
+    #[test]
+    fn duplicate_column_rejected() {
+        let columns = vec![
+            Column::new("a", ColumnType::Integer),
+            Column::new("a", ColumnType::Text),
+        ];
+        assert!(Schema::new(columns).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+use super::ast::{Expr, OrderBy};
+use super::executor::{Node, Operator};
+use super::pager::{Pager, PAGE_SIZE};
+use super::projection::Projection;
+use super::value::Value;
+use super::{Error, Result};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::rc::Rc;
+use std::{mem, vec};
+
+/// Memory the rows held by a sort may take up before they are spilled to disk as a sorted run
+pub const SORT_MEMORY: usize = 4 << 20;
+/// Most rows a sort keeps in memory when only the first few are needed, beyond which it sorts all
+/// rows and may spill them to disk
+pub const TOP_N_ROWS: usize = 10_000;
+/// Pages of a spilled sort kept in memory, for writing a run and reading the runs being merged
+const SPILL_CACHE_SIZE: usize = 64;
+
+// tag preceding each value of a spilled record
+const TAG_NULL: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_REAL: u8 = 2;
+const TAG_TEXT: u8 = 3;
+const TAG_BLOB: u8 = 4;
+const TAG_BOOLEAN: u8 = 5;
+
+/// ORDER BY term resolved against the select list, sorting on one of its output columns
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortTerm {
+    pub position: usize,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl SortTerm {
+    /// Resolve ORDER BY terms, which like in SQLite may name a column of the results by its alias
+    /// or give its position, starting from 1. Terms sorting on anything else are added to the
+    /// projection as extra columns after those of the results.
+    pub fn resolve(order_by: &[OrderBy], projection: &mut Projection) -> Result<Vec<Self>> {
+        let count = projection.names.len();
+        order_by
+            .iter()
+            .enumerate()
+            .map(|(i, term)| {
+                let position = match &term.expr {
+                    Expr::Literal(Value::Integer(position)) => {
+                        if *position < 1 || *position as usize > count {
+                            return Err(Error::ExecutionError(format!(
+                                "ORDER BY term {} out of range - should be between 1 and {}",
+                                i + 1,
+                                count
+                            )));
+                        }
+                        *position as usize - 1
+                    }
+                    Expr::Column { table: None, name } => {
+                        match projection.names[..count]
+                            .iter()
+                            .position(|output| output == name)
+                        {
+                            Some(position) => position,
+                            None => projection.push(term.expr.clone()),
+                        }
+                    }
+                    expr => projection.push(expr.clone()),
+                };
+                Ok(Self {
+                    position,
+                    descending: term.descending,
+                    nulls_first: term.nulls_first,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Order of two rows by the values of their sort keys, one per term
+pub fn compare(terms: &[SortTerm], a: &[Value], b: &[Value]) -> Ordering {
+    for ((term, a), b) in terms.iter().zip(a).zip(b) {
+        let ordering = match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if term.nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if term.nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            (a, b) if term.descending => b.compare(a),
+            (a, b) => a.compare(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Sorts the rows of its input, each made of the output columns of the results followed by any
+/// extra columns computed only to sort on, which are dropped
+pub struct Sort<'a> {
+    input: Node<'a>,
+    positions: Vec<usize>,
+    /// Number of output columns of the results
+    width: usize,
+    buffer: Option<Buffer>,
+    sorted: Option<Sorted>,
+}
+
+impl<'a> Sort<'a> {
+    /// Sort rows on the given terms, only keeping the first `count` rows if limited
+    pub fn new(input: Node<'a>, terms: Vec<SortTerm>, width: usize, count: Option<usize>) -> Self {
+        let positions = terms.iter().map(|term| term.position).collect();
+        let buffer = match count {
+            Some(count) if count <= TOP_N_ROWS => Buffer::Top(TopN::new(terms, count)),
+            _ => Buffer::Full(Box::new(Sorter::new(terms))),
+        };
+        Self {
+            input,
+            positions,
+            width,
+            buffer: Some(buffer),
+            sorted: None,
+        }
+    }
+}
+
+impl Operator for Sort<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        if let Some(mut buffer) = self.buffer.take() {
+            while let Some(mut row) = self.input.next()? {
+                let keys = self.positions.iter().map(|&i| row[i].clone()).collect();
+                row.truncate(self.width);
+                match &mut buffer {
+                    Buffer::Full(sorter) => sorter.push(keys, row)?,
+                    Buffer::Top(top) => top.push(keys, row),
+                }
+            }
+            self.sorted = Some(match buffer {
+                Buffer::Full(sorter) => sorter.finish()?,
+                Buffer::Top(top) => top.finish(),
+            });
+        }
+        self.sorted.as_mut().unwrap().next().transpose()
+    }
+}
+
+/// Rows being sorted, all of them or only the first few
+enum Buffer {
+    Full(Box<Sorter>),
+    Top(TopN),
+}
+
+/// Row being sorted, preceded by the values of its sort keys
+type Entry = (Vec<Value>, Vec<Value>);
+
+/// External merge sort of result rows.
+///
+/// Rows are held in memory until they exceed the memory budget, at which point they are sorted
+/// and written out as a run to a temporary file through its own pager. The runs are then merged,
+/// each contributing its smallest remaining row in turn. Rows that sort equally keep the order
+/// they were added in.
+pub struct Sorter {
+    terms: Rc<[SortTerm]>,
+    budget: usize,
+    entries: Vec<Entry>,
+    /// Approximate memory taken up by the entries
+    size: usize,
+    spill: Option<Spill>,
+}
+
+/// Runs spilled out of memory, in the order they were written
+struct Spill {
+    pager: Pager,
+    runs: Vec<Run>,
+}
+
+impl Sorter {
+    pub fn new(terms: Vec<SortTerm>) -> Self {
+        Self::with_budget(terms, SORT_MEMORY)
+    }
+
+    pub fn with_budget(terms: Vec<SortTerm>, budget: usize) -> Self {
+        Self {
+            terms: terms.into(),
+            budget,
+            entries: Vec::new(),
+            size: 0,
+            spill: None,
+        }
+    }
+
+    /// Add a row along with the values of its sort keys, one per term
+    pub fn push(&mut self, keys: Vec<Value>, row: Vec<Value>) -> Result<()> {
+        self.size += footprint(&keys) + footprint(&row);
+        self.entries.push((keys, row));
+        if self.size > self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_entries(&mut self) {
+        let terms = &self.terms;
+        self.entries.sort_by(|(a, _), (b, _)| compare(terms, a, b));
+    }
+
+    /// Write the rows held in memory to disk as a sorted run
+    fn spill(&mut self) -> Result<()> {
+        self.sort_entries();
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(Spill {
+                pager: Pager::temporary(SPILL_CACHE_SIZE)?,
+                runs: Vec::new(),
+            }),
+        };
+        let mut run = Run::default();
+        let mut record = Vec::new();
+        for (keys, row) in self.entries.drain(..) {
+            record.clear();
+            encode_values(&mut record, &keys);
+            encode_values(&mut record, &row);
+            run.append(&spill.pager, &(record.len() as u32).to_be_bytes())?;
+            run.append(&spill.pager, &record)?;
+        }
+        spill.runs.push(run);
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Rows in sorted order
+    pub fn finish(mut self) -> Result<Sorted> {
+        if self.spill.is_none() {
+            self.sort_entries();
+            return Ok(Sorted::Memory(self.entries.into_iter()));
+        }
+        if !self.entries.is_empty() {
+            self.spill()?;
+        }
+        let Spill { pager, runs } = self.spill.unwrap();
+        let mut merge = Merge {
+            terms: self.terms,
+            pager,
+            runs: runs.into_iter().map(RunReader::new).collect(),
+            heads: BinaryHeap::new(),
+        };
+        for run in 0..merge.runs.len() {
+            merge.advance(run)?;
+        }
+        Ok(Sorted::Merge(Box::new(merge)))
+    }
+}
+
+/// Sort of which only the first rows are needed, as with ORDER BY and LIMIT.
+///
+/// Rather than sorting all rows, it keeps just the smallest `capacity` seen so far in a max-heap,
+/// dropping the largest whenever a smaller row comes along.
+pub struct TopN {
+    terms: Rc<[SortTerm]>,
+    capacity: usize,
+    heap: BinaryHeap<Ranked>,
+    added: usize,
+}
+
+impl TopN {
+    pub fn new(terms: Vec<SortTerm>, capacity: usize) -> Self {
+        Self {
+            terms: terms.into(),
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity.saturating_add(1)),
+            added: 0,
+        }
+    }
+
+    /// Add a row along with the values of its sort keys, one per term
+    pub fn push(&mut self, keys: Vec<Value>, row: Vec<Value>) {
+        let ranked = Ranked {
+            terms: self.terms.clone(),
+            keys,
+            row,
+            rank: self.added,
+        };
+        self.added += 1;
+        if self.heap.len() < self.capacity {
+            self.heap.push(ranked);
+        } else if self.heap.peek().is_some_and(|largest| ranked < *largest) {
+            self.heap.pop();
+            self.heap.push(ranked);
+        }
+    }
+
+    /// Smallest rows in sorted order
+    pub fn finish(self) -> Sorted {
+        let entries: Vec<_> = self
+            .heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|ranked| (ranked.keys, ranked.row))
+            .collect();
+        Sorted::Memory(entries.into_iter())
+    }
+}
+
+/// Rough number of bytes values take up in memory
+fn footprint(values: &[Value]) -> usize {
+    let heap: usize = values
+        .iter()
+        .map(|value| match value {
+            Value::Text(s) => s.len(),
+            Value::Blob(b) => b.len(),
+            _ => 0,
+        })
+        .sum();
+    mem::size_of_val(values) + heap
+}
+
+/// Rows coming out of a sort
+pub enum Sorted {
+    Memory(vec::IntoIter<Entry>),
+    Merge(Box<Merge>),
+}
+
+impl Iterator for Sorted {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Memory(entries) => entries.next().map(|(_, row)| Ok(row)),
+            Self::Merge(merge) => merge.next().transpose(),
+        }
+    }
+}
+
+/// Merge of sorted runs, keeping the next row of each run in a heap
+pub struct Merge {
+    terms: Rc<[SortTerm]>,
+    pager: Pager,
+    runs: Vec<RunReader>,
+    heads: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl Merge {
+    /// Put the next row of a run into the heap, if it has one left
+    fn advance(&mut self, run: usize) -> Result<()> {
+        if let Some(record) = self.runs[run].next(&self.pager)? {
+            let mut keys = decode_values(&record);
+            let row = keys.split_off(self.terms.len());
+            self.heads.push(Reverse(Ranked {
+                terms: self.terms.clone(),
+                keys,
+                row,
+                rank: run,
+            }));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        let Some(Reverse(head)) = self.heads.pop() else {
+            return Ok(None);
+        };
+        self.advance(head.rank)?;
+        Ok(Some(head.row))
+    }
+}
+
+/// Row kept in a heap, ordered by its sort keys
+struct Ranked {
+    terms: Rc<[SortTerm]>,
+    keys: Vec<Value>,
+    row: Vec<Value>,
+    /// Run a merged row came from, or position a row of a top-N sort was added in, both of which
+    /// break ties so that earlier rows come first and the sort stays stable
+    rank: usize,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.terms, &self.keys, &other.keys).then(self.rank.cmp(&other.rank))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// Sorted records written one after another across pages of the spill file, each preceded by its
+/// length. A record may continue from one page onto the next.
+#[derive(Default)]
+struct Run {
+    pages: Vec<usize>,
+    len: usize,
+}
+
+impl Run {
+    fn append(&mut self, pager: &Pager, mut bytes: &[u8]) -> Result<()> {
+        while !bytes.is_empty() {
+            let offset = self.len % PAGE_SIZE;
+            if offset == 0 {
+                self.pages.push(pager.allocate_page()?);
+            }
+            let count = bytes.len().min(PAGE_SIZE - offset);
+            let mut page = pager.borrow_page_mut(*self.pages.last().unwrap())?;
+            page.as_mut_slice()[offset..offset + count].copy_from_slice(&bytes[..count]);
+            bytes = &bytes[count..];
+            self.len += count;
+        }
+        Ok(())
+    }
+}
+
+/// Position reached in reading back a run
+struct RunReader {
+    run: Run,
+    position: usize,
+}
+
+impl RunReader {
+    fn new(run: Run) -> Self {
+        Self { run, position: 0 }
+    }
+
+    fn read(&mut self, pager: &Pager, buffer: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let offset = self.position % PAGE_SIZE;
+            let count = (buffer.len() - filled).min(PAGE_SIZE - offset);
+            let page = pager.borrow_page(self.run.pages[self.position / PAGE_SIZE])?;
+            buffer[filled..filled + count]
+                .copy_from_slice(&page.as_slice()[offset..offset + count]);
+            filled += count;
+            self.position += count;
+        }
+        Ok(())
+    }
+
+    /// Next record of the run, if it has one left
+    fn next(&mut self, pager: &Pager) -> Result<Option<Vec<u8>>> {
+        if self.position == self.run.len {
+            return Ok(None);
+        }
+        let mut len = [0; 4];
+        self.read(pager, &mut len)?;
+        let mut record = vec![0; u32::from_be_bytes(len) as usize];
+        self.read(pager, &mut record)?;
+        Ok(Some(record))
+    }
+}
+
+/// Serialize values along with their types, as results are not bound to any schema
+fn encode_values(record: &mut Vec<u8>, values: &[Value]) {
+    for value in values {
+        match value {
+            Value::Null => record.push(TAG_NULL),
+            Value::Integer(i) => {
+                record.push(TAG_INTEGER);
+                record.extend_from_slice(&i.to_be_bytes());
+            }
+            Value::Real(r) => {
+                record.push(TAG_REAL);
+                record.extend_from_slice(&r.to_be_bytes());
+            }
+            Value::Text(s) => {
+                record.push(TAG_TEXT);
+                record.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                record.extend_from_slice(s.as_bytes());
+            }
+            Value::Blob(b) => {
+                record.push(TAG_BLOB);
+                record.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                record.extend_from_slice(b);
+            }
+            Value::Boolean(b) => {
+                record.push(TAG_BOOLEAN);
+                record.push(*b as u8);
+            }
+        }
+    }
+}
+
+/// Deserialize values written by `encode_values`, which only ever come from this process
+fn decode_values(mut record: &[u8]) -> Vec<Value> {
+    fn take<'a>(record: &mut &'a [u8], len: usize) -> &'a [u8] {
+        let (bytes, rest) = record.split_at(len);
+        *record = rest;
+        bytes
+    }
+    fn take_bytes<'a>(record: &mut &'a [u8]) -> &'a [u8] {
+        let len = u32::from_be_bytes(take(record, 4).try_into().unwrap()) as usize;
+        take(record, len)
+    }
+    let mut values = Vec::new();
+    while let Some((&tag, rest)) = record.split_first() {
+        record = rest;
+        values.push(match tag {
+            TAG_NULL => Value::Null,
+            TAG_INTEGER => {
+                Value::Integer(i64::from_be_bytes(take(&mut record, 8).try_into().unwrap()))
+            }
+            TAG_REAL => Value::Real(f64::from_be_bytes(take(&mut record, 8).try_into().unwrap())),
+            TAG_TEXT => Value::Text(String::from_utf8_lossy(take_bytes(&mut record)).into_owned()),
+            TAG_BLOB => Value::Blob(take_bytes(&mut record).to_vec()),
+            TAG_BOOLEAN => Value::Boolean(take(&mut record, 1)[0] != 0),
+            tag => unreachable!("invalid value tag {}", tag),
+        });
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(position: usize, descending: bool, nulls_first: bool) -> SortTerm {
+        SortTerm {
+            position,
+            descending,
+            nulls_first,
+        }
+    }
+
+    fn keys(terms: &[SortTerm], row: &[Value]) -> Vec<Value> {
+        terms
+            .iter()
+            .map(|term| row[term.position].clone())
+            .collect()
+    }
+
+    fn sort(terms: Vec<SortTerm>, budget: usize, rows: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        let mut sorter = Sorter::with_budget(terms.clone(), budget);
+        for row in rows {
+            sorter.push(keys(&terms, row), row.clone()).unwrap();
+        }
+        sorter.finish().unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn multiple_keys_and_nulls() {
+        let row = |a: Option<i64>, b: &str| {
+            vec![a.map_or(Value::Null, Value::Integer), Value::Text(b.into())]
+        };
+        let rows = vec![
+            row(Some(1), "b"),
+            row(None, "a"),
+            row(Some(2), "a"),
+            row(Some(1), "a"),
+            row(None, "b"),
+        ];
+        let sorted = sort(
+            vec![term(0, false, true), term(1, true, false)],
+            SORT_MEMORY,
+            &rows,
+        );
+        assert_eq!(
+            sorted,
+            vec![
+                rows[4].clone(),
+                rows[1].clone(),
+                rows[0].clone(),
+                rows[3].clone(),
+                rows[2].clone()
+            ]
+        );
+        let sorted = sort(vec![term(0, true, false)], SORT_MEMORY, &rows);
+        assert_eq!(
+            sorted,
+            vec![
+                rows[2].clone(),
+                rows[0].clone(),
+                rows[3].clone(),
+                rows[1].clone(),
+                rows[4].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn spilled_runs_merged() {
+        // scrambled keys, each repeated to check that equal rows keep their order across runs
+        let rows: Vec<Vec<Value>> = (0..3000)
+            .map(|i: i64| {
+                vec![
+                    Value::Integer((i * 379) % 1000),
+                    Value::Text(format!("row {} {}", i, "x".repeat(50))),
+                ]
+            })
+            .collect();
+        let mut sorter = Sorter::with_budget(vec![term(0, false, true)], 10_000);
+        for row in &rows {
+            sorter.push(vec![row[0].clone()], row.clone()).unwrap();
+        }
+        assert!(sorter.spill.as_ref().unwrap().runs.len() > 10);
+        let sorted: Vec<_> = sorter.finish().unwrap().map(Result::unwrap).collect();
+        let mut expected = rows.clone();
+        expected.sort_by(|a, b| a[0].compare(&b[0]));
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn top_rows_kept() {
+        let rows: Vec<Vec<Value>> = (0..500)
+            .map(|i: i64| vec![Value::Integer((i * 379) % 100), Value::Integer(i)])
+            .collect();
+        let mut top = TopN::new(vec![term(0, true, false)], 12);
+        for row in &rows {
+            top.push(vec![row[0].clone()], row.clone());
+        }
+        let sorted: Vec<_> = top.finish().map(Result::unwrap).collect();
+        let expected = sort(vec![term(0, true, false)], SORT_MEMORY, &rows);
+        assert_eq!(sorted, expected[..12]);
+    }
+
+    #[test]
+    fn values_roundtrip() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(-7),
+            Value::Real(2.5),
+            Value::Text("karl".into()),
+            Value::Blob(vec![0, 1]),
+            Value::Boolean(true),
+        ];
+        let mut record = Vec::new();
+        encode_values(&mut record, &values);
+        assert_eq!(decode_values(&record), values);
+    }
+}
@@ -1,74 +1,388 @@
-use super::row::InputRow;
-use super::{Database, Error, Result, Token, Tokens};
+use super::ast::{CreateIndex, Delete, Expr, Insert, Select, Update};
+use super::eval::{self, Scope};
+use super::executor;
+use super::parser::Parser;
+use super::planner;
+use super::row::Row;
+use super::schema::{Column, Schema};
+use super::value::Value;
+use super::{Database, Error, Result, Tokens};
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum Statement {
-    Insert(InputRow),
-    Select,
+    CreateTable { name: String, columns: Vec<Column> },
+    DropTable { name: String, if_exists: bool },
+    CreateIndex(CreateIndex),
+    DropIndex { name: String, if_exists: bool },
+    Insert(Insert),
+    Select(Box<Select>),
+    Update(Update),
+    Delete(Delete),
+    Begin,
+    Commit,
+    Rollback,
+    Vacuum,
     None,
 }
 
 impl Statement {
-    pub fn parse(mut tokens: Tokens) -> Result<Self> {
-        match tokens.next() {
-            None | Some(Token::None) => Ok(Self::None),
-            Some(Token::Meta(meta)) => Err(Error::SyntaxError(format!(
-                "encountered meta token '{}' when SQL token was expected",
-                meta
-            ))),
-            Some(Token::Other(s)) => match s {
-                "insert" => Ok(Self::Insert(InputRow::parse(&mut tokens)?)),
-                "select" => Ok(Self::Select),
-                keyword => Err(Error::SyntaxError(format!("unknown keyword '{}'", keyword))),
-            },
-        }
+    pub fn parse(tokens: Tokens) -> Result<Self> {
+        Parser::new(tokens).parse_statement()
     }
 
     pub fn execute(&self, db: &mut Database) -> Result<()> {
         eprintln!("Executing...");
         match self {
-            Self::Insert(row) => {
-                db.insert(&row.validate()?)?;
+            Self::CreateTable { name, columns } => {
+                db.create_table(name, Schema::new(columns.clone())?)
+            }
+            Self::DropTable { name, if_exists } => db.drop_table(name, *if_exists),
+            Self::CreateIndex(create) => db.create_index(
+                &create.name,
+                &create.table,
+                create.columns.clone(),
+                create.unique,
+            ),
+            Self::DropIndex { name, if_exists } => db.drop_index(name, *if_exists),
+            // each statement is applied as a whole, so a failing row undoes the ones before it
+            Self::Insert(insert) => db.autocommit(|db| execute_insert(db, insert)),
+            Self::Select(select) => db.read(|db| execute_select(db, select)),
+            // the rows to modify are found under the same lock that modifies them
+            Self::Update(update) => {
+                let updated = db.autocommit(|db| execute_update(db, update))?;
+                println!("{} rows updated", updated);
                 Ok(())
             }
-            Self::Select => {
-                for row in db.select()? {
-                    let row = InputRow::from(&row);
-                    println!("{}", row);
-                }
+            Self::Delete(delete) => {
+                let deleted = db.autocommit(|db| {
+                    let rows = matching_rows(db, &delete.table, delete.filter.as_ref())?;
+                    let rowids: Vec<i64> = rows.into_iter().map(|(rowid, _)| rowid).collect();
+                    db.delete(&delete.table, &rowids)
+                })?;
+                println!("{} rows deleted", deleted);
                 Ok(())
             }
+            Self::Begin => db.begin(),
+            Self::Commit => db.commit(),
+            Self::Rollback => db.rollback(),
+            Self::Vacuum => db.vacuum(),
             Self::None => Ok(()),
         }
     }
 }
 
+fn execute_insert(db: &mut Database, insert: &Insert) -> Result<()> {
+    let schema = &db.table(&insert.table)?.schema;
+    // position in the schema of each value given per row
+    let positions = match &insert.columns {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                schema
+                    .columns
+                    .iter()
+                    .position(|column| &column.name == name)
+                    .ok_or_else(|| {
+                        Error::ExecutionError(format!(
+                            "table {} has no column named {}",
+                            insert.table, name
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => (0..schema.columns.len()).collect(),
+    };
+    let width = schema.columns.len();
+    let mut rows = Vec::with_capacity(insert.rows.len());
+    for row in &insert.rows {
+        if row.len() != positions.len() {
+            return Err(Error::ExecutionError(format!(
+                "expected {} values but found {}",
+                positions.len(),
+                row.len()
+            )));
+        }
+        let mut values = vec![Value::Null; width];
+        for (&position, expr) in positions.iter().zip(row) {
+            values[position] = eval::evaluate(expr, &Scope::default(), &[])?;
+        }
+        rows.push(values);
+    }
+    db.insert_rows(&insert.table, rows)
+}
+
+fn execute_select(db: &Database, select: &Select) -> Result<()> {
+    let (names, mut node) = executor::plan(db, select)?;
+    println!("{}", names.join(","));
+    while let Some(row) = node.next()? {
+        println!("{}", Row::new(row));
+    }
+    Ok(())
+}
+
+fn execute_update(db: &mut Database, update: &Update) -> Result<usize> {
+    let schema = &db.table(&update.table)?.schema;
+    let scope = Scope::table(&update.table, schema);
+    let assignments = update
+        .assignments
+        .iter()
+        .map(|(name, expr)| Ok((scope.resolve(None, name)?, expr)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut rows = matching_rows(db, &update.table, update.filter.as_ref())?;
+    for (_, row) in &mut rows {
+        // every assignment sees the values the row had before the update
+        let old = row.values.clone();
+        for &(position, expr) in &assignments {
+            let value = eval::evaluate(expr, &scope, &old)?;
+            row.values[position] = schema.columns[position].coerce(value)?;
+        }
+    }
+    db.update(&update.table, rows)
+}
+
+/// Rows of a table satisfying an optional condition, collected up front so that the table can
+/// then be modified without disturbing the scan
+fn matching_rows(db: &Database, table: &str, filter: Option<&Expr>) -> Result<Vec<(i64, Row)>> {
+    let scope = Scope::table(table, &db.table(table)?.schema);
+    let mut rows = Vec::new();
+    for row in planner::scan(db, table, &scope, filter)? {
+        let (rowid, row) = row?;
+        if let Some(filter) = filter {
+            if !eval::matches(filter, &scope, &row.values)? {
+                continue;
+            }
+        }
+        rows.push((rowid, row));
+    }
+    Ok(rows)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sql::ast::{BinaryOperator, Join, JoinKind, OrderBy, SelectItem, TableRef};
+    use crate::sql::parser::tests::parse_select;
+    use crate::sql::schema::ColumnType;
     use test_case::test_case;
 
+    fn parse(raw: &str) -> Statement {
+        Statement::parse(Tokens::from(raw)).unwrap()
+    }
+
     // do we really want to test this?
     #[test]
     fn parse_none() {
-        let tokens = Tokens::from("");
-        assert_eq!(Statement::parse(tokens).unwrap(), Statement::None);
+        assert_eq!(parse(""), Statement::None);
     }
 
-    // #[test_case("select" => Statement::Select ; "select")]
-    // #[test_case("insert" => Statement::Insert ; "insert")]
-    // fn parse_meta_valid(raw: &str) -> Statement {
-    //     let tokens = Tokens::from(raw);
-    //     Statement::parse(tokens).unwrap()
-    // }
+    #[test]
+    fn parse_joins() {
+        let select =
+            parse_select("select * from a, b join c on c.id = b.id left outer join d cross join e")
+                .unwrap();
+        let join = |kind, name: &str, on: bool| Join {
+            kind,
+            table: TableRef {
+                name: name.into(),
+                alias: None,
+            },
+            on: on.then(|| Expr::Binary {
+                left: Box::new(Expr::Column {
+                    table: Some("c".into()),
+                    name: "id".into(),
+                }),
+                operator: BinaryOperator::Equal,
+                right: Box::new(Expr::Column {
+                    table: Some("b".into()),
+                    name: "id".into(),
+                }),
+            }),
+        };
+        assert_eq!(
+            select.joins,
+            vec![
+                join(JoinKind::Inner, "b", false),
+                join(JoinKind::Inner, "c", true),
+                join(JoinKind::Left, "d", false),
+                join(JoinKind::Inner, "e", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_bare_select() {
+        let Statement::Select(select) = parse("select") else {
+            panic!("expected select");
+        };
+        assert_eq!(select.projection, vec![SelectItem::Wildcard(None)]);
+        assert_eq!(select.from.unwrap().name, "users");
+    }
+
+    #[test]
+    fn parse_full_select() {
+        assert_eq!(
+            parse("select u.*, id * 2 as double, name n from users u where id > 1 group by name, 2 having count(*) > 1 order by name desc, id nulls last limit 10 offset 5;"),
+            Statement::Select(Box::new(Select {
+                projection: vec![
+                    SelectItem::Wildcard(Some("u".into())),
+                    SelectItem::Expr {
+                        expr: Expr::Binary {
+                            left: Box::new(Expr::column("id")),
+                            operator: BinaryOperator::Multiply,
+                            right: Box::new(Expr::Literal(Value::Integer(2))),
+                        },
+                        alias: Some("double".into()),
+                    },
+                    SelectItem::Expr {
+                        expr: Expr::column("name"),
+                        alias: Some("n".into()),
+                    },
+                ],
+                from: Some(TableRef {
+                    name: "users".into(),
+                    alias: Some("u".into()),
+                }),
+                joins: Vec::new(),
+                filter: Some(Expr::Binary {
+                    left: Box::new(Expr::column("id")),
+                    operator: BinaryOperator::Greater,
+                    right: Box::new(Expr::Literal(Value::Integer(1))),
+                }),
+                group_by: vec![Expr::column("name"), Expr::Literal(Value::Integer(2))],
+                having: Some(Expr::Binary {
+                    left: Box::new(Expr::Function {
+                        name: "count".into(),
+                        args: Vec::new(),
+                        distinct: false,
+                        wildcard: true,
+                    }),
+                    operator: BinaryOperator::Greater,
+                    right: Box::new(Expr::Literal(Value::Integer(1))),
+                }),
+                order_by: vec![
+                    OrderBy {
+                        expr: Expr::column("name"),
+                        descending: true,
+                        nulls_first: false,
+                    },
+                    OrderBy {
+                        expr: Expr::column("id"),
+                        descending: false,
+                        nulls_first: false,
+                    },
+                ],
+                limit: Some(Expr::Literal(Value::Integer(10))),
+                offset: Some(Expr::Literal(Value::Integer(5))),
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_insert() {
+        let one = || Expr::Literal(Value::Integer(1));
+        let text = |s: &str| Expr::Literal(Value::Text(s.into()));
+        assert_eq!(
+            parse("insert into t (a, b) values (1, 'x'), (1, 'y')"),
+            Statement::Insert(Insert {
+                table: "t".into(),
+                columns: Some(vec!["a".into(), "b".into()]),
+                rows: vec![vec![one(), text("x")], vec![one(), text("y")]],
+            })
+        );
+        assert_eq!(
+            parse("insert 1 'a'"),
+            Statement::Insert(Insert {
+                table: "users".into(),
+                columns: None,
+                rows: vec![vec![one(), text("a")]],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_update_and_delete() {
+        let filter = Some(Expr::IsNull {
+            operand: Box::new(Expr::column("b")),
+            negated: false,
+        });
+        assert_eq!(
+            parse("update t set a = 1 where b is null"),
+            Statement::Update(Update {
+                table: "t".into(),
+                assignments: vec![("a".into(), Expr::Literal(Value::Integer(1)))],
+                filter: filter.clone(),
+            })
+        );
+        assert_eq!(
+            parse("delete from t where b is null"),
+            Statement::Delete(Delete {
+                table: "t".into(),
+                filter,
+            })
+        );
+    }
+
+    #[test_case("begin" => Statement::Begin ; "begin")]
+    #[test_case("begin transaction;" => Statement::Begin ; "begin transaction")]
+    #[test_case("commit" => Statement::Commit ; "commit")]
+    #[test_case("rollback transaction" => Statement::Rollback ; "rollback")]
+    fn parse_transactions(raw: &str) -> Statement {
+        parse(raw)
+    }
+
+    #[test_case("drop table t" => Statement::DropTable { name: "t".into(), if_exists: false } ; "drop table")]
+    #[test_case("drop index if exists i" => Statement::DropIndex { name: "i".into(), if_exists: true } ; "drop index")]
+    #[test_case("create unique index i on t (a, b)" => Statement::CreateIndex(CreateIndex { name: "i".into(), table: "t".into(), columns: vec!["a".into(), "b".into()], unique: true }) ; "create index")]
+    #[test_case("vacuum;" => Statement::Vacuum ; "vacuum")]
+    fn parse_schema_changes(raw: &str) -> Statement {
+        parse(raw)
+    }
+
+    #[test]
+    fn parse_create_table() {
+        let mut name = Column::new("name", ColumnType::Text).max_length(32);
+        name.not_null = true;
+        assert_eq!(
+            parse("create table t (id integer primary key, name text(32) not null, score real);"),
+            Statement::CreateTable {
+                name: "t".into(),
+                columns: vec![
+                    Column::new("id", ColumnType::Integer).primary_key(),
+                    name,
+                    Column::new("score", ColumnType::Real),
+                ],
+            }
+        );
+    }
 
     #[test_case("fake")]
     #[test_case("placeholder")]
-    fn parse_meta_invalid(raw: &str) {
+    #[test_case("create index i")]
+    #[test_case("create table t id integer")]
+    #[test_case("create table (id integer)")]
+    #[test_case("create table t (id integer primary)")]
+    #[test_case("create table t (id)")]
+    #[test_case("create table t (id thing)")]
+    #[test_case("select id from")]
+    #[test_case("select * from t a b")]
+    #[test_case("insert into t (a) 1")]
+    #[test_case("insert into t values (1,)")]
+    #[test_case("update t a = 1")]
+    #[test_case("delete t")]
+    #[test_case("drop view v")]
+    #[test_case("insert 'open")]
+    fn parse_invalid(raw: &str) {
         let tokens = Tokens::from(raw);
         assert!(matches!(
             Statement::parse(tokens).unwrap_err(),
             super::Error::SyntaxError(_)
         ))
     }
+
+    #[test_case("select * form t" => "syntax error: expected end of statement but found 'form' at position 9" ; "misspelt keyword")]
+    #[test_case("select * from t where" => "syntax error: expected an expression but found end of input" ; "missing expression")]
+    #[test_case("select 1 + @" => "syntax error: unrecognized token at position 11" ; "bad character")]
+    fn error_points_at_token(raw: &str) -> String {
+        Statement::parse(Tokens::from(raw)).unwrap_err().to_string()
+    }
 }
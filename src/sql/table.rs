@@ -1,176 +1,297 @@
-use crate::sql::pager::{self, Pager};
-use crate::sql::row::{self, ValidatedRow};
+use crate::sql::btree::{BTree, Cursor, Node};
+use crate::sql::pager::Pager;
+use crate::sql::row::Row;
+use crate::sql::schema::Schema;
+use crate::sql::value::Value;
 use crate::sql::{Error, Result};
-use std::cell::{Ref, RefMut};
-use std::ops::Range;
-use std::path::PathBuf;
+use std::collections::HashSet;
 
-pub struct Table {
-    pager: Pager,
-    num_rows: usize,
+/// Counters describing a table's contents, persisted in the catalog so they survive reopening
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TableStats {
+    pub row_count: usize,
+    pub next_rowid: i64,
 }
 
-impl Table {
-    pub fn open(path: Option<&PathBuf>) -> Result<Self> {
-        let pager = Pager::open(path)?;
-        let num_rows = pager.len() / row::ROW_SIZE;
-        Ok(Table { pager, num_rows })
+impl Default for TableStats {
+    fn default() -> Self {
+        Self {
+            row_count: 0,
+            next_rowid: 1,
+        }
     }
+}
 
-    pub fn num_rows(&self) -> usize {
-        self.num_rows
+/// Table stored as a B+tree of rows, keyed on its INTEGER PRIMARY KEY or else an implicit rowid
+pub struct Table {
+    pub name: String,
+    pub schema: Schema,
+    root: usize,
+    stats: TableStats,
+}
+
+impl Table {
+    /// Load a table whose tree already exists at `root`
+    pub fn open(name: &str, schema: Schema, root: usize, stats: TableStats) -> Self {
+        Self {
+            name: name.into(),
+            schema,
+            root,
+            stats,
+        }
     }
 
-    /// insert a row into the table
-    pub fn insert(&mut self, row: &ValidatedRow) -> Result<()> {
-        if self.num_rows == MAX_ROWS {
-            return Err(Error::TableFullError(MAX_ROWS));
+    /// Load a table whose stats are not stored anywhere, recomputing them by walking every row
+    pub fn open_and_count(pager: &Pager, name: &str, schema: Schema, root: usize) -> Result<Self> {
+        let mut stats = TableStats::default();
+        for entry in Cursor::start(pager, root)? {
+            let (key, _) = entry?;
+            stats.row_count += 1;
+            stats.next_rowid = stats.next_rowid.max(decode_key(&key).saturating_add(1));
         }
-        let result = {
-            let mut cursor = Cursor::end(&self);
-            let mut slice = &mut *cursor.row_mut()?;
-            let result = row.write(&mut slice);
-            result
-        };
-        // only increment row counter if insert succeeded
-        let result = result.map(|_| self.num_rows += 1);
-        result
+        Ok(Self::open(name, schema, root, stats))
     }
 
-    /// select and return all rows from the table
-    pub fn select(&self) -> Result<Results> {
-        Ok(Results::new(Cursor::start(&self)))
+    /// Create an empty table in newly allocated pages
+    pub fn create(pager: &Pager, name: &str, schema: Schema) -> Result<Self> {
+        let root = BTree::create(pager)?.root();
+        Ok(Self::open(name, schema, root, TableStats::default()))
     }
-}
 
-pub struct Results<'a> {
-    cursor: Cursor<'a>
-}
+    pub fn root(&self) -> usize {
+        self.root
+    }
 
-impl<'a> Results<'a> {
-    fn new(cursor: Cursor<'a>) -> Self {
-        Self { cursor }
+    pub fn stats(&self) -> TableStats {
+        self.stats
     }
-}
 
-impl<'a> Iterator for Results<'a> {
-    type Item = ValidatedRow;
+    fn tree<'a>(&self, pager: &'a Pager) -> BTree<'a> {
+        BTree::new(pager, self.root)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(buffer) = self.cursor.next() {
-            let buffer = *buffer.unwrap();
-            Some(ValidatedRow::read(&buffer))
-        } else {
-            None
+    /// Rowid a new row will be stored under, filling in its INTEGER PRIMARY KEY if missing with
+    /// the next rowid to hand out
+    pub fn assign_rowid(&self, row: &mut Row, next_rowid: i64) -> i64 {
+        match self.schema.rowid_column() {
+            Some(column) => match row.values[column] {
+                Value::Integer(key) => key,
+                // like SQLite, a missing INTEGER PRIMARY KEY is assigned the next rowid
+                _ => {
+                    row.values[column] = Value::Integer(next_rowid);
+                    next_rowid
+                }
+            },
+            None => next_rowid,
         }
     }
-}
 
-struct Cursor<'a> {
-    table: &'a Table,
-    row_num: usize,
-}
+    /// Check that no row is stored under a rowid yet, as values of an INTEGER PRIMARY KEY must be
+    /// unique; other tables pick fresh rowids themselves
+    pub fn check_rowid(&self, pager: &Pager, rowid: i64) -> Result<()> {
+        let Some(column) = self.schema.rowid_column() else {
+            return Ok(());
+        };
+        if self.tree(pager).find(&encode_key(rowid))?.is_some() {
+            return Err(self.duplicate_rowid(column, rowid));
+        }
+        Ok(())
+    }
 
-impl<'a> Cursor<'a> {
-    pub fn start(table: &'a Table) -> Self {
-        Self { table, row_num: 0 }
+    /// Error for a rowid already taken by another row, given the position of the INTEGER PRIMARY
+    /// KEY holding it
+    fn duplicate_rowid(&self, column: usize, rowid: i64) -> Error {
+        Error::ConstraintViolation {
+            table: self.name.clone(),
+            column: self.schema.columns[column].name.clone(),
+            key: rowid.to_string(),
+        }
     }
 
-    pub fn end(table: &'a Table) -> Self {
-        Self { table, row_num: table.num_rows() }
+    /// Check that a row about to be written does not take a rowid that another one is taking in
+    /// the same statement
+    pub fn check_pending_rowid(&self, pending: &mut HashSet<i64>, rowid: i64) -> Result<()> {
+        match self.schema.rowid_column() {
+            Some(column) if !pending.insert(rowid) => Err(self.duplicate_rowid(column, rowid)),
+            _ => Ok(()),
+        }
     }
 
-    pub fn at_end(&self) -> bool {
-        self.row_num == self.table.num_rows()
+    /// insert a row into the table, returning its rowid
+    pub fn insert(&mut self, pager: &Pager, mut row: Row) -> Result<i64> {
+        let next_rowid = self.stats.next_rowid;
+        let rowid = self.assign_rowid(&mut row, next_rowid);
+        self.tree(pager)
+            .insert(&encode_key(rowid), &row.encode(&self.schema))?;
+        self.stats.row_count += 1;
+        self.stats.next_rowid = next_rowid.max(rowid.saturating_add(1));
+        Ok(rowid)
     }
 
-    fn row(&self) -> Result<Ref<'a, [u8; row::ROW_SIZE]>> {
-        let index = PageIndex::from(self.row_num);
-        let page = self.table.pager.borrow_page(index.page)?;
-        let row = Ref::map(page, |page| {
-            let slice = &page.as_slice()[index.range()];
-            let buffer: &[u8; row::ROW_SIZE] = slice.try_into().unwrap();
-            buffer
-        });
-        Ok(row)
+    /// Look up the row with the given rowid
+    pub fn get(&self, pager: &Pager, rowid: i64) -> Result<Option<Row>> {
+        let tree = self.tree(pager);
+        let Some((page, cell)) = tree.find(&encode_key(rowid))? else {
+            return Ok(None);
+        };
+        let page = pager.borrow_page(page)?;
+        let node = Node::new(page.as_slice())?;
+        Ok(Some(Row::decode(&self.schema, node.value(cell))?))
     }
 
-    fn row_mut(&mut self) -> Result<RefMut<'a, [u8; row::ROW_SIZE]>> {
-        let index = PageIndex::from(self.row_num);
-        let page = self.table.pager.borrow_page_mut(index.page)?;
-        let row = RefMut::map(page, |page| {
-            let slice = &mut page.as_mut_slice()[index.range()];
-            let buffer: &mut [u8; row::ROW_SIZE] = slice.try_into().unwrap();
-            buffer
-        });
-        Ok(row)
+    /// Overwrite the row with the given rowid, returning false if there is no such row
+    pub fn replace(&mut self, pager: &Pager, rowid: i64, row: &Row) -> Result<bool> {
+        self.tree(pager)
+            .replace(&encode_key(rowid), &row.encode(&self.schema))
     }
-}
 
-impl<'a> Iterator for Cursor<'a> {
-    type Item = Result<Ref<'a, [u8; row::ROW_SIZE]>>;
+    /// Overwrite the row with the given rowid, moving it to a new key if its INTEGER PRIMARY KEY
+    /// was changed, and return false if there is no such row
+    pub fn update(&mut self, pager: &Pager, rowid: i64, row: Row) -> Result<bool> {
+        let moved = match self.schema.rowid_column() {
+            Some(column) => row.values[column] != Value::Integer(rowid),
+            None => false,
+        };
+        if !moved {
+            return self.replace(pager, rowid, &row);
+        }
+        if !self.delete(pager, rowid)? {
+            return Ok(false);
+        }
+        self.insert(pager, row)?;
+        Ok(true)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if !self.at_end() {
-            let row = Some(self.row());
-            self.row_num += 1;
-            row
-        } else {
-            None
+    /// Remove the row with the given rowid, returning false if there is no such row
+    pub fn delete(&mut self, pager: &Pager, rowid: i64) -> Result<bool> {
+        let removed = self.tree(pager).remove(&encode_key(rowid))?;
+        if removed {
+            self.stats.row_count -= 1;
         }
+        Ok(removed)
+    }
+
+    /// Hand the pages of the table back to the pager
+    pub fn destroy(self, pager: &Pager) -> Result<()> {
+        self.tree(pager).destroy()
+    }
+
+    /// Move the pages of the table numbered `limit` or above into free pages from `destinations`,
+    /// including the root
+    pub fn relocate(
+        &mut self,
+        pager: &Pager,
+        limit: usize,
+        destinations: &mut Vec<usize>,
+    ) -> Result<()> {
+        self.root = self.tree(pager).relocate(limit, destinations)?;
+        Ok(())
+    }
+
+    /// select and return all rows from the table along with their rowids
+    pub fn select<'a>(&'a self, pager: &'a Pager) -> Result<Results<'a>> {
+        Ok(Results::new(&self.schema, Cursor::start(pager, self.root)?))
     }
 }
 
-#[derive(Debug)]
-struct PageIndex {
-    page: usize,
-    offset: usize,
+/// Encode a row key so that byte-wise comparison matches numeric order
+pub fn encode_key(key: i64) -> [u8; 8] {
+    ((key as u64) ^ (1 << 63)).to_be_bytes()
 }
 
-impl PageIndex {
-    fn range(&self) -> Range<usize> {
-        let end = self.offset + row::ROW_SIZE;
-        self.offset..end
-    }
+pub fn decode_key(key: &[u8]) -> i64 {
+    (u64::from_be_bytes(key.try_into().unwrap()) ^ (1 << 63)) as i64
 }
 
-impl From<usize> for PageIndex {
-    fn from(row_index: usize) -> Self {
-        PageIndex {
-            page: row_index / ROWS_PER_PAGE,
-            offset: row::ROW_SIZE * (row_index % ROWS_PER_PAGE),
-        }
-    }
+pub struct Results<'a> {
+    schema: &'a Schema,
+    cursor: Cursor<'a>,
 }
 
-impl From<PageIndex> for usize {
-    fn from(page_index: PageIndex) -> Self {
-        page_index.page * ROWS_PER_PAGE + (page_index.offset / row::ROW_SIZE)
+impl<'a> Results<'a> {
+    fn new(schema: &'a Schema, cursor: Cursor<'a>) -> Self {
+        Self { schema, cursor }
     }
 }
 
-const ROWS_PER_PAGE: usize = pager::PAGE_SIZE / row::ROW_SIZE;
-const MAX_ROWS: usize = ROWS_PER_PAGE * pager::MAX_PAGES;
+impl<'a> Iterator for Results<'a> {
+    type Item = Result<(i64, Row)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next().map(|entry| {
+            let (key, record) = entry?;
+            Ok((decode_key(&key), Row::decode(self.schema, &record)?))
+        })
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::sql::row::InputRow;
+    use crate::sql::schema::{Column, ColumnType};
+
+    fn schema(rowid: bool) -> Schema {
+        let id = Column::new("id", ColumnType::Integer);
+        let id = if rowid { id.primary_key() } else { id };
+        Schema::new(vec![id, Column::new("username", ColumnType::Text)]).unwrap()
+    }
+
+    fn sample_row(id: i64) -> Row {
+        Row::new(vec![Value::Integer(id), Value::Text(format!("karl{}", id))])
+    }
+
+    fn select_all(pager: &Pager, table: &Table) -> Vec<Row> {
+        table
+            .select(pager)
+            .unwrap()
+            .map(|row| row.unwrap().1)
+            .collect()
+    }
 
     #[test]
     fn insert_and_select() {
-        let sample_row = InputRow {
-            id: "1".into(),
-            username: "karl".into(),
-            email: "karl.havok@hotmail.com".into(),
-        };
-        let mut table = Table::open(None).unwrap();
-        table.insert(&sample_row.validate().unwrap()).unwrap();
-        let result: Vec<_> = table
-            .select()
-            .unwrap()
-            .map(|row| InputRow::from(&row))
-            .collect();
-        assert_eq!(result, vec![sample_row]);
+        let pager = Pager::open(None).unwrap();
+        let mut table = Table::create(&pager, "users", schema(true)).unwrap();
+        table.insert(&pager, sample_row(1)).unwrap();
+        assert_eq!(select_all(&pager, &table), vec![sample_row(1)]);
+    }
+
+    #[test]
+    fn select_in_key_order() {
+        let pager = Pager::open(None).unwrap();
+        let mut table = Table::create(&pager, "users", schema(true)).unwrap();
+        for id in (-50..50).rev() {
+            table.insert(&pager, sample_row(id)).unwrap();
+        }
+        let expected: Vec<_> = (-50..50).map(sample_row).collect();
+        assert_eq!(select_all(&pager, &table), expected);
+    }
+
+    #[test]
+    fn select_in_insertion_order_without_rowid_column() {
+        let pager = Pager::open(None).unwrap();
+        let mut table = Table::create(&pager, "users", schema(false)).unwrap();
+        for id in (0..50).rev() {
+            table.insert(&pager, sample_row(id)).unwrap();
+        }
+        let expected: Vec<_> = (0..50).rev().map(sample_row).collect();
+        assert_eq!(select_all(&pager, &table), expected);
+    }
+
+    #[test]
+    fn missing_rowid_assigned() {
+        let pager = Pager::open(None).unwrap();
+        let mut table = Table::create(&pager, "users", schema(true)).unwrap();
+        table.insert(&pager, sample_row(7)).unwrap();
+        table
+            .insert(
+                &pager,
+                Row::new(vec![Value::Null, Value::Text("karl8".into())]),
+            )
+            .unwrap();
+        assert_eq!(
+            select_all(&pager, &table),
+            vec![sample_row(7), sample_row(8)]
+        );
     }
 }
@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// Single column value as decoded from a row
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    Boolean(bool),
+}
+
+impl Value {
+    /// Name of the value's type as used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Integer(_) => "integer",
+            Self::Real(_) => "real",
+            Self::Text(_) => "text",
+            Self::Blob(_) => "blob",
+            Self::Boolean(_) => "boolean",
+        }
+    }
+
+    /// Truth value in SQL's three-valued logic, where NULL is unknown
+    pub fn truth(&self) -> Option<bool> {
+        match self {
+            Self::Null => None,
+            Self::Boolean(b) => Some(*b),
+            Self::Integer(i) => Some(*i != 0),
+            Self::Real(r) => Some(*r != 0.0),
+            Self::Text(s) => Some(s.trim().parse::<f64>().is_ok_and(|r| r != 0.0)),
+            Self::Blob(_) => Some(false),
+        }
+    }
+
+    /// Numeric value of integers, reals and booleans, which compare and calculate as numbers
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Integer(i) => Some(*i as f64),
+            Self::Real(r) => Some(*r),
+            Self::Boolean(b) => Some(*b as i64 as f64),
+            _ => None,
+        }
+    }
+
+    /// Total order over all values: NULL sorts first, then numbers, text and finally blobs
+    pub fn compare(&self, other: &Self) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Integer(_) | Value::Real(_) | Value::Boolean(_) => 1,
+                Value::Text(_) => 2,
+                Value::Blob(_) => 3,
+            }
+        }
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Blob(a), Self::Blob(b)) => a.cmp(b),
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                _ => rank(a).cmp(&rank(b)),
+            },
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => Ok(()),
+            Self::Integer(i) => write!(f, "{}", i),
+            // always show a decimal point so reals are distinguishable from integers
+            Self::Real(r) if r.is_finite() && r.fract() == 0.0 => write!(f, "{:.1}", r),
+            Self::Real(r) => write!(f, "{}", r),
+            Self::Text(s) => write!(f, "{}", s),
+            Self::Blob(bytes) => {
+                write!(f, "x'")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "'")
+            }
+            Self::Boolean(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Value::Integer(2), Value::Real(2.5) => Ordering::Less ; "mixed numbers")]
+    #[test_case(Value::Boolean(true), Value::Integer(1) => Ordering::Equal ; "boolean as number")]
+    #[test_case(Value::Null, Value::Integer(i64::MIN) => Ordering::Less ; "null first")]
+    #[test_case(Value::Real(1e30), Value::Text("0".into()) => Ordering::Less ; "numbers before text")]
+    #[test_case(Value::Text("b".into()), Value::Text("a".into()) => Ordering::Greater ; "text")]
+    #[test_case(Value::Blob(vec![0]), Value::Text("z".into()) => Ordering::Greater ; "blobs last")]
+    fn compare(a: Value, b: Value) -> Ordering {
+        a.compare(&b)
+    }
+}
@@ -0,0 +1,367 @@
+use crate::sql::pager::{Page, PAGE_SIZE};
+use crate::sql::Result;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"flakewal";
+// log header layout
+const HEADER_SIZE: usize = 16;
+const OFFSET_PAGE_SIZE: usize = 8;
+const OFFSET_SALT: usize = 12;
+// frame header layout, followed by the page itself
+const FRAME_HEADER_SIZE: usize = 16;
+const OFFSET_PAGE: usize = 0;
+/// Number of pages in the database after the transaction, for a commit frame, or else 0
+const OFFSET_COMMIT: usize = 4;
+const OFFSET_FRAME_SALT: usize = 8;
+const OFFSET_CHECKSUM: usize = 12;
+const FRAME_SIZE: usize = FRAME_HEADER_SIZE + PAGE_SIZE;
+
+/// Write-ahead log kept in a `-wal` file next to a database file.
+///
+/// Modified pages are appended to the log as frames rather than written over the database file.
+/// Once synced, a frame marked as a commit makes every frame before it durable. Frames after the
+/// last commit, or torn by a crash, are ignored when the log is read back, so a transaction is
+/// either recovered whole or not at all. Checkpoints copy committed pages into the database file
+/// and empty the log.
+pub struct Wal {
+    path: PathBuf,
+    /// Opened by the first write, so that reading a database never creates a log
+    file: Option<File>,
+    /// Stamped on every frame so that frames left over from before the log was emptied are not
+    /// mistaken for current ones
+    salt: u32,
+    /// Offset of the latest frame of each page, including frames not committed yet
+    frames: HashMap<usize, u64>,
+    /// Offset of the latest frame of each page as of the last commit
+    committed: HashMap<usize, u64>,
+    /// End of the last frame written, or 0 if the log has no header yet
+    len: u64,
+    committed_len: u64,
+    /// Whether commits wait for the log to reach the disk
+    sync: bool,
+}
+
+impl Wal {
+    /// Open the log belonging to a database file, recovering the frames of every transaction it
+    /// holds that was committed
+    pub fn open(db_path: &Path) -> Result<Self> {
+        let mut path = OsString::from(db_path);
+        path.push("-wal");
+        let mut wal = Self {
+            path: path.into(),
+            file: None,
+            salt: 0,
+            frames: HashMap::new(),
+            committed: HashMap::new(),
+            len: 0,
+            committed_len: 0,
+            sync: true,
+        };
+        wal.refresh()?;
+        Ok(wal)
+    }
+
+    /// Choose whether commits wait for the log to reach the disk
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Read the log again to pick up transactions committed by other connections, returning
+    /// whether there were any or the log was emptied. The file is reopened, since another
+    /// connection may have deleted it in the meantime.
+    pub fn refresh(&mut self) -> Result<bool> {
+        debug_assert!(!self.has_uncommitted(), "refreshing a log mid-transaction");
+        let before = (self.salt, self.committed_len);
+        self.file = None;
+        self.frames.clear();
+        self.committed.clear();
+        self.len = 0;
+        self.committed_len = 0;
+        match OpenOptions::new().read(true).write(true).open(&self.path) {
+            Ok(file) => {
+                self.file = Some(file);
+                self.recover()?;
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        Ok((self.salt, self.committed_len) != before)
+    }
+
+    /// Index the frames of every committed transaction. Whatever follows the last commit may be
+    /// another connection's transaction in progress, so it is left for the next writer to replace.
+    fn recover(&mut self) -> Result<()> {
+        let file = self.file.as_mut().unwrap();
+        let mut header = [0; HEADER_SIZE];
+        if !read_at(file, 0, &mut header)?
+            || &header[..MAGIC.len()] != MAGIC
+            || read_u32(&header, OFFSET_PAGE_SIZE) as usize != PAGE_SIZE
+        {
+            // a log that never got a complete header cannot hold any commits
+            return Ok(());
+        }
+        self.salt = read_u32(&header, OFFSET_SALT);
+        let mut frame = vec![0; FRAME_SIZE];
+        let mut offset = HEADER_SIZE as u64;
+        while read_at(file, offset, &mut frame)? {
+            if read_u32(&frame, OFFSET_FRAME_SALT) != self.salt
+                || read_u32(&frame, OFFSET_CHECKSUM) != frame_checksum(&frame)
+            {
+                break;
+            }
+            self.frames
+                .insert(read_u32(&frame, OFFSET_PAGE) as usize, offset);
+            offset += FRAME_SIZE as u64;
+            if read_u32(&frame, OFFSET_COMMIT) != 0 {
+                self.committed = self.frames.clone();
+                self.committed_len = offset;
+            }
+        }
+        self.frames = self.committed.clone();
+        self.len = self.committed_len;
+        Ok(())
+    }
+
+    /// Whether the log file exists
+    pub fn exists(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Number of frames in the log, whether committed or not
+    pub fn frame_count(&self) -> usize {
+        (self.len.saturating_sub(HEADER_SIZE as u64) / FRAME_SIZE as u64) as usize
+    }
+
+    /// Whether frames were appended since the last commit
+    pub fn has_uncommitted(&self) -> bool {
+        self.len > self.committed_len
+    }
+
+    /// Latest version of a page in the log, if it has one
+    pub fn read(&mut self, index: usize) -> Result<Option<Page>> {
+        match self.frames.get(&index) {
+            Some(&offset) => self.read_frame(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Version of a page as of the last commit in the log, if it has one
+    pub fn read_committed(&mut self, index: usize) -> Result<Option<Page>> {
+        match self.committed.get(&index) {
+            Some(&offset) => self.read_frame(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn read_frame(&mut self, offset: u64) -> Result<Page> {
+        let mut data = vec![0; PAGE_SIZE];
+        let file = self.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(offset + FRAME_HEADER_SIZE as u64))?;
+        file.read_exact(&mut data)?;
+        Ok(Page::from_vec(data))
+    }
+
+    /// Append a new version of a page. Passing the number of pages in the database marks the frame
+    /// as a commit, which syncs the log before returning.
+    pub fn append(&mut self, index: usize, page: &Page, commit: Option<usize>) -> Result<()> {
+        if self.len == 0 {
+            self.start()?;
+        } else if !self.has_uncommitted() {
+            // drop whatever follows the last commit so new frames are never read along with it
+            self.file.as_mut().unwrap().set_len(self.len)?;
+        }
+        let mut frame = Vec::with_capacity(FRAME_SIZE);
+        frame.extend_from_slice(&(index as u32).to_be_bytes());
+        frame.extend_from_slice(&(commit.unwrap_or(0) as u32).to_be_bytes());
+        frame.extend_from_slice(&self.salt.to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(page.as_slice());
+        let checksum = frame_checksum(&frame);
+        frame[OFFSET_CHECKSUM..FRAME_HEADER_SIZE].copy_from_slice(&checksum.to_be_bytes());
+        let file = self.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(self.len))?;
+        file.write_all(&frame)?;
+        self.frames.insert(index, self.len);
+        self.len += FRAME_SIZE as u64;
+        if commit.is_some() {
+            if self.sync {
+                file.sync_data()?;
+            }
+            self.committed = self.frames.clone();
+            self.committed_len = self.len;
+        }
+        Ok(())
+    }
+
+    /// Write the log header, creating the file if needed
+    fn start(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+            self.file = Some(file);
+        }
+        let mut header = [0; HEADER_SIZE];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[OFFSET_PAGE_SIZE..OFFSET_SALT].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        header[OFFSET_SALT..].copy_from_slice(&self.salt.to_be_bytes());
+        let file = self.file.as_mut().unwrap();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        self.len = HEADER_SIZE as u64;
+        self.committed_len = self.len;
+        Ok(())
+    }
+
+    /// Forget the frames appended since the last commit
+    pub fn rollback(&mut self) -> Result<()> {
+        if !self.has_uncommitted() {
+            return Ok(());
+        }
+        self.frames = self.committed.clone();
+        self.len = self.committed_len;
+        self.file.as_mut().unwrap().set_len(self.len)?;
+        Ok(())
+    }
+
+    /// Pages with a committed version in the log, in ascending order
+    pub fn committed_pages(&self) -> Vec<usize> {
+        let mut pages: Vec<usize> = self.committed.keys().copied().collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Empty the log once its committed pages have safely been copied into the database file
+    pub fn reset(&mut self) -> Result<()> {
+        debug_assert!(!self.has_uncommitted(), "emptying a log mid-transaction");
+        if let Some(file) = self.file.as_mut() {
+            file.set_len(0)?;
+            if self.sync {
+                file.sync_all()?;
+            }
+        }
+        self.salt = self.salt.wrapping_add(1);
+        self.frames.clear();
+        self.committed.clear();
+        self.len = 0;
+        self.committed_len = 0;
+        Ok(())
+    }
+
+    /// Delete the log file, which must have been emptied by a checkpoint first
+    pub fn remove(&mut self) -> Result<()> {
+        debug_assert!(
+            self.committed.is_empty(),
+            "removing a log with pending pages"
+        );
+        if self.file.take().is_some() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fill a buffer from a position in a file, returning false if the file ends first
+pub fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> Result<bool> {
+    file.seek(SeekFrom::Start(offset))?;
+    match file.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Checksum of a frame, skipping the checksum field itself
+fn frame_checksum(frame: &[u8]) -> u32 {
+    checksum(&[&frame[..OFFSET_CHECKSUM], &frame[FRAME_HEADER_SIZE..]])
+}
+
+/// FNV-1a hash of a sequence of byte strings, for telling whole records from torn ones
+pub fn checksum(parts: &[&[u8]]) -> u32 {
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(0x811c9dc5, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::NamedTempFile;
+
+    fn page(byte: u8) -> Page {
+        Page::from_vec(vec![byte; PAGE_SIZE])
+    }
+
+    fn first_byte(wal: &mut Wal, index: usize) -> Option<u8> {
+        wal.read(index).unwrap().map(|page| page.as_slice()[0])
+    }
+
+    #[test]
+    fn committed_frames_recovered() {
+        let file = NamedTempFile::new("committed_frames_recovered.flake").unwrap();
+        {
+            let mut wal = Wal::open(file.path()).unwrap();
+            wal.append(1, &page(1), None).unwrap();
+            wal.append(2, &page(2), Some(3)).unwrap();
+            wal.append(1, &page(3), None).unwrap();
+            assert_eq!(first_byte(&mut wal, 1), Some(3));
+        }
+        let mut wal = Wal::open(file.path()).unwrap();
+        assert_eq!(wal.frame_count(), 2);
+        assert_eq!(first_byte(&mut wal, 1), Some(1));
+        assert_eq!(first_byte(&mut wal, 2), Some(2));
+        assert_eq!(wal.committed_pages(), vec![1, 2]);
+    }
+
+    #[test]
+    fn torn_frames_ignored() {
+        let file = NamedTempFile::new("torn_frames_ignored.flake").unwrap();
+        {
+            let mut wal = Wal::open(file.path()).unwrap();
+            wal.append(1, &page(1), Some(2)).unwrap();
+            wal.append(1, &page(2), Some(2)).unwrap();
+        }
+        // corrupt the last byte of the second frame, as if the crash came mid-write
+        let path = file.path().with_file_name("torn_frames_ignored.flake-wal");
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, data).unwrap();
+        let mut wal = Wal::open(file.path()).unwrap();
+        assert_eq!(first_byte(&mut wal, 1), Some(1));
+    }
+
+    #[test]
+    fn reset_discards_frames() {
+        let file = NamedTempFile::new("reset_discards_frames.flake").unwrap();
+        let mut wal = Wal::open(file.path()).unwrap();
+        wal.append(1, &page(1), Some(2)).unwrap();
+        wal.reset().unwrap();
+        assert_eq!(first_byte(&mut wal, 1), None);
+        wal.append(2, &page(2), Some(3)).unwrap();
+        drop(wal);
+        let mut wal = Wal::open(file.path()).unwrap();
+        assert_eq!(wal.committed_pages(), vec![2]);
+        wal.reset().unwrap();
+        wal.remove().unwrap();
+        assert!(!file
+            .path()
+            .with_file_name("reset_discards_frames.flake-wal")
+            .exists());
+    }
+}
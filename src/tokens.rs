@@ -1,41 +1,189 @@
-use std::str::SplitWhitespace;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
 
-#[derive(Debug)]
-pub enum Token<'a> {
-    None,
-    Meta(&'a str),
-    Other(&'a str),
+/// Byte offsets of a token within the input line
+pub type Span = Range<usize>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Keyword {
+    All,
+    And,
+    As,
+    Asc,
+    Begin,
+    Between,
+    By,
+    Commit,
+    Create,
+    Cross,
+    Delete,
+    Desc,
+    Distinct,
+    Drop,
+    Exists,
+    False,
+    From,
+    Group,
+    Having,
+    If,
+    In,
+    Index,
+    Inner,
+    Insert,
+    Into,
+    Is,
+    Join,
+    Left,
+    Like,
+    Limit,
+    Not,
+    Null,
+    Offset,
+    On,
+    Or,
+    Order,
+    Outer,
+    Primary,
+    Rollback,
+    Select,
+    Set,
+    Table,
+    True,
+    Unique,
+    Update,
+    Vacuum,
+    Values,
+    Where,
 }
 
-impl<'a> From<&'a str> for Token<'a> {
-    fn from(s: &'a str) -> Self {
-        match s.chars().nth(0) {
-            None => Self::None,
-            Some('.') => Self::Meta(s),
-            Some(_) => Self::Other(s),
-        }
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("all", Keyword::All),
+    ("and", Keyword::And),
+    ("as", Keyword::As),
+    ("asc", Keyword::Asc),
+    ("begin", Keyword::Begin),
+    ("between", Keyword::Between),
+    ("by", Keyword::By),
+    ("commit", Keyword::Commit),
+    ("create", Keyword::Create),
+    ("cross", Keyword::Cross),
+    ("delete", Keyword::Delete),
+    ("desc", Keyword::Desc),
+    ("distinct", Keyword::Distinct),
+    ("drop", Keyword::Drop),
+    ("exists", Keyword::Exists),
+    ("false", Keyword::False),
+    ("from", Keyword::From),
+    ("group", Keyword::Group),
+    ("having", Keyword::Having),
+    ("if", Keyword::If),
+    ("in", Keyword::In),
+    ("index", Keyword::Index),
+    ("inner", Keyword::Inner),
+    ("insert", Keyword::Insert),
+    ("into", Keyword::Into),
+    ("is", Keyword::Is),
+    ("join", Keyword::Join),
+    ("left", Keyword::Left),
+    ("like", Keyword::Like),
+    ("limit", Keyword::Limit),
+    ("not", Keyword::Not),
+    ("null", Keyword::Null),
+    ("offset", Keyword::Offset),
+    ("on", Keyword::On),
+    ("or", Keyword::Or),
+    ("order", Keyword::Order),
+    ("outer", Keyword::Outer),
+    ("primary", Keyword::Primary),
+    ("rollback", Keyword::Rollback),
+    ("select", Keyword::Select),
+    ("set", Keyword::Set),
+    ("table", Keyword::Table),
+    ("true", Keyword::True),
+    ("unique", Keyword::Unique),
+    ("update", Keyword::Update),
+    ("vacuum", Keyword::Vacuum),
+    ("values", Keyword::Values),
+    ("where", Keyword::Where),
+];
+
+impl Keyword {
+    fn lookup(word: &str) -> Option<Self> {
+        KEYWORDS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(word))
+            .map(|(_, keyword)| *keyword)
     }
 }
 
-impl<'a> From<Token<'a>> for &'a str {
-    fn from(token: Token<'a>) -> Self {
-        match token {
-            Token::None => "",
-            Token::Meta(s) | Token::Other(s) => s,
-        }
+impl Display for Keyword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (name, _) = KEYWORDS.iter().find(|(_, k)| k == self).unwrap();
+        write!(f, "{}", name)
     }
 }
 
-impl<'a> From<Token<'a>> for String {
-    fn from(token: Token<'a>) -> Self {
-        let s: &'a str = token.into();
-        String::from(s)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Concat,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// Meta command such as `.exit`, only recognised at the start of the input
+    Meta(String),
+    Keyword(Keyword),
+    Identifier(String),
+    String(String),
+    Integer(i64),
+    Real(f64),
+    Blob(Vec<u8>),
+    Operator(Operator),
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    /// Input that could not be tokenized, with a description of the problem
+    Error(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub span: Span,
+    /// Source text the token was produced from
+    pub text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    pub fn is_keyword(&self, keyword: Keyword) -> bool {
+        self.kind == TokenKind::Keyword(keyword)
     }
 }
 
-#[derive(Debug)]
+impl<'a> Display for Token<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' at position {}", self.text, self.span.start)
+    }
+}
+
+/// Lexer splitting a line of input into SQL tokens, skipping whitespace and comments
+#[derive(Clone, Debug)]
 pub struct Tokens<'a> {
-    raw: SplitWhitespace<'a>,
+    source: &'a str,
+    position: usize,
     next: Option<Token<'a>>,
 }
 
@@ -43,6 +191,205 @@ impl<'a> Tokens<'a> {
     pub fn peek(&mut self) -> Option<&<Self as Iterator>::Item> {
         self.next.as_ref()
     }
+
+    /// Length of the input, used to report errors at the end of the line
+    pub fn source_len(&self) -> usize {
+        self.source.len()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn peek_char(&self, skip: usize) -> Option<char> {
+        self.rest().chars().nth(skip)
+    }
+
+    /// Skip whitespace and comments, returning an error message for an unterminated comment
+    fn skip_trivia(&mut self) -> Option<String> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if trimmed.starts_with("--") {
+                self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if let Some(comment) = trimmed.strip_prefix("/*") {
+                match comment.find("*/") {
+                    Some(end) => self.position += end + 4,
+                    None => {
+                        self.position = self.source.len();
+                        return Some("unterminated comment".into());
+                    }
+                }
+            } else {
+                return None;
+            }
+        }
+    }
+
+    fn lex(&mut self) -> Option<Token<'a>> {
+        let start = self.position;
+        let error = self.skip_trivia();
+        if let Some(message) = error {
+            return Some(self.token(start, TokenKind::Error(message)));
+        }
+        let start = self.position;
+        let c = self.peek_char(0)?;
+        let kind = match c {
+            '.' if start == 0 && self.peek_char(1).is_some_and(|c| c.is_alphabetic()) => {
+                let len = self
+                    .rest()
+                    .find(char::is_whitespace)
+                    .unwrap_or(self.rest().len());
+                self.position += len;
+                TokenKind::Meta(self.source[start..self.position].into())
+            }
+            'x' | 'X' if self.peek_char(1) == Some('\'') => {
+                self.position += 1;
+                match self.quoted('\'') {
+                    Ok(digits) => parse_hex(&digits).map_or_else(
+                        || TokenKind::Error("invalid blob literal".into()),
+                        TokenKind::Blob,
+                    ),
+                    Err(message) => TokenKind::Error(message),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let len = self
+                    .rest()
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                    .unwrap_or(self.rest().len());
+                self.position += len;
+                let word = &self.source[start..self.position];
+                Keyword::lookup(word)
+                    .map_or_else(|| TokenKind::Identifier(word.into()), TokenKind::Keyword)
+            }
+            '0'..='9' => self.number(),
+            '.' if self.peek_char(1).is_some_and(|c| c.is_ascii_digit()) => self.number(),
+            '\'' => self
+                .quoted('\'')
+                .map_or_else(TokenKind::Error, TokenKind::String),
+            '"' | '`' => self
+                .quoted(c)
+                .map_or_else(TokenKind::Error, TokenKind::Identifier),
+            _ => self.punctuation(c),
+        };
+        Some(self.token(start, kind))
+    }
+
+    fn token(&self, start: usize, kind: TokenKind) -> Token<'a> {
+        Token {
+            kind,
+            span: start..self.position,
+            text: &self.source[start..self.position],
+        }
+    }
+
+    /// Consume a quoted literal, where a doubled quote character stands for itself
+    fn quoted(&mut self, quote: char) -> Result<String, String> {
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices().skip(1).peekable();
+        while let Some((i, c)) = chars.next() {
+            if c == quote {
+                if chars.peek().map(|(_, c)| *c) == Some(quote) {
+                    chars.next();
+                } else {
+                    self.position += i + 1;
+                    return Ok(value);
+                }
+            }
+            value.push(c);
+        }
+        self.position = self.source.len();
+        Err("unterminated quoted literal".into())
+    }
+
+    fn number(&mut self) -> TokenKind {
+        let start = self.position;
+        let digits = |tokens: &mut Self| {
+            let len = tokens
+                .rest()
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(tokens.rest().len());
+            tokens.position += len;
+        };
+        digits(self);
+        let mut real = false;
+        if self.peek_char(0) == Some('.') {
+            real = true;
+            self.position += 1;
+            digits(self);
+        }
+        if matches!(self.peek_char(0), Some('e' | 'E')) {
+            let sign = usize::from(matches!(self.peek_char(1), Some('+' | '-')));
+            if self.peek_char(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                real = true;
+                self.position += 1 + sign;
+                digits(self);
+            }
+        }
+        // a number running straight into a word, as in `12abc`, is not a valid token
+        if self
+            .peek_char(0)
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        {
+            let len = self
+                .rest()
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(self.rest().len());
+            self.position += len;
+            return TokenKind::Error("unrecognized token".into());
+        }
+        let text = &self.source[start..self.position];
+        if real {
+            text.parse().map_or_else(
+                |_| TokenKind::Error("invalid number".into()),
+                TokenKind::Real,
+            )
+        } else {
+            text.parse().map_or_else(
+                |_| TokenKind::Error("integer literal out of range".into()),
+                TokenKind::Integer,
+            )
+        }
+    }
+
+    fn punctuation(&mut self, c: char) -> TokenKind {
+        let next = self.peek_char(1);
+        let (kind, len) = match (c, next) {
+            ('=', Some('=')) => (TokenKind::Operator(Operator::Equal), 2),
+            ('=', _) => (TokenKind::Operator(Operator::Equal), 1),
+            ('!', Some('=')) | ('<', Some('>')) => (TokenKind::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (TokenKind::Operator(Operator::LessEqual), 2),
+            ('<', _) => (TokenKind::Operator(Operator::Less), 1),
+            ('>', Some('=')) => (TokenKind::Operator(Operator::GreaterEqual), 2),
+            ('>', _) => (TokenKind::Operator(Operator::Greater), 1),
+            ('|', Some('|')) => (TokenKind::Operator(Operator::Concat), 2),
+            ('+', _) => (TokenKind::Operator(Operator::Plus), 1),
+            ('-', _) => (TokenKind::Operator(Operator::Minus), 1),
+            ('*', _) => (TokenKind::Operator(Operator::Star), 1),
+            ('/', _) => (TokenKind::Operator(Operator::Slash), 1),
+            ('%', _) => (TokenKind::Operator(Operator::Percent), 1),
+            ('(', _) => (TokenKind::LeftParen, 1),
+            (')', _) => (TokenKind::RightParen, 1),
+            (',', _) => (TokenKind::Comma, 1),
+            (';', _) => (TokenKind::Semicolon, 1),
+            ('.', _) => (TokenKind::Dot, 1),
+            (c, _) => (TokenKind::Error("unrecognized token".into()), c.len_utf8()),
+        };
+        self.position += len;
+        kind
+    }
+}
+
+fn parse_hex(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
 }
 
 impl<'a> Iterator for Tokens<'a> {
@@ -50,22 +397,106 @@ impl<'a> Iterator for Tokens<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let last = self.next.take();
-        self.next = self.raw.next().map(|x| x.into());
+        self.next = self.lex();
         last
     }
 }
 
-impl<'a> From<Tokens<'a>> for String {
-    fn from(tokens: Tokens) -> Self {
-        let tokens: Vec<_> = tokens.map(String::from).collect();
-        tokens.join(" ")
+impl<'a> From<&'a str> for Tokens<'a> {
+    fn from(source: &'a str) -> Self {
+        let mut tokens = Self {
+            source,
+            position: 0,
+            next: None,
+        };
+        tokens.next = tokens.lex();
+        tokens
     }
 }
 
-impl<'a> From<&'a str> for Tokens<'a> {
-    fn from(raw: &'a str) -> Self {
-        let mut raw = raw.trim().split_whitespace();
-        let next = raw.next().map(|x| x.into());
-        Self { raw, next }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn kinds(raw: &str) -> Vec<TokenKind> {
+        Tokens::from(raw).map(|token| token.kind).collect()
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("   \t " ; "whitespace")]
+    #[test_case("-- nothing to see" ; "line comment")]
+    #[test_case("/* multi\nline */" ; "block comment")]
+    fn lex_nothing(raw: &str) {
+        assert_eq!(kinds(raw), vec![]);
+    }
+
+    #[test]
+    fn lex_statement() {
+        assert_eq!(
+            kinds("SELECT * from t where id>=1.5e1 -- trailing\n;"),
+            vec![
+                TokenKind::Keyword(Keyword::Select),
+                TokenKind::Operator(Operator::Star),
+                TokenKind::Keyword(Keyword::From),
+                TokenKind::Identifier("t".into()),
+                TokenKind::Keyword(Keyword::Where),
+                TokenKind::Identifier("id".into()),
+                TokenKind::Operator(Operator::GreaterEqual),
+                TokenKind::Real(15.0),
+                TokenKind::Semicolon,
+            ]
+        );
+    }
+
+    #[test_case("'Karl Havok'" => TokenKind::String("Karl Havok".into()) ; "string")]
+    #[test_case("'it''s'" => TokenKind::String("it's".into()) ; "escaped quote")]
+    #[test_case("\"select\"" => TokenKind::Identifier("select".into()) ; "quoted identifier")]
+    #[test_case("`my col`" => TokenKind::Identifier("my col".into()) ; "backtick identifier")]
+    #[test_case("42" => TokenKind::Integer(42) ; "integer")]
+    #[test_case(".5" => TokenKind::Real(0.5) ; "leading dot real")]
+    #[test_case("X'00fF'" => TokenKind::Blob(vec![0, 255]) ; "blob")]
+    #[test_case("<>" => TokenKind::Operator(Operator::NotEqual) ; "not equal")]
+    #[test_case("||" => TokenKind::Operator(Operator::Concat) ; "concat")]
+    #[test_case(".exit" => TokenKind::Meta(".exit".into()) ; "meta")]
+    #[test_case("karl_1$" => TokenKind::Identifier("karl_1$".into()) ; "identifier")]
+    #[test_case("WhErE" => TokenKind::Keyword(Keyword::Where) ; "mixed case keyword")]
+    fn lex_single(raw: &str) -> TokenKind {
+        let mut tokens = kinds(raw);
+        assert_eq!(tokens.len(), 1);
+        tokens.remove(0)
+    }
+
+    #[test_case("'open" ; "unterminated string")]
+    #[test_case("x'abc'" ; "odd blob")]
+    #[test_case("12abc" ; "number into word")]
+    #[test_case("99999999999999999999" ; "integer overflow")]
+    #[test_case("@" ; "unknown character")]
+    #[test_case("/* open" ; "unterminated comment")]
+    fn lex_error(raw: &str) {
+        assert!(matches!(kinds(raw).as_slice(), [TokenKind::Error(_)]));
+    }
+
+    #[test]
+    fn spans() {
+        let spans: Vec<_> = Tokens::from("a  'b c',1")
+            .map(|token| (token.span, token.text))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(0..1, "a"), (3..8, "'b c'"), (8..9, ","), (9..10, "1")]
+        );
+    }
+
+    #[test]
+    fn dot_not_meta_after_first_token() {
+        assert_eq!(
+            kinds("t.col"),
+            vec![
+                TokenKind::Identifier("t".into()),
+                TokenKind::Dot,
+                TokenKind::Identifier("col".into()),
+            ]
+        );
     }
 }
@@ -2,6 +2,7 @@ use std::process::Command;
 use assert_fs::NamedTempFile;
 use rexpect::session::{self, PtySession};
 use rexpect::errors::Result;
+use rexpect::process::signal::Signal;
 use rexpect::process::wait::WaitStatus;
 use test_case::test_case;
 
@@ -50,31 +51,31 @@ fn launch_and_exit() -> Result<()> {
 }
 
 #[test]
-fn table_full() -> Result<()> {
+fn table_beyond_cache_size() -> Result<()> {
     let mut repl = Repl::spawn()?;
-    for _ in 0..1500 {
-        repl.execute("insert 1 karl karl.havok@hotmail.com")?;
+    for id in 0..1500 {
+        repl.execute(&format!("insert {} 'karl' 'karl.havok@hotmail.com'", id))?;
     }
-    repl.expect_error("table full");
+    repl.expect_no_error("table full");
     Ok(())
 }
 
 #[test]
 fn table_not_full() -> Result<()> {
     let mut repl = Repl::spawn()?;
-    for _ in 0..1000 {
-        repl.execute("insert 1 karl karl.havok@hotmail.com")?;
+    for id in 0..1000 {
+        repl.execute(&format!("insert {} 'karl' 'karl.havok@hotmail.com'", id))?;
     }
     repl.expect_no_error("table full");
     Ok(())
 }
 
-#[test_case("one" ; "non numeric ID")]
+#[test_case("'one'" ; "non numeric ID")]
 #[test_case("1.43" ; "decimal ID")]
-#[test_case("-14" ; "negative integer ID")]
+#[test_case("'12abc'" ; "trailing characters in ID")]
 fn invalid_id(id_string: &str) -> Result<()> {
     let mut repl = Repl::spawn()?;
-    repl.execute(&format!("insert {} karl karl.havok@hotmail.com", id_string))?;
+    repl.execute(&format!("insert {} 'karl' 'karl.havok@hotmail.com'", id_string))?;
     repl.expect_error("failed while parsing id");
     Ok(())
 }
@@ -83,7 +84,7 @@ fn invalid_id(id_string: &str) -> Result<()> {
 fn valid_username() -> Result<()> {
     let mut repl = Repl::spawn()?;
     let long_username: String = (0..20).map(|_| "a").collect();
-    repl.execute(&format!("insert 1 {} a@b.c", long_username))?;
+    repl.execute(&format!("insert 1 '{}' 'a@b.c'", long_username))?;
     repl.expect_no_error("username too long");
     Ok(())
 }
@@ -92,7 +93,7 @@ fn valid_username() -> Result<()> {
 fn invalid_username() -> Result<()> {
     let mut repl = Repl::spawn()?;
     let long_username: String = (0..100).map(|_| "a").collect();
-    repl.execute(&format!("insert 1 {} a@b.c", long_username))?;
+    repl.execute(&format!("insert 1 '{}' 'a@b.c'", long_username))?;
     repl.expect_error("username too long");
     Ok(())
 }
@@ -101,7 +102,7 @@ fn invalid_username() -> Result<()> {
 fn valid_email() -> Result<()> {
     let mut repl = Repl::spawn()?;
     let long_email: String = (0..100).map(|_| "a").collect();
-    repl.execute(&format!("insert 1 karl {}", long_email))?;
+    repl.execute(&format!("insert 1 'karl' '{}'", long_email))?;
     repl.expect_no_error("email too long");
     Ok(())
 }
@@ -110,7 +111,7 @@ fn valid_email() -> Result<()> {
 fn invalid_email() -> Result<()> {
     let mut repl = Repl::spawn()?;
     let long_email: String = (0..500).map(|_| "a").collect();
-    repl.execute(&format!("insert 1 karl {}", long_email))?;
+    repl.execute(&format!("insert 1 'karl' '{}'", long_email))?;
     repl.expect_error("email too long");
     Ok(())
 }
@@ -118,9 +119,9 @@ fn invalid_email() -> Result<()> {
 #[test]
 fn insert_and_select() -> Result<()> {
     let mut repl = Repl::spawn()?;
-    repl.execute("insert 1 karl karl.havok@hotmail.com")?;
-    repl.execute("insert 2 dangerous dangerous.nights@yahoo.com")?;
-    repl.execute("insert 3 fri day.nights@gmail.com")?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
     repl.execute("select")?;
     repl.session.exp_regex(r#"
 1,karl,karl.havok@hotmail\.com\r?
@@ -130,15 +131,27 @@ fn insert_and_select() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn duplicate_id() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 1 'fri' 'day.nights@gmail.com'")?;
+    repl.expect_error("constraint violation: duplicate key 1 for id in table users");
+    repl.execute("insert 2 'fri' 'day.nights@gmail.com'")?;
+    repl.execute("update users set id = 1 where id = 2")?;
+    repl.expect_error("constraint violation: duplicate key 1 for id in table users");
+    Ok(())
+}
+
 #[test]
 fn persist_single_page() -> Result<()> {
     let db_file = NamedTempFile::new("persist_single_page.flake").unwrap();
     let db_path = db_file.path().to_string_lossy().into_owned();
     {
         let mut repl = Repl::spawn_with_args(vec![&db_path])?;
-        repl.execute("insert 1 karl karl.havok@hotmail.com")?;
-        repl.execute("insert 2 dangerous dangerous.nights@yahoo.com")?;
-        repl.execute("insert 3 fri day.nights@gmail.com")?;
+        repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+        repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+        repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
         repl.execute(".exit")?;
         repl.session.process.wait()?;
     }
@@ -159,14 +172,109 @@ fn persist_single_page() -> Result<()> {
     Ok(())
 }
 
-#[test] #[ignore] // broken by logging output #1
+#[test_case("wal" ; "write-ahead log")]
+#[test_case("rollback" ; "rollback journal")]
+fn persist_after_kill(journal_mode: &str) -> Result<()> {
+    let db_file = NamedTempFile::new("persist_after_kill.flake").unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    {
+        let mut repl = Repl::spawn_with_args(vec![&db_path, "--journal-mode", journal_mode])?;
+        repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+        repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+        repl.execute("begin")?;
+        repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
+        // wait for the last statement to finish before pulling the plug
+        repl.session.exp_regex(r#"\nflakedb> "#)?;
+        repl.session.process.kill(Signal::SIGKILL)?;
+    }
+    {
+        let mut repl = Repl::spawn_with_args(vec![&db_path])?;
+        repl.execute("select")?;
+        repl.session.exp_regex(r#"
+1,karl,karl.havok@hotmail\.com\r?
+2,dangerous,dangerous\.nights@yahoo\.com\r?
+flakedb> "#).unwrap();
+    }
+    Ok(())
+}
+
+#[test_case("wal" ; "wal")]
+#[test_case("rollback" ; "rollback journal")]
+fn processes_share_database(journal_mode: &str) -> Result<()> {
+    let db_file = NamedTempFile::new("processes_share_database.flake").unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    let mut writer = Repl::spawn_with_args(vec![&db_path, "--journal-mode", journal_mode])?;
+    writer.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    writer.execute("begin")?;
+    writer.execute("insert 2 'fri' 'day.nights@gmail.com'")?;
+    writer.session.exp_regex(r#"\nflakedb> "#)?;
+    let mut other = Repl::spawn_with_args(vec![&db_path, "--journal-mode", journal_mode, "--busy-timeout", "100"])?;
+    other.execute("insert 3 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    other.expect_error("database is locked");
+    writer.session.send_line("commit")?;
+    writer.session.exp_regex(r#"\nflakedb> "#)?;
+    other.execute("select")?;
+    other.session.exp_regex(r#"
+1,karl,karl\.havok@hotmail\.com\r?
+2,fri,day\.nights@gmail\.com\r?
+flakedb> "#).unwrap();
+    Ok(())
+}
+
+#[test_case("wal", "rollback" ; "wal")]
+#[test_case("rollback", "wal" ; "rollback journal")]
+fn processes_follow_journal_mode(created: &str, other: &str) -> Result<()> {
+    let db_file = NamedTempFile::new("processes_follow_journal_mode.flake").unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    let mut first = Repl::spawn_with_args(vec![&db_path, "--journal-mode", created])?;
+    first.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    first.session.exp_regex(r#"\nflakedb> "#)?;
+    let mut second = Repl::spawn_with_args(vec![&db_path, "--journal-mode", other])?;
+    second.execute("insert 2 'fri' 'day.nights@gmail.com'")?;
+    second.session.exp_regex(r#"\nflakedb> "#)?;
+    first.session.send_line("select")?;
+    first.session.exp_regex(r#"
+1,karl,karl\.havok@hotmail\.com\r?
+2,fri,day\.nights@gmail\.com\r?
+flakedb> "#).unwrap();
+    for mut repl in [second, first] {
+        repl.session.send_line(".exit")?;
+        let status = repl.session.process.wait().unwrap();
+        assert!(matches!(status, WaitStatus::Exited(_, 0)));
+    }
+    Ok(())
+}
+
+#[test]
+fn vacuum() -> Result<()> {
+    let db_file = NamedTempFile::new("vacuum.flake").unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    let mut repl = Repl::spawn_with_args(vec![&db_path, "--journal-mode", "rollback", "--no-sync"])?;
+    repl.execute("create table scratch (id integer primary key, note text)")?;
+    for id in 0..100 {
+        repl.execute(&format!("insert {} 'karl' 'karl.havok@hotmail.com'", id))?;
+        repl.execute(&format!("insert into scratch values ({}, '{:0>500}')", id, id))?;
+    }
+    repl.execute("drop table scratch")?;
+    repl.execute("vacuum")?;
+    repl.expect_no_error("SQL error");
+    repl.execute("select * from users where id = 99")?;
+    repl.session.exp_regex(r#"99,karl,karl\.havok@hotmail\.com"#).unwrap();
+    repl.execute(".exit")?;
+    repl.session.process.wait()?;
+    let len = std::fs::metadata(db_file.path()).unwrap().len();
+    assert!(len <= 8 * 4096, "file of {} bytes was not truncated", len);
+    Ok(())
+}
+
+#[test]
 fn persist_multi_page() -> Result<()> {
     let db_file = NamedTempFile::new("persist_multi_page.flake").unwrap();
     let db_path = db_file.path().to_string_lossy().into_owned();
     let mut inserts = Vec::new();
     let mut expected_lines = Vec::new();
     for id in 0..20 {
-        inserts.push(format!("insert {} karl{} karl.havok.{}@hotmail.com", id, id, id));
+        inserts.push(format!("insert {} 'karl{}' 'karl.havok.{}@hotmail.com'", id, id, id));
         expected_lines.push(format!(r#"{},karl{},karl\.havok\.{}@hotmail\.com"#, id, id, id));
     }
     {
@@ -190,7 +298,7 @@ fn persist_multi_page() -> Result<()> {
     Ok(())
 }
 
-#[test] #[ignore] // broken by logging output #1 and partial page roundtrip issue #2
+#[test]
 fn persist_repeated() -> Result<()> {
     let db_file = NamedTempFile::new("persist_repeated.flake").unwrap();
     let db_path = db_file.path().to_string_lossy().into_owned();
@@ -207,7 +315,7 @@ fn persist_repeated() -> Result<()> {
     });
 
     for id in 0..num_inserts {
-        inserts.push(format!("insert {} karl{} karl.havok.{}@hotmail.com", id, id, id));
+        inserts.push(format!("insert {} 'karl{}' 'karl.havok.{}@hotmail.com'", id, id, id));
         expected_lines.push(format!(r#"{},karl{},karl\.havok\.{}@hotmail\.com"#, id, id, id));
     }
     for range in ranges {
@@ -229,4 +337,300 @@ fn persist_repeated() -> Result<()> {
         result.unwrap();
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn create_table_and_select() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("create table scores (name text, score real, passed boolean)")?;
+    repl.execute("insert into scores values ('karl', 9.5, true)")?;
+    repl.execute("insert into scores (passed, score, name) values (false, 3, 'dangerous')")?;
+    repl.execute("select * from scores")?;
+    repl.session.exp_regex(r#"
+karl,9\.5,true\r?
+dangerous,3\.0,false\r?
+"#).unwrap();
+    Ok(())
+}
+
+#[test]
+fn select_where() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
+    repl.execute("insert 4 null 'anonymous@yahoo.com'")?;
+    repl.execute("select * from users where id between 2 and 4 and (email like '%@YAHOO.com' or username is null)")?;
+    repl.session.exp_regex(r#"
+2,dangerous,dangerous.nights@yahoo.com\r?
+4,,anonymous@yahoo.com"#).unwrap();
+    repl.execute("select * from users where nope = 1")?;
+    repl.expect_error("no such column: nope");
+    Ok(())
+}
+
+#[test]
+fn select_projection() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("select email, id * 2 as double, upper(username) from users where id = 1")?;
+    repl.session.exp_regex(r#"
+email,double,upper\(username\)\r?
+karl.havok@hotmail.com,2,KARL"#).unwrap();
+    repl.execute("select 1 + 2, 'flake' || 'db'")?;
+    repl.session.exp_regex(r#"
+1 \+ 2,'flake' \|\| 'db'\r?
+3,flakedb"#).unwrap();
+    Ok(())
+}
+
+#[test]
+fn select_order_by() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("insert 3 null 'anonymous@yahoo.com'")?;
+    repl.execute("insert 4 'fri' 'day.nights@yahoo.com'")?;
+    repl.execute("select id, username as name from users order by name desc nulls first")?;
+    repl.session.exp_regex(r#"
+id,name\r?
+3,\r?
+1,karl\r?
+4,fri\r?
+2,dangerous"#).unwrap();
+    repl.execute("select id from users order by length(email) > 20, 1 desc")?;
+    repl.session.exp_regex(r#"
+id\r?
+4\r?
+3\r?
+2\r?
+1"#).unwrap();
+    repl.execute("select id from users order by 2")?;
+    repl.expect_error("ORDER BY term 1 out of range - should be between 1 and 1");
+    Ok(())
+}
+
+#[test]
+fn select_limit_offset() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    for id in 1..=5 {
+        repl.execute(&format!("insert {} 'user{}' 'user{}@example.com'", id, id, id))?;
+    }
+    // the scan stops before reaching the row whose division fails
+    repl.execute("select id, 10 / (3 - id) from users limit 2")?;
+    repl.session.exp_regex(r#"
+1,5\r?
+2,10"#).unwrap();
+    repl.expect_no_error("integer overflow");
+    repl.execute("select id from users order by id desc limit 2 offset 1")?;
+    repl.session.exp_regex(r#"
+id\r?
+4\r?
+3"#).unwrap();
+    repl.execute("select id from users order by username limit 3, 10")?;
+    repl.session.exp_regex(r#"
+id\r?
+4\r?
+5"#).unwrap();
+    repl.execute("select id from users limit 'two'")?;
+    repl.expect_error("LIMIT must be an integer");
+    Ok(())
+}
+
+#[test]
+fn select_group_by() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("insert 3 'fri' 'day.nights@yahoo.com'")?;
+    repl.execute("insert 4 null 'anonymous@yahoo.com'")?;
+    repl.execute("select count(*), count(username), min(id), max(username) from users")?;
+    repl.session.exp_regex(r#"
+count\(\*\),count\(username\),min\(id\),max\(username\)\r?
+4,3,1,karl"#).unwrap();
+    repl.execute("select email like '%yahoo%' as yahoo, count(*) n, avg(id), group_concat(username, ';') from users group by 1 having count(*) > 1 order by yahoo")?;
+    repl.session.exp_regex(r#"
+yahoo,n,avg\(id\),group_concat\(username, ';'\)\r?
+true,3,3\.0,dangerous;fri"#).unwrap();
+    repl.execute("select id from users where count(*) > 1")?;
+    repl.expect_error("misuse of aggregate function count\\(\\)");
+    Ok(())
+}
+
+#[test]
+fn select_join() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("create table orders (id integer primary key, user_id integer, item text)")?;
+    repl.execute("create index orders_by_user on orders (user_id)")?;
+    repl.execute("insert into orders (user_id, item) values (1, 'flakes'), (1, 'bowl'), (3, 'milk')")?;
+    repl.execute("select username, item from users u join orders o on o.user_id = u.id order by item")?;
+    repl.session.exp_regex(r#"
+username,item\r?
+karl,bowl\r?
+karl,flakes"#).unwrap();
+    repl.execute("select u.username, count(o.id) from users u left join orders o on user_id = u.id group by u.id")?;
+    repl.session.exp_regex(r#"
+username,count\(o\.id\)\r?
+karl,2\r?
+dangerous,0"#).unwrap();
+    repl.execute("select count(*) from users, orders")?;
+    repl.session.exp_regex(r#"
+count\(\*\)\r?
+6"#).unwrap();
+    repl.execute("select id from users join orders on user_id = id")?;
+    repl.expect_error("ambiguous column name: id");
+    Ok(())
+}
+
+#[test]
+fn delete_where() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
+    repl.execute("delete from users where email like '%nights%'")?;
+    repl.session.exp_regex(r#"\n2 rows deleted"#).unwrap();
+    repl.execute("select id from users")?;
+    repl.session.exp_regex(r#"
+id\r?
+1"#).unwrap();
+    repl.execute("delete from flakedb_master")?;
+    repl.expect_error("table flakedb_master may not be modified");
+    Ok(())
+}
+
+#[test]
+fn update_where() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("update users set username = upper(username), email = username || '@flakedb.org' where id > 1")?;
+    repl.session.exp_regex(r#"\n1 rows updated"#).unwrap();
+    repl.execute("select")?;
+    repl.session.exp_regex(r#"
+1,karl,karl.havok@hotmail.com\r?
+2,DANGEROUS,dangerous@flakedb.org"#).unwrap();
+    repl.execute("update users set username = 'a-name-that-goes-on-and-on-and-on-forever'")?;
+    repl.expect_error("username too long");
+    repl.execute("update users set nope = 1")?;
+    repl.expect_error("no such column: nope");
+    Ok(())
+}
+
+#[test]
+fn insert_into_missing_table() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert into nowhere values (1, 2, 3)")?;
+    repl.expect_error("no such table: nowhere");
+    Ok(())
+}
+
+#[test]
+fn persist_created_table() -> Result<()> {
+    let db_file = NamedTempFile::new("persist_created_table.flake").unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    {
+        let mut repl = Repl::spawn_with_args(vec![&db_path])?;
+        repl.execute("create table names (name text)")?;
+        repl.execute("insert into names values ('karl')")?;
+        repl.execute(".exit")?;
+        repl.session.process.wait()?;
+    }
+    {
+        let mut repl = Repl::spawn_with_args(vec![&db_path])?;
+        repl.execute("select * from flakedb_master")?;
+        repl.session.exp_regex(r#"
+table,users,users,2,create table users \(.*\),0,1\r?
+table,names,names,3,create table names \(name text\),1,2"#).unwrap();
+        repl.execute("select * from names")?;
+        repl.session.exp_regex(r#"\nkarl\r?\n"#).unwrap();
+    }
+    Ok(())
+}
+
+#[test]
+fn open_non_database_file() -> Result<()> {
+    let db_file = NamedTempFile::new("open_non_database_file.txt").unwrap();
+    std::fs::write(db_file.path(), "just some text\n".repeat(1000)).unwrap();
+    let db_path = db_file.path().to_string_lossy().into_owned();
+    let mut repl = Repl::spawn_with_args(vec![&db_path])?;
+    repl.expect_error("not a flakedb database");
+    let status = repl.session.process.wait().unwrap();
+    assert!(matches!(status, WaitStatus::Exited(_, 1)));
+    assert_eq!(std::fs::read_to_string(db_file.path()).unwrap(), "just some text\n".repeat(1000));
+    Ok(())
+}
+
+#[test]
+fn create_and_drop_index() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("create unique index users_email on users (email)")?;
+    repl.execute("insert 3 'fri' 'karl.havok@hotmail.com'")?;
+    repl.expect_error("duplicate key karl.havok@hotmail.com for email in table users");
+    repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
+    repl.execute("select id from users where email >= 'd' and email < 'e'")?;
+    repl.session.exp_regex(r#"
+id\r?
+2\r?
+3"#).unwrap();
+    repl.execute("drop index users_email")?;
+    repl.execute("insert 4 'karl' 'karl.havok@hotmail.com'")?;
+    repl.expect_no_error("constraint violation");
+    repl.execute("drop table users")?;
+    repl.execute("select")?;
+    repl.expect_error("no such table: users");
+    Ok(())
+}
+
+#[test]
+fn transactions() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert 1 'karl' 'karl.havok@hotmail.com'")?;
+    repl.execute("insert into users values (2, 'fri', 'day.nights@gmail.com'), (1, 'x', 'y')")?;
+    repl.expect_error("duplicate key 1 for id in table users");
+    repl.execute("begin transaction")?;
+    repl.execute("begin")?;
+    repl.expect_error("cannot start a transaction within a transaction");
+    repl.execute("insert 3 'fri' 'day.nights@gmail.com'")?;
+    repl.execute("commit")?;
+    repl.execute("commit")?;
+    repl.expect_error("cannot commit - no transaction is active");
+    repl.execute("begin")?;
+    repl.execute("insert 2 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("delete from users where id = 1")?;
+    repl.execute("rollback")?;
+    repl.execute("select id from users")?;
+    repl.session.exp_regex(r#"
+id\r?
+1\r?
+3\r?
+flakedb> "#).unwrap();
+    Ok(())
+}
+
+#[test]
+fn failed_statement_in_transaction() -> Result<()> {
+    let mut repl = Repl::spawn()?;
+    repl.execute("insert into users values (1, 'karl', 'k'), (2, 'fri', 'f'), (5, 'day', 'd')")?;
+    repl.execute("begin")?;
+    repl.execute("insert 3 'dangerous' 'dangerous.nights@yahoo.com'")?;
+    repl.execute("update users set id = id + 3")?;
+    repl.expect_error("duplicate key 5 for id in table users");
+    repl.execute("insert into users values (6, 'a', 'a'), (7, 'b', 'b'), (6, 'c', 'c')")?;
+    repl.expect_error("duplicate key 6 for id in table users");
+    repl.execute("commit")?;
+    repl.execute("select id from users")?;
+    repl.session.exp_regex(r#"
+id\r?
+1\r?
+2\r?
+3\r?
+5\r?
+flakedb> "#).unwrap();
+    Ok(())
+}